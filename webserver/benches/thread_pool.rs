@@ -0,0 +1,86 @@
+//! Compares the scheduling modes of the thread pool, on factorials that split into more jobs as they run
+//!
+//! A few of the factorials are much larger than the rest, so the threads that pick them up
+//! end up with most of the work unless the jobs they queue are shared out.
+//! Run with `cargo bench --bench thread_pool`
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use webserver::thread_pool::{PoolConfig, RejectionPolicy, Scheduling, Scope, ThreadPool};
+
+const NUM_THREADS: u32 = 8;
+/// Enough room for every job the factorials split into, so the threads never block on the queue
+const QUEUE_CAPACITY: usize = 1 << 16;
+const ITERATIONS: usize = 10;
+/// Factorials are calculated modulo a prime, to keep them in a u64
+const MODULUS: u64 = 1_000_000_007;
+/// Ranges longer than this are split in half, with one half queued as a new job
+const SPLIT_LENGTH: u64 = 4096;
+
+fn main() {
+    // mostly small factorials, with a large one every so often
+    let factorials: Vec<u64> = (0..64)
+        .map(|i| if i % 16 == 0 { 4_000_000 } else { 50_000 })
+        .collect();
+    let expected: Vec<u64> = factorials
+        .iter()
+        .map(|&n| (1..=n).fold(1, |product, i| product * i % MODULUS))
+        .collect();
+    for scheduling in [Scheduling::SharedQueue, Scheduling::WorkStealing] {
+        let config = PoolConfig::new(NUM_THREADS)
+            .with_queue(QUEUE_CAPACITY, RejectionPolicy::Block)
+            .with_scheduling(scheduling);
+        let pool = ThreadPool::from_config(config);
+        let mut times: Vec<Duration> = (0..ITERATIONS)
+            .map(|_| {
+                let start = Instant::now();
+                let results = factorials_on(&pool, &factorials);
+                let elapsed = start.elapsed();
+                assert_eq!(results, expected);
+                elapsed
+            })
+            .collect();
+        times.sort();
+        println!(
+            "{scheduling:?}: median {:?}, fastest {:?}, slowest {:?}",
+            times[ITERATIONS / 2],
+            times[0],
+            times[ITERATIONS - 1]
+        );
+    }
+}
+
+/// Calculate each factorial on the pool
+fn factorials_on(pool: &ThreadPool, factorials: &[u64]) -> Vec<u64> {
+    let products: Vec<AtomicU64> = factorials.iter().map(|_| AtomicU64::new(1)).collect();
+    pool.scope(|scope| {
+        for (&n, product) in factorials.iter().zip(&products) {
+            scope
+                .spawn(move || multiply_range(scope, 1, n + 1, product))
+                .unwrap();
+        }
+    });
+    products.into_iter().map(AtomicU64::into_inner).collect()
+}
+
+/// Multiply the numbers from `start` up to `end` into the product, queueing half the range if it is long
+fn multiply_range<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    start: u64,
+    end: u64,
+    product: &'scope AtomicU64,
+) {
+    if end - start > SPLIT_LENGTH {
+        let middle = start + (end - start) / 2;
+        scope
+            .spawn(move || multiply_range(scope, middle, end, product))
+            .unwrap();
+        multiply_range(scope, start, middle, product);
+        return;
+    }
+    let partial = (start..end).fold(1, |partial, n| partial * n % MODULUS);
+    product
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+            Some(current * partial % MODULUS)
+        })
+        .unwrap();
+}
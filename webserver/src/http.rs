@@ -1,13 +1,56 @@
+mod access_log;
+mod base64;
+mod body;
+mod compression;
+mod conditional;
+mod connection;
+mod cookie;
+mod date;
+mod deflate;
+mod form;
+mod handler;
+mod headers;
+mod http2;
+mod log_sink;
 mod method;
+mod middleware;
+mod mime;
 mod request;
 mod response;
+mod router;
 mod server;
+mod session;
+mod sse;
+mod static_files;
 mod status;
+#[cfg(feature = "tls")]
+mod tls;
+mod url;
 mod version;
+mod websocket;
 
+pub use access_log::{AccessLog, AccessLogEntry, LogFormat};
+pub use body::BodyParseError;
+pub use compression::Compression;
+pub use conditional::conditional_response;
+pub use connection::{Acceptor, Connection, TcpAcceptor};
+pub use cookie::{Cookie, SameSite};
+pub use form::{Form, FormConfig, FormError, UploadedFile};
+pub use handler::Handler;
+pub use headers::{HeaderParseError, HttpHeaders};
+pub use log_sink::{LogSink, RotatingFileSink, StdoutSink};
 pub use method::HttpMethod;
-pub use request::HttpRequest;
+pub use middleware::{Middleware, MiddlewareStack, Next};
+pub use request::{HttpRequest, RequestParseError, StartLineParseError};
 pub use response::HttpResponse;
-pub use server::Server;
+pub use router::Router;
+pub use server::{Server, ServerSummary, ShutdownHandle};
+pub use session::{Session, Sessions};
+pub use sse::{Event, EventStream};
+pub use static_files::StaticFiles;
 pub use status::HttpStatus;
+#[cfg(feature = "tls")]
+pub use tls::{TlsAcceptor, TlsConnection, TlsError};
+pub use url::{Query, RequestTarget};
 pub use version::HttpVersion;
+pub use websocket::{CloseFrame, Message, WebSocket, WebSocketError};
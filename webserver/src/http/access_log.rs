@@ -0,0 +1,273 @@
+use crate::http::date::format_log_date;
+use crate::http::{HttpMethod, HttpStatus, HttpVersion, LogSink};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Records every response the server writes, in the log format written to the sink
+///
+/// Server errors are also reported to stderr, so they're seen even when the log goes elsewhere.
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Box<dyn LogSink>,
+    errors_only: bool,
+}
+
+/// How each request is written to the access log
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The Common Log Format, `host ident authuser [date] "request line" status bytes`
+    Common,
+    /// The Common Log Format, followed by the quoted Referer and User-Agent headers
+    Combined,
+    /// One JSON object per line, which is the only format that includes the latency
+    Json,
+}
+
+/// What happened to a single request
+///
+/// The method, path and version are missing when the response is to a request that couldn't be read,
+/// or to a connection turned away before any request was read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogEntry {
+    pub peer: Option<SocketAddr>,
+    /// When the request started arriving
+    pub time: SystemTime,
+    pub method: Option<HttpMethod>,
+    /// The request target as the client sent it, including any query string
+    pub path: Option<String>,
+    pub version: Option<HttpVersion>,
+    pub status: HttpStatus,
+    /// The length of the response body, not counting the head
+    pub bytes_sent: usize,
+    /// How long it took from the request starting to arrive until the response was sent
+    pub latency: Duration,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat, sink: impl LogSink) -> Self {
+        AccessLog {
+            format,
+            sink: Box::new(sink),
+            errors_only: false,
+        }
+    }
+
+    /// Only log responses with a client or server error status, by default false
+    pub fn with_errors_only(mut self, errors_only: bool) -> Self {
+        self.errors_only = errors_only;
+        self
+    }
+
+    /// Write the entry to the sink, reporting failures to stderr rather than failing the request
+    pub fn log(&self, entry: &AccessLogEntry) {
+        let status = &entry.status;
+        if status.is_server_error() {
+            eprintln!("{} failed with: {status}", request_line(entry));
+        }
+        if self.errors_only && !status.is_client_error() && !status.is_server_error() {
+            return;
+        }
+        if let Err(err) = self.sink.write_line(&self.format.format(entry)) {
+            eprintln!("Failed to write to the access log, received error: {err}");
+        }
+    }
+}
+
+impl LogFormat {
+    /// Format the entry as a single line, without a line ending
+    pub fn format(&self, entry: &AccessLogEntry) -> String {
+        match self {
+            LogFormat::Common => common_log_line(entry),
+            LogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                common_log_line(entry),
+                entry.referer.as_deref().map_or("-".into(), escape_quoted),
+                entry
+                    .user_agent
+                    .as_deref()
+                    .map_or("-".into(), escape_quoted)
+            ),
+            LogFormat::Json => json_log_line(entry),
+        }
+    }
+}
+
+fn common_log_line(entry: &AccessLogEntry) -> String {
+    let host = entry
+        .peer
+        .map_or("-".to_string(), |peer| peer.ip().to_string());
+    let bytes_sent = match entry.bytes_sent {
+        0 => "-".to_string(),
+        bytes_sent => bytes_sent.to_string(),
+    };
+    format!(
+        "{host} - - [{}] \"{}\" {} {bytes_sent}",
+        format_log_date(entry.time),
+        escape_quoted(&request_line(entry)),
+        entry.status.status_code()
+    )
+}
+
+/// The method, target and version the request started with, or `-` if it couldn't be read
+fn request_line(entry: &AccessLogEntry) -> String {
+    match (entry.method, &entry.path, entry.version) {
+        (Some(method), Some(path), Some(version)) => format!("{method} {path} {version}"),
+        _ => "-".to_string(),
+    }
+}
+
+fn json_log_line(entry: &AccessLogEntry) -> String {
+    let optional_string = |value: Option<&str>| value.map_or("null".to_string(), json_string);
+    let timestamp = entry
+        .time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    format!(
+        "{{\"time\":{timestamp:.3},\"peer\":{},\"method\":{},\"path\":{},\"version\":{},\
+         \"status\":{},\"bytes_sent\":{},\"latency_ms\":{:.3},\"referer\":{},\"user_agent\":{}}}",
+        optional_string(entry.peer.map(|peer| peer.to_string()).as_deref()),
+        optional_string(entry.method.map(|method| method.to_string()).as_deref()),
+        optional_string(entry.path.as_deref()),
+        optional_string(entry.version.map(|version| version.to_string()).as_deref()),
+        entry.status.status_code(),
+        entry.bytes_sent,
+        entry.latency.as_secs_f64() * 1000.0,
+        optional_string(entry.referer.as_deref()),
+        optional_string(entry.user_agent.as_deref())
+    )
+}
+
+/// Escape a value to go between double quotes in a log line, so it can't break the line's format
+fn escape_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        match char {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(char);
+            }
+            char if char.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", char as u32);
+            }
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// Quote and escape a value as a JSON string
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            char if char.is_control() => {
+                let _ = write!(json, "\\u{:04x}", char as u32);
+            }
+            char => json.push(char),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            peer: Some("127.0.0.1:51234".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(784111777),
+            method: Some(HttpMethod::Get),
+            path: Some("/index.html".to_string()),
+            version: Some(HttpVersion::Http1_1),
+            status: HttpStatus::Ok200,
+            bytes_sent: 2326,
+            latency: Duration::from_micros(1500),
+            referer: None,
+            user_agent: Some("curl/8.0 \"test\"".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_common_format() {
+        assert_eq!(
+            LogFormat::Common.format(&entry()),
+            "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html HTTP/1.1\" 200 2326"
+        );
+        let empty = AccessLogEntry {
+            peer: None,
+            status: HttpStatus::NotModified304,
+            bytes_sent: 0,
+            ..entry()
+        };
+        assert_eq!(
+            LogFormat::Common.format(&empty),
+            "- - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html HTTP/1.1\" 304 -"
+        );
+        let unread = AccessLogEntry {
+            method: None,
+            path: None,
+            version: None,
+            status: HttpStatus::BadRequest400,
+            ..entry()
+        };
+        assert_eq!(
+            LogFormat::Common.format(&unread),
+            "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"-\" 400 2326"
+        );
+    }
+
+    #[test]
+    fn test_combined_format() {
+        assert_eq!(
+            LogFormat::Combined.format(&entry()),
+            "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html HTTP/1.1\" 200 2326 \
+             \"-\" \"curl/8.0 \\\"test\\\"\""
+        );
+    }
+
+    #[test]
+    fn test_json_format() {
+        assert_eq!(
+            LogFormat::Json.format(&entry()),
+            "{\"time\":784111777.000,\"peer\":\"127.0.0.1:51234\",\"method\":\"GET\",\
+             \"path\":\"/index.html\",\"version\":\"HTTP/1.1\",\"status\":200,\"bytes_sent\":2326,\
+             \"latency_ms\":1.500,\"referer\":null,\"user_agent\":\"curl/8.0 \\\"test\\\"\"}"
+        );
+    }
+
+    #[test]
+    fn test_errors_only() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink_lines = Arc::clone(&lines);
+        let sink = move |line: &str| {
+            sink_lines.lock().unwrap().push(line.to_string());
+            Ok(())
+        };
+        let access_log = AccessLog::new(LogFormat::Common, sink).with_errors_only(true);
+        for status in [
+            HttpStatus::Ok200,
+            HttpStatus::NotModified304,
+            HttpStatus::NotFound404,
+            HttpStatus::ServiceUnavailable503,
+        ] {
+            access_log.log(&AccessLogEntry { status, ..entry() });
+        }
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" 404 2326"));
+        assert!(lines[1].ends_with(" 503 2326"));
+    }
+}
@@ -0,0 +1,123 @@
+/// The standard alphabet (RFC 4648 section 4), where the URL safe one uses `-` and `_` for the last two
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode with the standard alphabet and padding
+pub(crate) fn encode(input: &[u8]) -> String {
+    encode_with(input, ALPHABET, true)
+}
+
+/// Encode with the URL and filename safe alphabet, without padding, for use in cookies and URLs
+pub(crate) fn encode_url(input: &[u8]) -> String {
+    encode_with(
+        input,
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_",
+        false,
+    )
+}
+
+fn encode_with(input: &[u8], alphabet: &[u8; 64], padded: bool) -> String {
+    let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &byte)| bits | u32::from(byte) << (16 - 8 * i));
+        // n bytes make up n + 1 characters, with the rest padding
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(alphabet[(bits >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else if padded {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decode with the standard alphabet, which must be padded
+pub(crate) fn decode(encoded: &str) -> Option<Vec<u8>> {
+    if !encoded.len().is_multiple_of(4) || encoded.ends_with("===") {
+        return None;
+    }
+    decode_with(encoded.trim_end_matches('='), |byte| match byte {
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    })
+}
+
+/// Decode with the URL and filename safe alphabet, with or without padding, as `HTTP2-Settings` uses
+pub(crate) fn decode_url(encoded: &str) -> Option<Vec<u8>> {
+    decode_with(encoded.trim_end_matches('='), |byte| match byte {
+        b'-' => Some(62),
+        b'_' => Some(63),
+        _ => None,
+    })
+}
+
+/// Decode unpadded input, with `last_two` giving the values of the characters that differ between alphabets
+fn decode_with(encoded: &str, last_two: fn(u8) -> Option<u8>) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits: u32 = 0;
+    let mut pending = 0;
+    for byte in encoded.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            _ => last_two(byte)?,
+        };
+        bits = (bits << 6) | u32::from(value);
+        pending += 6;
+        if pending >= 8 {
+            pending -= 8;
+            decoded.push((bits >> pending) as u8);
+        }
+    }
+    // a single character left over can't make up a byte
+    (pending < 6).then_some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // examples from RFC 4648 section 10
+        for (input, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode(input.as_bytes()), encoded);
+            assert_eq!(decode(encoded), Some(input.as_bytes().to_vec()));
+        }
+        assert_eq!(encode(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("+/8="), Some(vec![0xfb, 0xff]));
+        // padding is required, and the URL safe alphabet isn't allowed
+        assert_eq!(decode("Zm8"), None);
+        assert_eq!(decode("Z==="), None);
+        assert_eq!(decode("-_8="), None);
+    }
+
+    #[test]
+    fn test_decode_url() {
+        assert_eq!(decode_url("AAMAAABk"), Some(vec![0, 3, 0, 0, 0, 100]));
+        assert_eq!(decode_url("_-8"), Some(vec![0xff, 0xef]));
+        assert_eq!(decode_url(""), Some(Vec::new()));
+        assert_eq!(decode_url("AAMAAA=="), Some(vec![0, 3, 0, 0]));
+        assert_eq!(decode_url("A+=="), None);
+        assert_eq!(decode_url("A"), None);
+        assert_eq!(encode_url(&[0xff, 0xef]), "_-8");
+        assert_eq!(encode_url(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,485 @@
+use crate::http::body::BodyParseError::*;
+use crate::http::HttpHeaders;
+use std::fmt;
+use std::io::{self, BufRead, ErrorKind, Read};
+
+/// The longest chunk size or trailer line accepted in a chunked body, not counting the line ending
+const MAX_CHUNK_LINE_LENGTH: usize = 4096;
+
+/// How much of the body is read at a time
+const READ_SIZE: usize = 8192;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BodyParseError {
+    InvalidContentLength(String),
+    UnsupportedTransferEncoding(String),
+    InvalidChunk(String),
+    TooLarge { limit: usize },
+    ConnectionError(ErrorKind),
+}
+
+/// A body read from the connection as it's used, rather than held in memory
+pub(crate) struct BodyReader(Box<dyn Read + Send>);
+
+/// Why a line couldn't be read
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LineError {
+    TooLong { limit: usize },
+    ConnectionError(ErrorKind),
+}
+
+impl From<std::io::Error> for BodyParseError {
+    fn from(err: std::io::Error) -> Self {
+        ConnectionError(err.kind())
+    }
+}
+
+/// How a body that failed while it was being used is reported to its reader
+impl From<BodyParseError> for io::Error {
+    fn from(err: BodyParseError) -> Self {
+        match err {
+            TooLarge { limit } => io::Error::new(
+                ErrorKind::FileTooLarge,
+                format!("Body larger than {limit} bytes"),
+            ),
+            ConnectionError(kind) => io::Error::from(kind),
+            err => io::Error::new(ErrorKind::InvalidData, format!("{err:?}")),
+        }
+    }
+}
+
+impl From<LineError> for BodyParseError {
+    fn from(err: LineError) -> Self {
+        match err {
+            LineError::TooLong { limit } => {
+                InvalidChunk(format!("Chunk line longer than {limit} bytes"))
+            }
+            LineError::ConnectionError(kind) => ConnectionError(kind),
+        }
+    }
+}
+
+impl BodyReader {
+    pub(crate) fn new(reader: impl Read + Send + 'static) -> Self {
+        BodyReader(Box::new(reader))
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyReader")
+    }
+}
+
+/// Readers are only equal to themselves, since their content can't be compared without reading it
+impl PartialEq for BodyReader {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self.0.as_ref(), other.0.as_ref())
+    }
+}
+
+/// Read a request body from the reader, using the framing described by the headers (RFC 9112 section 6.3)
+///
+/// Trailer fields of a chunked body are appended to the headers.
+pub(crate) fn read_body(
+    reader: &mut impl BufRead,
+    headers: &mut HttpHeaders,
+    max_size: usize,
+) -> Result<Vec<u8>, BodyParseError> {
+    let mut decoder = BodyDecoder::new(headers, max_size)?;
+    let mut body = Vec::new();
+    loop {
+        let start = body.len();
+        body.resize(start + READ_SIZE, 0);
+        let read = decoder.read(reader, &mut body[start..])?;
+        body.truncate(start + read);
+        if read == 0 {
+            break;
+        }
+    }
+    for (name, value) in decoder.trailers {
+        headers.append(&name, &value);
+    }
+    Ok(body)
+}
+
+/// The Content-Length of the message, which must be the same for every value given
+fn content_length(headers: &HttpHeaders) -> Result<Option<usize>, BodyParseError> {
+    // parsing alone would also accept a sign
+    let mut lengths = headers.get_list("Content-Length").map(|value| {
+        Some(value)
+            .filter(|value| value.bytes().all(|byte| byte.is_ascii_digit()))
+            .and_then(|value| value.parse::<usize>().ok())
+            .ok_or_else(|| InvalidContentLength(value.to_string()))
+    });
+    let Some(first) = lengths.next().transpose()? else {
+        return Ok(None);
+    };
+    for length in lengths {
+        if length? != first {
+            return Err(InvalidContentLength(
+                "Conflicting Content-Length values".to_string(),
+            ));
+        }
+    }
+    Ok(Some(first))
+}
+
+/// Decodes a request body as it is read from the connection, so it doesn't have to be held in memory
+#[derive(Debug)]
+pub(crate) struct BodyDecoder {
+    framing: Framing,
+    max_size: usize,
+    /// The length of the body and trailers so far, or the whole Content-Length
+    size: usize,
+    trailers: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Framing {
+    Length {
+        remaining: usize,
+    },
+    /// A chunked body, with `remaining` bytes left of the current chunk, or 0 before a chunk size line
+    Chunked {
+        remaining: usize,
+    },
+    Done,
+}
+
+impl BodyDecoder {
+    /// Prepare to read the body described by the headers
+    ///
+    /// A message with both Transfer-Encoding and Content-Length may be trying to smuggle a request past a proxy
+    /// that frames it the other way, so the Content-Length is removed and `Connection: close` set,
+    /// to close the connection after the response (RFC 9112 section 6.1)
+    pub(crate) fn new(headers: &mut HttpHeaders, max_size: usize) -> Result<Self, BodyParseError> {
+        // Transfer-Encoding overrides Content-Length
+        let encodings: Vec<&str> = headers.get_list("Transfer-Encoding").collect();
+        let framing = if !encodings.is_empty() {
+            // chunked is the only coding supported, and applying it more than once is meaningless
+            if !matches!(encodings.as_slice(), [encoding] if encoding.eq_ignore_ascii_case("chunked"))
+            {
+                return Err(UnsupportedTransferEncoding(encodings.join(", ")));
+            }
+            if headers.contains("Content-Length") {
+                headers.remove("Content-Length");
+                headers.insert("Connection", "close");
+            }
+            Framing::Chunked { remaining: 0 }
+        } else {
+            match content_length(headers)? {
+                Some(length) if length > max_size => return Err(TooLarge { limit: max_size }),
+                Some(length) => Framing::Length { remaining: length },
+                None => Framing::Done,
+            }
+        };
+        let size = match framing {
+            Framing::Length { remaining } => remaining,
+            _ => 0,
+        };
+        Ok(BodyDecoder {
+            framing,
+            max_size,
+            size,
+            trailers: Vec::new(),
+        })
+    }
+
+    /// Read some of the body into the buffer, returning 0 once it has all been read
+    pub(crate) fn read(
+        &mut self,
+        reader: &mut impl BufRead,
+        buf: &mut [u8],
+    ) -> Result<usize, BodyParseError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.framing {
+                Framing::Done | Framing::Length { remaining: 0 } => return Ok(0),
+                Framing::Length { remaining } => {
+                    let read = read_some(reader, buf, remaining)?;
+                    self.framing = Framing::Length {
+                        remaining: remaining - read,
+                    };
+                    return Ok(read);
+                }
+                Framing::Chunked { remaining: 0 } => self.read_chunk_size(reader)?,
+                Framing::Chunked { remaining } => {
+                    let read = read_some(reader, buf, remaining)?;
+                    self.framing = Framing::Chunked {
+                        remaining: remaining - read,
+                    };
+                    if read == remaining {
+                        match read_line(reader, MAX_CHUNK_LINE_LENGTH)? {
+                            Some(line) if line.is_empty() => {}
+                            _ => {
+                                return Err(InvalidChunk(
+                                    "Chunk data was not followed by CRLF".to_string(),
+                                ))
+                            }
+                        }
+                    }
+                    return Ok(read);
+                }
+            }
+        }
+    }
+
+    fn read_chunk_size(&mut self, reader: &mut impl BufRead) -> Result<(), BodyParseError> {
+        let size_line = read_line(reader, MAX_CHUNK_LINE_LENGTH)?
+            .ok_or(ConnectionError(ErrorKind::UnexpectedEof))?;
+        // ignore any chunk extensions after the size
+        let size_hex = size_line.split(';').next().unwrap_or_default().trim();
+        let size = Some(size_hex)
+            .filter(|size_hex| size_hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|size_hex| usize::from_str_radix(size_hex, 16).ok())
+            .ok_or_else(|| InvalidChunk(format!("Invalid chunk size: {size_line}")))?;
+        if size == 0 {
+            self.read_trailers(reader)?;
+            self.framing = Framing::Done;
+            return Ok(());
+        }
+        // compared this way round, so a huge chunk size can't overflow
+        if size > self.max_size - self.size {
+            return Err(TooLarge {
+                limit: self.max_size,
+            });
+        }
+        self.size += size;
+        self.framing = Framing::Chunked { remaining: size };
+        Ok(())
+    }
+
+    fn read_trailers(&mut self, reader: &mut impl BufRead) -> Result<(), BodyParseError> {
+        // the trailer section ends with an empty line, just like the header section
+        // trailers count towards the size limit, so a client can't send them endlessly
+        while let Some(line) =
+            read_line(reader, MAX_CHUNK_LINE_LENGTH)?.filter(|line| !line.is_empty())
+        {
+            self.size += line.len();
+            if self.size > self.max_size {
+                return Err(TooLarge {
+                    limit: self.max_size,
+                });
+            }
+            let trailer = HttpHeaders::parse_line(&line)
+                .map_err(|_| InvalidChunk(format!("Invalid trailer field: {line}")))?;
+            self.trailers.push(trailer);
+        }
+        Ok(())
+    }
+}
+
+/// Read at most `limit` bytes into the buffer, where the body promises at least that many more
+fn read_some(
+    reader: &mut impl BufRead,
+    buf: &mut [u8],
+    limit: usize,
+) -> Result<usize, BodyParseError> {
+    let length = buf.len().min(limit);
+    match reader.read(&mut buf[..length])? {
+        0 => Err(ConnectionError(ErrorKind::UnexpectedEof)),
+        read => Ok(read),
+    }
+}
+
+/// Read a line of at most `max_length` bytes, stripping the `\n` or `\r\n` line ending,
+/// returning None if the reader is exhausted
+///
+/// Reading stops as soon as the line is too long, so a client can't make the server buffer an endless line.
+pub(crate) fn read_line(
+    reader: &mut impl BufRead,
+    max_length: usize,
+) -> Result<Option<String>, LineError> {
+    let connection_error = |err: std::io::Error| LineError::ConnectionError(err.kind());
+    let mut line = Vec::new();
+    // allow for the line ending
+    let max_read = max_length as u64 + 2;
+    if reader
+        .by_ref()
+        .take(max_read)
+        .read_until(b'\n', &mut line)
+        .map_err(connection_error)?
+        == 0
+    {
+        return Ok(None);
+    }
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    } else if line.len() as u64 == max_read {
+        return Err(LineError::TooLong { limit: max_length });
+    }
+    if line.len() > max_length {
+        return Err(LineError::TooLong { limit: max_length });
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| LineError::ConnectionError(ErrorKind::InvalidData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HttpHeaders {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn call_read_body(
+        raw_body: &str,
+        headers: &mut HttpHeaders,
+        max_size: usize,
+    ) -> Result<Vec<u8>, BodyParseError> {
+        read_body(&mut raw_body.as_bytes(), headers, max_size)
+    }
+
+    #[test]
+    fn test_content_length() {
+        let mut headers = headers(&[("Content-Length", "5")]);
+        assert_eq!(
+            call_read_body("helloGET / HTTP/1.1", &mut headers, 1024),
+            Ok(b"hello".to_vec())
+        );
+
+        let mut headers = HttpHeaders::new();
+        assert_eq!(call_read_body("ignored", &mut headers, 1024), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_content_length_errors() {
+        let mut too_large = headers(&[("Content-Length", "5")]);
+        assert_eq!(
+            call_read_body("hello", &mut too_large, 4),
+            Err(TooLarge { limit: 4 })
+        );
+
+        let mut conflicting = headers(&[("Content-Length", "5"), ("Content-Length", "6")]);
+        assert!(matches!(
+            call_read_body("hello!", &mut conflicting, 1024),
+            Err(InvalidContentLength(_))
+        ));
+
+        let mut negative = headers(&[("Content-Length", "-1")]);
+        assert_eq!(
+            call_read_body("", &mut negative, 1024),
+            Err(InvalidContentLength("-1".to_string()))
+        );
+
+        let mut signed = headers(&[("Content-Length", "+5")]);
+        assert_eq!(
+            call_read_body("hello", &mut signed, 1024),
+            Err(InvalidContentLength("+5".to_string()))
+        );
+
+        let mut truncated = headers(&[("Content-Length", "10")]);
+        assert_eq!(
+            call_read_body("short", &mut truncated, 1024),
+            Err(ConnectionError(ErrorKind::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_chunked() {
+        let mut headers = headers(&[("Transfer-Encoding", "chunked")]);
+        let raw_body = "5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nChecksum: abc\r\n\r\n";
+        assert_eq!(
+            call_read_body(raw_body, &mut headers, 1024),
+            Ok(b"hello, world".to_vec())
+        );
+        assert_eq!(headers.get("checksum"), Some("abc"));
+        assert_eq!(headers.get("Connection"), None);
+    }
+
+    #[test]
+    fn test_chunked_with_content_length() {
+        let mut headers = headers(&[
+            ("Transfer-Encoding", "chunked"),
+            ("Content-Length", "100"),
+            ("Connection", "keep-alive"),
+        ]);
+        assert_eq!(
+            call_read_body("5\r\nhello\r\n0\r\n\r\n", &mut headers, 1024),
+            Ok(b"hello".to_vec())
+        );
+        // the length is dropped, and the connection closed once the request is answered
+        assert_eq!(headers.get("Content-Length"), None);
+        assert_eq!(headers.get("Connection"), Some("close"));
+    }
+
+    #[test]
+    fn test_chunked_errors() {
+        let mut chunked = headers(&[("Transfer-Encoding", "chunked")]);
+        assert_eq!(
+            call_read_body("5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n", &mut chunked, 8),
+            Err(TooLarge { limit: 8 })
+        );
+        assert_eq!(
+            call_read_body("1\r\nh\r\nffffffffffffffff\r\n", &mut chunked, 1024),
+            Err(TooLarge { limit: 1024 })
+        );
+        assert!(matches!(
+            call_read_body("z\r\nhello\r\n0\r\n\r\n", &mut chunked, 1024),
+            Err(InvalidChunk(_))
+        ));
+        assert!(matches!(
+            call_read_body("2\r\nhello\r\n0\r\n\r\n", &mut chunked, 1024),
+            Err(InvalidChunk(_))
+        ));
+        assert!(matches!(
+            call_read_body("+5\r\nhello\r\n0\r\n\r\n", &mut chunked, 1024),
+            Err(InvalidChunk(_))
+        ));
+
+        let mut gzip = headers(&[("Transfer-Encoding", "gzip")]);
+        assert_eq!(
+            call_read_body("", &mut gzip, 1024),
+            Err(UnsupportedTransferEncoding("gzip".to_string()))
+        );
+        let mut gzip_chunked = headers(&[("Transfer-Encoding", "gzip, chunked")]);
+        assert_eq!(
+            call_read_body("0\r\n\r\n", &mut gzip_chunked, 1024),
+            Err(UnsupportedTransferEncoding("gzip, chunked".to_string()))
+        );
+        let mut chunked_twice = headers(&[
+            ("Transfer-Encoding", "chunked"),
+            ("Transfer-Encoding", "chunked"),
+        ]);
+        assert_eq!(
+            call_read_body("0\r\n\r\n", &mut chunked_twice, 1024),
+            Err(UnsupportedTransferEncoding("chunked, chunked".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_read_line_limit() {
+        let mut reader = "12345\r\n123456\n1234567".as_bytes();
+        assert_eq!(read_line(&mut reader, 5), Ok(Some("12345".to_string())));
+        assert_eq!(
+            read_line(&mut reader, 5),
+            Err(LineError::TooLong { limit: 5 })
+        );
+
+        let mut reader = "1234567".as_bytes();
+        assert_eq!(
+            read_line(&mut reader, 5),
+            Err(LineError::TooLong { limit: 5 })
+        );
+        let mut reader = "12345".as_bytes();
+        assert_eq!(read_line(&mut reader, 5), Ok(Some("12345".to_string())));
+        assert_eq!(read_line(&mut reader, 5), Ok(None));
+    }
+}
@@ -0,0 +1,297 @@
+use crate::http::deflate::{gzip, zlib};
+use crate::http::{HttpRequest, HttpResponse, HttpStatus, Middleware, Next};
+
+/// Middleware compressing response bodies with gzip or deflate, when the client accepts them
+///
+/// The encoding is chosen from the request's `Accept-Encoding` header, preferring gzip when both are equally
+/// acceptable. Small bodies, streamed bodies, partial content and types that are already compressed (most images,
+/// audio, video and archives) are sent as they are.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{Compression, HttpRequest, HttpResponse, MiddlewareStack};
+/// let stack = MiddlewareStack::new(|_request: HttpRequest| HttpResponse::ok().body("Hello"))
+///     .wrap(Compression::new().with_min_size(512));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: usize,
+}
+
+/// A content coding the server can produce
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Compression {
+    /// Bodies smaller than this aren't worth the time to compress, and may even grow
+    const DEFAULT_MIN_SIZE: usize = 1024;
+
+    pub fn new() -> Self {
+        Compression {
+            min_size: Compression::DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Only compress bodies of at least this many bytes
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    fn compress(
+        &self,
+        accept_encoding: Option<Encoding>,
+        mut response: HttpResponse,
+    ) -> HttpResponse {
+        if !response.status_allows_body()
+            || response.is_streaming()
+            || response.status == HttpStatus::PartialContent206
+            || response.headers.contains("Content-Encoding")
+            || response
+                .headers
+                .get("Content-Type")
+                .is_some_and(is_compressed_type)
+        {
+            return response;
+        }
+        // the body depends on the request's Accept-Encoding, even when it isn't compressed, which caches need to know
+        if !response
+            .headers
+            .get_list("Vary")
+            .any(|vary| vary == "*" || vary.eq_ignore_ascii_case("Accept-Encoding"))
+        {
+            response.headers.append("Vary", "Accept-Encoding");
+        }
+        let Some(encoding) = accept_encoding else {
+            return response;
+        };
+        if response.body.len() < self.min_size {
+            return response;
+        }
+
+        let compressed = match encoding {
+            Encoding::Gzip => gzip(&response.body),
+            Encoding::Deflate => zlib(&response.body),
+        };
+        if compressed.len() >= response.body.len() {
+            return response;
+        }
+        response.body = compressed;
+        response
+            .headers
+            .insert("Content-Encoding", encoding.as_str());
+        if response.headers.contains("Content-Length") {
+            let length = response.body.len().to_string();
+            response.headers.insert("Content-Length", &length);
+        }
+        // the compressed body is a different sequence of bytes, so it can't share a strong validator or byte ranges
+        if let Some(etag) = response
+            .headers
+            .get("ETag")
+            .filter(|etag| !etag.starts_with("W/"))
+        {
+            let weak = format!("W/{etag}");
+            response.headers.insert("ETag", &weak);
+        }
+        response.headers.remove("Accept-Ranges");
+        response
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let encoding = negotiate(&request);
+        self.compress(encoding, next.run(request))
+    }
+}
+
+impl Encoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// The most preferred encoding the client accepts, if any (RFC 9110 section 12.5.3)
+///
+/// Without an `Accept-Encoding` header, the response is left uncompressed.
+fn negotiate(request: &HttpRequest) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+    for element in request.headers().get_list("Accept-Encoding") {
+        let mut parts = element.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default();
+        let quality = parts
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, value)| parse_quality(value.trim()));
+        // a malformed q-value makes the element meaningless, rather than acceptable
+        let Some(quality) = quality else {
+            continue;
+        };
+        if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(quality);
+        } else if coding.eq_ignore_ascii_case("deflate") {
+            deflate = Some(quality);
+        } else if coding == "*" {
+            any = Some(quality);
+        }
+    }
+    // codings not listed take the quality of `*`, and a quality of 0 means not acceptable
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Parse a q-value, which is between 0 and 1 with at most 3 decimal places
+fn parse_quality(value: &str) -> Option<f32> {
+    let (whole, decimals) = value.split_once('.').unwrap_or((value, ""));
+    if !matches!(whole, "0" | "1")
+        || decimals.len() > 3
+        || !decimals.bytes().all(|byte| byte.is_ascii_digit())
+    {
+        return None;
+    }
+    let quality: f32 = value.trim_end_matches('.').parse().ok()?;
+    (quality <= 1.0).then_some(quality)
+}
+
+/// Whether the type is already compressed, so compressing it again would waste time for little gain
+fn is_compressed_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mime.split_once('/') {
+        Some(("image", subtype)) => !matches!(subtype, "svg+xml" | "x-icon" | "bmp"),
+        Some(("audio" | "video", _)) => true,
+        Some(("font", subtype)) => matches!(subtype, "woff" | "woff2"),
+        Some(("application", subtype)) => matches!(
+            subtype,
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip2"
+                | "x-7z-compressed"
+                | "x-xz"
+                | "zstd"
+                | "vnd.rar"
+                | "x-rar-compressed"
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Handler, MiddlewareStack};
+
+    fn request(accept_encoding: Option<&str>) -> HttpRequest {
+        let mut lines = vec!["GET / HTTP/1.1".to_string()];
+        if let Some(accept_encoding) = accept_encoding {
+            lines.push(format!("Accept-Encoding: {accept_encoding}"));
+        }
+        HttpRequest::from_lines(lines.into_iter()).unwrap()
+    }
+
+    fn negotiated(accept_encoding: &str) -> Option<Encoding> {
+        negotiate(&request(Some(accept_encoding)))
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(&request(None)), None);
+        assert_eq!(negotiated("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiated("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiated("gzip;q=0.5, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiated("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiated("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiated("*;q=0.2, gzip;q=0"), Some(Encoding::Deflate));
+        assert_eq!(negotiated("gzip;q=0, deflate;q=0.000"), None);
+        assert_eq!(negotiated("identity, br"), None);
+        assert_eq!(
+            negotiated("gzip;q=2, deflate;q=0.5"),
+            Some(Encoding::Deflate)
+        );
+    }
+
+    fn handler(_request: HttpRequest) -> HttpResponse {
+        HttpResponse::ok()
+            .header("Content-Type", "text/html")
+            .header("ETag", "\"v1\"")
+            .header("Accept-Ranges", "bytes")
+            .body("<p>Hello, world!</p>\n".repeat(100))
+    }
+
+    #[test]
+    fn test_compression() {
+        let stack = MiddlewareStack::new(handler).wrap(Compression::new());
+        let response = stack.handle(request(Some("gzip")));
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("W/\"v1\""));
+        assert_eq!(response.headers.get("Accept-Ranges"), None);
+        assert_eq!(response.body[..2], [0x1f, 0x8b]);
+        assert!(response.body.len() < 200);
+
+        let response = stack.handle(request(Some("deflate")));
+        assert_eq!(response.headers.get("Content-Encoding"), Some("deflate"));
+        assert_eq!(response.body[0], 0x78);
+
+        let response = stack.handle(request(None));
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.body, handler(request(None)).body);
+    }
+
+    #[test]
+    fn test_compression_skipped() {
+        let compression = Compression::new();
+        let small = HttpResponse::ok().body("Hello");
+        let response = compression.compress(Some(Encoding::Gzip), small);
+        assert_eq!(response.body, b"Hello");
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+
+        for content_type in ["image/png", "video/mp4", "application/zip; charset=binary"] {
+            let response = HttpResponse::ok()
+                .header("Content-Type", content_type)
+                .body(vec![0; 4096]);
+            let response = compression.compress(Some(Encoding::Gzip), response);
+            assert_eq!(response.headers.get("Content-Encoding"), None);
+            assert_eq!(response.headers.get("Vary"), None);
+            assert_eq!(response.body.len(), 4096);
+        }
+
+        let partial = HttpResponse::new(HttpStatus::PartialContent206).body(vec![0; 4096]);
+        let response = compression.compress(Some(Encoding::Gzip), partial);
+        assert_eq!(response.body.len(), 4096);
+
+        let svg = HttpResponse::ok()
+            .header("Content-Type", "image/svg+xml")
+            .body(vec![b' '; 4096]);
+        let response = compression.compress(Some(Encoding::Gzip), svg);
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+    }
+}
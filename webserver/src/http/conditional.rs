@@ -0,0 +1,508 @@
+use crate::http::date::parse_http_date;
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpStatus};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Requests for more ranges than this are answered with the full representation, rather than building a huge multipart body
+const MAX_RANGES: usize = 16;
+
+/// Apply the request's conditional and `Range` headers to a complete `200 OK` response
+///
+/// The response's `ETag` and `Last-Modified` headers are compared against
+/// `If-Match`, `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since` (RFC 9110 section 13),
+/// giving a `304 Not Modified` or `412 Precondition Failed` response where they apply.
+/// Otherwise a `Range` request for `bytes` is answered with a `206 Partial Content` response
+/// (as `multipart/byteranges` when several ranges are requested), or `416 Range Not Satisfiable`.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{conditional_response, HttpRequest, HttpResponse, HttpStatus};
+/// let lines = ["GET / HTTP/1.1", "If-None-Match: \"v1\""].map(String::from);
+/// let request = HttpRequest::from_lines(lines.into_iter()).unwrap();
+/// let response = HttpResponse::ok().header("ETag", "\"v1\"").body("content");
+/// assert_eq!(conditional_response(&request, response).status, HttpStatus::NotModified304);
+/// ```
+pub fn conditional_response(request: &HttpRequest, mut response: HttpResponse) -> HttpResponse {
+    if response.status != HttpStatus::Ok200 {
+        return response;
+    }
+    let body = std::mem::take(&mut response.body);
+    match select(request, response, body.len()) {
+        // only the whole representation has the body, responses like a 304 have none
+        Selection::Response(mut response) => {
+            if response.status == HttpStatus::Ok200 {
+                response.body = body;
+            }
+            response
+        }
+        Selection::Partial(partial, parts) => {
+            let mut partial_body = Vec::new();
+            for part in parts {
+                match part {
+                    Part::Bytes(bytes) => partial_body.extend_from_slice(&bytes),
+                    Part::Range(range) => partial_body.extend_from_slice(&body[range]),
+                }
+            }
+            partial.body(partial_body)
+        }
+    }
+}
+
+/// [`conditional_response`] for a file of the given length, given a `200 OK` response with its headers
+///
+/// Rather than reading the whole file into memory, the body is read from the file as it's sent,
+/// seeking to each range that was requested.
+pub(crate) fn conditional_file_response(
+    request: &HttpRequest,
+    response: HttpResponse,
+    file: File,
+    length: usize,
+) -> HttpResponse {
+    let (response, parts) = match select(request, response, length) {
+        Selection::Response(response) if response.status == HttpStatus::Ok200 => {
+            (response, vec![Part::Range(0..length)])
+        }
+        Selection::Response(response) => return response,
+        Selection::Partial(partial, parts) => (partial, parts),
+    };
+    let body = PartsReader {
+        file,
+        parts: parts.into(),
+    };
+    response
+        .header("Content-Length", &body.len().to_string())
+        .body_reader(body)
+}
+
+/// What to send once the request's conditions have been checked against a representation
+enum Selection {
+    /// The response as it is, which is either the whole representation or a response with no body, like a 304
+    Response(HttpResponse),
+    /// A `206 Partial Content` response, whose body is made up of the parts
+    Partial(HttpResponse, Vec<Part>),
+}
+
+/// A part of a partial response's body, either some bytes of its own or a range of the representation
+enum Part {
+    Bytes(Vec<u8>),
+    Range(Range<usize>),
+}
+
+impl Part {
+    fn len(&self) -> usize {
+        match self {
+            Part::Bytes(bytes) => bytes.len(),
+            Part::Range(range) => range.len(),
+        }
+    }
+}
+
+/// Reads the parts of a response's body, seeking to each range of the file in turn
+struct PartsReader {
+    file: File,
+    parts: VecDeque<Part>,
+}
+
+impl PartsReader {
+    /// The length of the whole body
+    fn len(&self) -> usize {
+        self.parts.iter().map(Part::len).sum()
+    }
+}
+
+impl Read for PartsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while let Some(part) = self.parts.front_mut() {
+            let length = match part {
+                Part::Bytes(bytes) => {
+                    let length = bytes.len().min(buf.len());
+                    buf[..length].copy_from_slice(&bytes[..length]);
+                    bytes.drain(..length);
+                    length
+                }
+                Part::Range(range) => {
+                    let length = range.len().min(buf.len());
+                    self.file.seek(SeekFrom::Start(range.start as u64))?;
+                    let length = self.file.read(&mut buf[..length])?;
+                    if length == 0 && range.start < range.end {
+                        let message = "The file was cut short while it was being sent";
+                        return Err(io::Error::new(ErrorKind::UnexpectedEof, message));
+                    }
+                    range.start += length;
+                    length
+                }
+            };
+            if part.len() == 0 {
+                self.parts.pop_front();
+            }
+            if length > 0 {
+                return Ok(length);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Check the request's conditions against a `200 OK` response for a representation of the given length
+fn select(request: &HttpRequest, response: HttpResponse, length: usize) -> Selection {
+    let is_get_or_head = matches!(request.method(), HttpMethod::Get | HttpMethod::Head);
+    let etag = response.headers.get("ETag").map(str::to_string);
+    let last_modified = response
+        .headers
+        .get("Last-Modified")
+        .and_then(parse_http_date);
+
+    // If-Match and If-Unmodified-Since guard against changing a representation the client hasn't seen
+    if let Some(if_match) = request.header("If-Match") {
+        if !etag_list_matches(if_match, etag.as_deref(), true) {
+            return Selection::Response(precondition_failed());
+        }
+    } else if let Some(since) = request
+        .header("If-Unmodified-Since")
+        .and_then(parse_http_date)
+    {
+        if last_modified.is_some_and(|modified| modified > since) {
+            return Selection::Response(precondition_failed());
+        }
+    }
+
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        if etag_list_matches(if_none_match, etag.as_deref(), false) {
+            return Selection::Response(if is_get_or_head {
+                not_modified(response)
+            } else {
+                precondition_failed()
+            });
+        }
+    } else if let Some(since) = request
+        .header("If-Modified-Since")
+        .and_then(parse_http_date)
+    {
+        if is_get_or_head && last_modified.is_some_and(|modified| modified <= since) {
+            return Selection::Response(not_modified(response));
+        }
+    }
+
+    let response = response.header("Accept-Ranges", "bytes");
+    match request.header("Range") {
+        Some(range) if request.method() == HttpMethod::Get => {
+            let if_range_matches = request
+                .header("If-Range")
+                .is_none_or(|if_range| if_range_matches(if_range, etag.as_deref(), last_modified));
+            if if_range_matches {
+                range_response(range, response, length)
+            } else {
+                Selection::Response(response)
+            }
+        }
+        _ => Selection::Response(response),
+    }
+}
+
+fn precondition_failed() -> HttpResponse {
+    HttpResponse::new(HttpStatus::PreconditionFailed412)
+}
+
+/// Strip the body from the response, keeping the headers that describe the representation
+fn not_modified(mut response: HttpResponse) -> HttpResponse {
+    response.status = HttpStatus::NotModified304;
+    response.body = Vec::new();
+    response.headers.remove("Content-Type");
+    response.headers.remove("Content-Length");
+    response
+}
+
+/// Whether a list of entity tags like `"a", W/"b"` (or `*`) matches the etag
+///
+/// Strong comparison requires both tags be strong, weak comparison only compares the opaque tag
+fn etag_list_matches(list: &str, etag: Option<&str>, strong: bool) -> bool {
+    let Some(etag) = etag else {
+        return false;
+    };
+    if list.trim() == "*" {
+        return true;
+    }
+    let (etag_is_weak, etag_tag) = split_weak(etag);
+    list.split(',')
+        .map(|candidate| split_weak(candidate.trim()))
+        .any(|(candidate_is_weak, candidate_tag)| {
+            candidate_tag == etag_tag && !(strong && (candidate_is_weak || etag_is_weak))
+        })
+}
+
+fn split_weak(etag: &str) -> (bool, &str) {
+    match etag.strip_prefix("W/") {
+        Some(tag) => (true, tag),
+        None => (false, etag),
+    }
+}
+
+/// If-Range holds either an entity tag, which must strongly match, or the exact Last-Modified date
+fn if_range_matches(if_range: &str, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        etag_list_matches(if_range, etag, true)
+    } else {
+        parse_http_date(if_range).is_some_and(|date| Some(date) == last_modified)
+    }
+}
+
+fn range_response(range_header: &str, response: HttpResponse, length: usize) -> Selection {
+    let Some(ranges) = parse_ranges(range_header, length) else {
+        // an invalid or unsupported Range header is ignored
+        return Selection::Response(response);
+    };
+    if ranges.is_empty() {
+        let unsatisfiable = HttpResponse::new(HttpStatus::RangeNotSatisfiable416)
+            .header("Content-Range", &format!("bytes */{length}"));
+        return Selection::Response(unsatisfiable);
+    }
+
+    let mut partial = HttpResponse::new(HttpStatus::PartialContent206);
+    for (name, value) in response.headers.iter() {
+        if !name.eq_ignore_ascii_case("Content-Type")
+            && !name.eq_ignore_ascii_case("Content-Length")
+        {
+            partial.headers.append(name, value);
+        }
+    }
+    let content_type = response.headers.get("Content-Type");
+    if let [range] = ranges.as_slice() {
+        if let Some(content_type) = content_type {
+            partial.headers.append("Content-Type", content_type);
+        }
+        partial
+            .headers
+            .append("Content-Range", &content_range(range, length));
+        return Selection::Partial(partial, vec![Part::Range(range.clone())]);
+    }
+
+    let boundary = multipart_boundary(length);
+    let mut parts = Vec::new();
+    for range in &ranges {
+        let mut part_head = format!("--{boundary}\r\n");
+        if let Some(content_type) = content_type {
+            part_head.push_str(&format!("Content-Type: {content_type}\r\n"));
+        }
+        part_head.push_str(&format!(
+            "Content-Range: {}\r\n\r\n",
+            content_range(range, length)
+        ));
+        parts.push(Part::Bytes(part_head.into_bytes()));
+        parts.push(Part::Range(range.clone()));
+        parts.push(Part::Bytes(b"\r\n".to_vec()));
+    }
+    parts.push(Part::Bytes(format!("--{boundary}--\r\n").into_bytes()));
+    let partial = partial.header(
+        "Content-Type",
+        &format!("multipart/byteranges; boundary={boundary}"),
+    );
+    Selection::Partial(partial, parts)
+}
+
+fn content_range(range: &Range<usize>, length: usize) -> String {
+    format!("bytes {}-{}/{length}", range.start, range.end - 1)
+}
+
+/// A boundary that is vanishingly unlikely to appear in the body
+fn multipart_boundary(length: usize) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("webserver-byteranges-{:x}", nanos ^ length as u128)
+}
+
+/// Parse a `bytes=` Range header into the satisfiable ranges of a representation of the given length
+///
+/// Returns None if the header is invalid or requests too many ranges, in which case it should be ignored
+fn parse_ranges(header: &str, length: usize) -> Option<Vec<Range<usize>>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (start, end) = spec.split_once('-')?;
+        let range = match (start, end) {
+            // a suffix range, the last `end` bytes
+            ("", suffix) => {
+                let suffix: usize = suffix.parse().ok()?;
+                (suffix > 0).then(|| length.saturating_sub(suffix)..length)
+            }
+            (start, "") => {
+                let start: usize = start.parse().ok()?;
+                (start < length).then_some(start..length)
+            }
+            (start, end) => {
+                let start: usize = start.parse().ok()?;
+                let end: usize = end.parse().ok()?;
+                if end < start {
+                    return None;
+                }
+                (start < length).then(|| start..end.saturating_add(1).min(length))
+            }
+        };
+        // unsatisfiable ranges are skipped, a 416 is only sent if none can be satisfied
+        ranges.extend(range.filter(|range| !range.is_empty()));
+    }
+    Some(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, headers: &[&str]) -> HttpRequest {
+        let lines = std::iter::once(format!("{method} /file HTTP/1.1"))
+            .chain(headers.iter().map(|header| header.to_string()));
+        HttpRequest::from_lines(lines).unwrap()
+    }
+
+    fn file_response() -> HttpResponse {
+        HttpResponse::ok()
+            .header("Content-Type", "text/plain")
+            .header("ETag", "\"abc\"")
+            .header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .body("0123456789")
+    }
+
+    fn respond(method: &str, headers: &[&str]) -> HttpResponse {
+        conditional_response(&request(method, headers), file_response())
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let response = respond("GET", &["If-None-Match: \"xyz\", W/\"abc\""]);
+        assert_eq!(response.status, HttpStatus::NotModified304);
+        assert_eq!(response.body, b"");
+        assert_eq!(response.headers.get("ETag"), Some("\"abc\""));
+        assert_eq!(response.headers.get("Content-Type"), None);
+
+        let response = respond(
+            "HEAD",
+            &["If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT"],
+        );
+        assert_eq!(response.status, HttpStatus::NotModified304);
+
+        let response = respond("GET", &["If-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT"]);
+        assert_eq!(response.status, HttpStatus::Ok200);
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let response = respond(
+            "GET",
+            &[
+                "If-None-Match: \"xyz\"",
+                "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT",
+            ],
+        );
+        assert_eq!(response.status, HttpStatus::Ok200);
+    }
+
+    #[test]
+    fn test_precondition_failed() {
+        assert_eq!(
+            respond("PUT", &["If-Match: \"xyz\""]).status,
+            HttpStatus::PreconditionFailed412
+        );
+        assert_eq!(
+            respond("PUT", &["If-Match: W/\"abc\""]).status,
+            HttpStatus::PreconditionFailed412
+        );
+        assert_eq!(respond("PUT", &["If-Match: *"]).status, HttpStatus::Ok200);
+        assert_eq!(
+            respond(
+                "PUT",
+                &["If-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT"]
+            )
+            .status,
+            HttpStatus::PreconditionFailed412
+        );
+        assert_eq!(
+            respond("DELETE", &["If-None-Match: *"]).status,
+            HttpStatus::PreconditionFailed412
+        );
+    }
+
+    #[test]
+    fn test_single_range() {
+        let response = respond("GET", &["Range: bytes=2-4"]);
+        assert_eq!(response.status, HttpStatus::PartialContent206);
+        assert_eq!(response.body, b"234");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(response.headers.get("Content-Type"), Some("text/plain"));
+
+        assert_eq!(respond("GET", &["Range: bytes=-3"]).body, b"789");
+        assert_eq!(respond("GET", &["Range: bytes=7-100"]).body, b"789");
+        let response = respond("GET", &["Range: bytes=0-18446744073709551615"]);
+        assert_eq!(response.status, HttpStatus::PartialContent206);
+        assert_eq!(response.body, b"0123456789");
+        // ranges are only applied to GET requests
+        assert_eq!(
+            respond("HEAD", &["Range: bytes=2-4"]).status,
+            HttpStatus::Ok200
+        );
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        let response = respond("GET", &["Range: bytes=0-1, 20-30, 8-"]);
+        assert_eq!(response.status, HttpStatus::PartialContent206);
+        let content_type = response.headers.get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let expected = format!(
+            "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+             --{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+             --{boundary}--\r\n"
+        );
+        assert_eq!(String::from_utf8(response.body).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_unsatisfiable_and_invalid_ranges() {
+        let response = respond("GET", &["Range: bytes=10-20"]);
+        assert_eq!(response.status, HttpStatus::RangeNotSatisfiable416);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */10"));
+        assert_eq!(
+            respond("GET", &["Range: bytes=-0"]).status,
+            HttpStatus::RangeNotSatisfiable416
+        );
+
+        for invalid in ["Range: bytes=5-2", "Range: items=0-1", "Range: bytes=a-b"] {
+            assert_eq!(respond("GET", &[invalid]).status, HttpStatus::Ok200);
+        }
+    }
+
+    #[test]
+    fn test_if_range() {
+        let response = respond("GET", &["Range: bytes=0-1", "If-Range: \"abc\""]);
+        assert_eq!(response.status, HttpStatus::PartialContent206);
+        let response = respond(
+            "GET",
+            &[
+                "Range: bytes=0-1",
+                "If-Range: Sun, 06 Nov 1994 08:49:37 GMT",
+            ],
+        );
+        assert_eq!(response.status, HttpStatus::PartialContent206);
+
+        let response = respond("GET", &["Range: bytes=0-1", "If-Range: \"old\""]);
+        assert_eq!(response.status, HttpStatus::Ok200);
+        assert_eq!(response.body, b"0123456789");
+    }
+}
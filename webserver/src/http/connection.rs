@@ -0,0 +1,174 @@
+use crate::http::server::reject_overloaded;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+/// A connection to a client, which requests are read from and responses are written to
+pub trait Connection: Read + Write + Send + 'static {
+    /// Limit how long a read waits for data, with `None` waiting forever
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Limit how long a write waits for the client to make room for more data, with `None` waiting forever
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// The address of the client
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// The socket under the connection, if it has one that can be watched from another thread
+    ///
+    /// HTTP/2 watches the socket for the client to send something, so it's only offered on connections with one.
+    fn socket(&self) -> Option<&TcpStream> {
+        None
+    }
+
+    /// Whether the connection is encrypted, since HTTP/2 is only offered in cleartext on unencrypted connections
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+/// Turns the TCP streams a [`Server`](crate::http::Server) accepts into connections, e.g. by starting a TLS session
+pub trait Acceptor: Send + Sync + 'static {
+    type Connection: Connection;
+
+    /// Set up the connection, on the thread that will handle it
+    fn accept(&self, stream: TcpStream) -> io::Result<Self::Connection>;
+
+    /// Turn away a connection when the server is too busy to handle it, without blocking new connections
+    fn reject(&self, stream: TcpStream);
+}
+
+/// Serves plain HTTP straight over TCP
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TcpAcceptor;
+
+/// A connection whose reads fail with [`ErrorKind::TimedOut`] once a deadline passes
+///
+/// Socket timeouts only limit each read, so a client sending a byte at a time could otherwise keep a
+/// request arriving forever. Each read waits at most until the deadline, and at most the read timeout.
+pub(crate) struct TimedConnection<C: Connection> {
+    connection: C,
+    deadline: Option<Instant>,
+    read_timeout: Option<Duration>,
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn socket(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+impl Acceptor for TcpAcceptor {
+    type Connection = TcpStream;
+
+    fn accept(&self, stream: TcpStream) -> io::Result<TcpStream> {
+        Ok(stream)
+    }
+
+    /// Respond with a 503, telling the client to try again later
+    fn reject(&self, mut stream: TcpStream) {
+        reject_overloaded(&mut stream);
+    }
+}
+
+impl<C: Connection> TimedConnection<C> {
+    pub fn new(connection: C) -> Self {
+        TimedConnection {
+            connection,
+            deadline: None,
+            read_timeout: None,
+        }
+    }
+
+    /// Fail reads once the deadline passes, with `None` allowing reads to continue indefinitely
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Limit how long each read waits for data, regardless of the deadline
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.read_timeout = read_timeout;
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.connection
+    }
+
+    /// The underlying connection, once it no longer needs a deadline
+    pub fn into_inner(self) -> C {
+        self.connection
+    }
+}
+
+impl<C: Connection> Read for TimedConnection<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Some(
+                    self.read_timeout
+                        .map_or(remaining, |timeout| timeout.min(remaining)),
+                )
+            }
+            None => self.read_timeout,
+        };
+        self.connection.set_read_timeout(timeout)?;
+        self.connection.read(buf)
+    }
+}
+
+impl<C: Connection> Write for TimedConnection<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.connection.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connection.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_timed_connection_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut connection = TimedConnection::new(stream);
+        connection.set_deadline(Some(Instant::now() + Duration::from_millis(200)));
+        connection.set_read_timeout(Some(Duration::from_secs(5)));
+
+        // data trickling in doesn't extend the deadline
+        let started = Instant::now();
+        let mut buf = [0; 8];
+        client.write_all(b"a").unwrap();
+        assert_eq!(connection.read(&mut buf).unwrap(), 1);
+        let err = loop {
+            match connection.read(&mut buf) {
+                Ok(_) => unreachable!("The client only sent one byte"),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
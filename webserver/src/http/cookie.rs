@@ -0,0 +1,252 @@
+use crate::http::date::format_http_date;
+use crate::http::headers::is_token_char;
+use std::fmt::{self, Display};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A cookie for the client to store, sent in a `Set-Cookie` header with [`HttpResponse::set_cookie`]
+///
+/// Without `Expires` or `Max-Age` the cookie lasts until the browser is closed.
+///
+/// [`HttpResponse::set_cookie`]: crate::http::HttpResponse::set_cookie
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use webserver::http::{Cookie, SameSite};
+/// let cookie = Cookie::new("theme", "dark")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only()
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "theme=dark; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/// Whether the cookie is sent with requests started by other sites (RFC 6265bis section 4.1.2.7)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only sent with requests from the same site
+    Strict,
+    /// Also sent when following a link from another site, but not with its forms or subresources
+    Lax,
+    /// Sent with every request, which browsers only allow for `Secure` cookies
+    None,
+}
+
+impl Cookie {
+    /// A cookie with the name and value, which aren't encoded in any way
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name isn't a token, or the value contains whitespace, `"`, `,`, `;`, `\` or control characters.
+    pub fn new(name: &str, value: &str) -> Cookie {
+        assert!(
+            !name.is_empty() && name.bytes().all(is_token_char),
+            "Invalid cookie name {name:?}"
+        );
+        assert!(
+            value.bytes().all(is_cookie_octet),
+            "Invalid cookie value {value:?}"
+        );
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            expires: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie telling the client to delete its cookie with the name
+    ///
+    /// The path and domain must match the ones the cookie was set with.
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "")
+            .expires(UNIX_EPOCH)
+            .max_age(Duration::ZERO)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Only send the cookie with requests for this path and those below it
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path contains `;` or control characters.
+    pub fn path(mut self, path: &str) -> Cookie {
+        assert!(is_attribute_value(path), "Invalid cookie path {path:?}");
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Send the cookie to the domain and its subdomains, rather than only the host that set it
+    ///
+    /// # Panics
+    ///
+    /// Panics if the domain contains `;` or control characters.
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        assert!(
+            is_attribute_value(domain),
+            "Invalid cookie domain {domain:?}"
+        );
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Delete the cookie at this time, which older clients understand while `Max-Age` takes precedence
+    pub fn expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Delete the cookie after this long, with zero deleting it at once
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn secure(mut self) -> Cookie {
+        self.secure = true;
+        self
+    }
+
+    /// Hide the cookie from scripts in the page
+    pub fn http_only(mut self) -> Cookie {
+        self.http_only = true;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl Display for Cookie {
+    /// Format the value of a `Set-Cookie` header
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// The name and value of each cookie in a `Cookie` header, e.g. `a=1; b=2`, skipping any without a `=`
+pub(crate) fn parse_cookies(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let value = value.trim();
+        // the value may be quoted, though the quotes aren't part of it
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        Some((name.trim(), value))
+    })
+}
+
+/// Whether the byte can appear in a cookie value (RFC 6265 section 4.1.1)
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e)
+}
+
+fn is_attribute_value(value: &str) -> bool {
+    !value.contains(';') && !value.contains(|c: char| c.is_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_cookie() {
+        let cookie = Cookie::new("id", "a3fWa")
+            .path("/docs")
+            .domain("example.com")
+            .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+            .secure()
+            .http_only()
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "id=a3fWa; Path=/docs; Domain=example.com; Expires=Sun, 06 Nov 1994 08:49:37 GMT; \
+             Secure; HttpOnly; SameSite=Strict"
+        );
+        assert_eq!(Cookie::new("empty", "").to_string(), "empty=");
+        assert_eq!(
+            Cookie::removal("id").path("/").to_string(),
+            "id=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_value() {
+        let _ = Cookie::new("name", "two; values");
+    }
+
+    #[test]
+    fn test_parse_cookies() {
+        assert_eq!(
+            parse_cookies("a=1; b=\"two\";c=; d==e; invalid").collect::<Vec<_>>(),
+            vec![("a", "1"), ("b", "two"), ("c", ""), ("d", "=e")]
+        );
+        assert_eq!(parse_cookies("").count(), 0);
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Format a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT` (the IMF-fixdate of RFC 9110)
+///
+/// Times before the unix epoch are formatted as the epoch
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = seconds / SECONDS_PER_DAY;
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        // the epoch was a thursday
+        DAY_NAMES[(days % 7) as usize],
+        day,
+        MONTH_NAMES[month as usize - 1],
+        year,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Format a time as an access log date, e.g. `06/Nov/1994:08:49:37 +0000` (the strftime format `%d/%b/%Y:%H:%M:%S %z`)
+pub fn format_log_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+    let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTH_NAMES[month as usize - 1],
+        year,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Parse an HTTP date in the IMF-fixdate format, returning None if it is invalid
+///
+/// The obsolete RFC 850 and asctime formats are not supported
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = date.split(' ').collect();
+    let [day_name, day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+    if !DAY_NAMES
+        .iter()
+        .any(|name| day_name.strip_suffix(',') == Some(name))
+    {
+        return None;
+    }
+    let day: u64 = parse_digits(day, 2)?;
+    let month = MONTH_NAMES.iter().position(|name| name == month)? as u64 + 1;
+    let year: u64 = parse_digits(year, 4)?;
+    let time_parts: Vec<&str> = time.split(':').collect();
+    let [hours, minutes, seconds] = time_parts.as_slice() else {
+        return None;
+    };
+    let (hours, minutes, seconds) = (
+        parse_digits(hours, 2)?,
+        parse_digits(minutes, 2)?,
+        parse_digits(seconds, 2)?,
+    );
+    if year < 1970 || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+fn parse_digits(text: &str, length: usize) -> Option<u64> {
+    if text.len() != length || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Convert days since the unix epoch to a (year, month, day) date in the proleptic Gregorian calendar
+///
+/// Uses Howard Hinnant's `civil_from_days` algorithm, which works in 400 year eras starting on the 1st of March
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The inverse of [`civil_from_days`]
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_http_date() {
+        assert_eq!(
+            format_http_date(UNIX_EPOCH + Duration::from_secs(784111777)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(
+            format_http_date(UNIX_EPOCH),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+        assert_eq!(
+            format_http_date(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn test_format_log_date() {
+        assert_eq!(
+            format_log_date(UNIX_EPOCH + Duration::from_secs(784111777)),
+            "06/Nov/1994:08:49:37 +0000"
+        );
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        for seconds in [0, 784111777, 951_782_400, 1_700_000_000, 4_102_444_799] {
+            let time = UNIX_EPOCH + Duration::from_secs(seconds);
+            assert_eq!(parse_http_date(&format_http_date(time)), Some(time));
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 6 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+    }
+}
@@ -0,0 +1,398 @@
+//! DEFLATE compression (RFC 1951), with the gzip (RFC 1952) and zlib (RFC 1950) wrappers used by HTTP
+//!
+//! Repeated strings are found with LZ77 over a 32 KiB window, then written with the fixed Huffman codes.
+//! Data that doesn't compress is written in stored blocks instead, so the output is never much larger than the input.
+
+/// The furthest back a match can refer to
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// How many earlier positions with the same hash are checked for a match, trading speed for compression
+const MAX_CHAIN: usize = 64;
+const MAX_STORED_BLOCK: usize = u16::MAX as usize;
+const END_OF_BLOCK: u16 = 256;
+/// Marks an empty slot in the hash tables
+const NO_POSITION: u32 = u32::MAX;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// Compress the data in the gzip format, for `Content-Encoding: gzip`
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // no file name or modification time, and an unknown operating system
+    let mut output = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    output.extend(deflate(data));
+    output.extend(crc32(data).to_le_bytes());
+    // the length is stored modulo 2^32
+    output.extend((data.len() as u32).to_le_bytes());
+    output
+}
+
+/// Compress the data in the zlib format, for `Content-Encoding: deflate`
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // a 32 KiB window, and a header check making the first two bytes a multiple of 31
+    let mut output = vec![0x78, 0x01];
+    output.extend(deflate(data));
+    output.extend(adler32(data).to_be_bytes());
+    output
+}
+
+/// Compress the data as raw DEFLATE blocks
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let compressed = fixed_huffman_block(data);
+    let stored_length = data.len() + data.len().div_ceil(MAX_STORED_BLOCK).max(1) * 5;
+    if compressed.len() > stored_length {
+        stored_blocks(data)
+    } else {
+        compressed
+    }
+}
+
+/// Writes bits starting from the least significant bit of each byte, as DEFLATE requires
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    length: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            length: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= u64::from(value) << self.length;
+        self.length += count;
+        while self.length >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.length -= 8;
+        }
+    }
+
+    /// Huffman codes are written starting from their most significant bit
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.length > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Finds earlier occurrences of the data at each position, using hash chains of 3 byte prefixes
+struct MatchFinder {
+    /// The most recent position with each hash
+    head: Vec<u32>,
+    /// The previous position with the same hash, for each position in the window
+    previous: Vec<u32>,
+}
+
+impl MatchFinder {
+    fn new() -> Self {
+        MatchFinder {
+            head: vec![NO_POSITION; 1 << HASH_BITS],
+            previous: vec![NO_POSITION; WINDOW_SIZE],
+        }
+    }
+
+    fn hash(data: &[u8], position: usize) -> usize {
+        let prefix =
+            u32::from_le_bytes([data[position], data[position + 1], data[position + 2], 0]);
+        (prefix.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH <= data.len() {
+            let hash = MatchFinder::hash(data, position);
+            self.previous[position % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = position as u32;
+        }
+    }
+
+    /// The (length, distance) of the longest earlier match for the data at the position, with a length of 0 if none
+    fn longest_match(&self, data: &[u8], position: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if position + MIN_MATCH > data.len() {
+            return best;
+        }
+        let max_length = MAX_MATCH.min(data.len() - position);
+        let mut candidate = self.head[MatchFinder::hash(data, position)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NO_POSITION {
+                break;
+            }
+            let start = candidate as usize;
+            // positions further back than the window may have been overwritten in the chain
+            if start >= position || position - start > WINDOW_SIZE {
+                break;
+            }
+            let length = data[start..]
+                .iter()
+                .zip(&data[position..position + max_length])
+                .take_while(|(earlier, current)| earlier == current)
+                .count();
+            if length > best.0 {
+                best = (length, position - start);
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.previous[start % WINDOW_SIZE];
+        }
+        best
+    }
+}
+
+/// Compress the data into a single block using the fixed Huffman codes
+fn fixed_huffman_block(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // the final block, compressed with the fixed codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut finder = MatchFinder::new();
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = finder.longest_match(data, position);
+        if length >= MIN_MATCH {
+            write_match(&mut writer, length, distance);
+            for inserted in position..position + length {
+                finder.insert(data, inserted);
+            }
+            position += length;
+        } else {
+            write_literal(&mut writer, u16::from(data[position]));
+            finder.insert(data, position);
+            position += 1;
+        }
+    }
+    write_literal(&mut writer, END_OF_BLOCK);
+    writer.finish()
+}
+
+/// Write a literal byte or the end of block marker, using its fixed Huffman code
+fn write_literal(writer: &mut BitWriter, symbol: u16) {
+    let symbol = u32::from(symbol);
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let length_index = LENGTH_BASES.partition_point(|&base| usize::from(base) <= length) - 1;
+    write_literal(writer, 257 + length_index as u16);
+    writer.write_bits(
+        (length - usize::from(LENGTH_BASES[length_index])) as u32,
+        LENGTH_EXTRA_BITS[length_index],
+    );
+
+    let distance_index = DISTANCE_BASES.partition_point(|&base| usize::from(base) <= distance) - 1;
+    // distance codes are all 5 bits long
+    writer.write_code(distance_index as u32, 5);
+    writer.write_bits(
+        (distance - usize::from(DISTANCE_BASES[distance_index])) as u32,
+        DISTANCE_EXTRA_BITS[distance_index],
+    );
+}
+
+/// Write the data uncompressed, in as many blocks as needed
+fn stored_blocks(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + 5);
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        // an empty final block
+        output.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        // the block type bits are 0, and the header is padded to a byte
+        output.push(u8::from(chunks.peek().is_none()));
+        let length = chunk.len() as u16;
+        output.extend(length.to_le_bytes());
+        output.extend((!length).to_le_bytes());
+        output.extend(chunk);
+    }
+    output
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 == 1 {
+                0xedb8_8320 ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[index] = value;
+        index += 1;
+    }
+    table
+}
+
+/// The CRC-32 checksum used by gzip
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// The Adler-32 checksum used by zlib
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (a, b) = data.iter().fold((1, 0), |(a, b), &byte| {
+        let a = (a + u32::from(byte)) % MODULUS;
+        (a, (b + a) % MODULUS)
+    });
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decompresses the blocks this module writes, which only use stored blocks and the fixed codes
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut reader = BitReader { data, position: 0 };
+        let mut output = Vec::new();
+        loop {
+            let is_final = reader.bits(1) == 1;
+            match reader.bits(2) {
+                0 => {
+                    reader.position = reader.position.div_ceil(8) * 8;
+                    let length = reader.bits(16) as usize;
+                    assert_eq!(reader.bits(16) as usize, !length & 0xffff);
+                    let start = reader.position / 8;
+                    output.extend(&data[start..start + length]);
+                    reader.position += length * 8;
+                }
+                1 => loop {
+                    let symbol = reader.fixed_literal();
+                    if symbol < 256 {
+                        output.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let index = symbol as usize - 257;
+                    let length = LENGTH_BASES[index] as usize
+                        + reader.bits(LENGTH_EXTRA_BITS[index]) as usize;
+                    let index = reader.code(5) as usize;
+                    let distance = DISTANCE_BASES[index] as usize
+                        + reader.bits(DISTANCE_EXTRA_BITS[index]) as usize;
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance]);
+                    }
+                },
+                block_type => panic!("Unexpected block type {block_type}"),
+            }
+            if is_final {
+                return output;
+            }
+        }
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, bit| {
+                let byte = self.data[self.position / 8];
+                let value = value | (u32::from(byte >> (self.position % 8)) & 1) << bit;
+                self.position += 1;
+                value
+            })
+        }
+
+        fn code(&mut self, length: u32) -> u32 {
+            (0..length).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        fn fixed_literal(&mut self) -> u32 {
+            let code = self.code(7);
+            if code <= 0b0010111 {
+                return code + 256;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xbf => code - 0x30,
+                0xc0..=0xc7 => code - 0xc0 + 280,
+                _ => (code << 1 | self.bits(1)) - 0x190 + 144,
+            }
+        }
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        let repetitive = "<li>An item in a list</li>\n".repeat(1000).into_bytes();
+        let bytes: Vec<u8> = (0..=255).collect();
+        // a sequence with no repeats, which is stored instead of compressed
+        let random: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for data in [&b""[..], b"a", b"aaaaaaaaaa", &bytes, &repetitive, &random] {
+            let compressed = deflate(data);
+            assert_eq!(inflate(&compressed), data);
+        }
+        assert!(deflate(&repetitive).len() < repetitive.len() / 20);
+        assert!(deflate(&random).len() <= random.len() + 10);
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_wrappers() {
+        let data = b"hello hello hello hello";
+        let gzipped = gzip(data);
+        assert_eq!(gzipped[..3], [0x1f, 0x8b, 8]);
+        assert_eq!(inflate(&gzipped[10..gzipped.len() - 8]), data);
+        assert_eq!(
+            gzipped[gzipped.len() - 4..],
+            (data.len() as u32).to_le_bytes()
+        );
+
+        let zlibbed = zlib(data);
+        assert_eq!(u16::from_be_bytes([zlibbed[0], zlibbed[1]]) % 31, 0);
+        assert_eq!(inflate(&zlibbed[2..zlibbed.len() - 4]), data);
+        assert_eq!(zlibbed[zlibbed.len() - 4..], adler32(data).to_be_bytes());
+    }
+}
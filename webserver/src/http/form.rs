@@ -0,0 +1,867 @@
+use crate::http::{HttpHeaders, HttpResponse, HttpStatus, Query};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The largest headers of a single multipart part, which only need to name it
+const MAX_PART_HEAD_SIZE: usize = 8 * 1024;
+/// The longest boundary a multipart body can use (RFC 2046 section 5.1.1)
+const MAX_BOUNDARY_LENGTH: usize = 70;
+/// How much of the body is read at a time
+const READ_SIZE: usize = 8 * 1024;
+
+/// The fields of a submitted form, from an `application/x-www-form-urlencoded` or `multipart/form-data` body
+///
+/// A multipart form is decoded as its body arrives, with each uploaded file written to a temporary file as its
+/// part is read, so large uploads aren't held in memory. The files are deleted when they're dropped,
+/// unless they're persisted first.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{HttpRequest, HttpResponse};
+/// fn handle(mut request: HttpRequest) -> HttpResponse {
+///     let form = match request.form() {
+///         Ok(form) => form,
+///         Err(err) => return err.into(),
+///     };
+///     let age: u32 = match form.parse("age") {
+///         Ok(age) => age,
+///         Err(err) => return err.into(),
+///     };
+///     let name = form.get("name").unwrap_or("stranger");
+///     HttpResponse::ok().body(format!("Hello {name}, aged {age}"))
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Query,
+    files: Vec<(String, UploadedFile)>,
+}
+
+/// A file uploaded in a multipart form, held in a temporary file that's deleted when this is dropped
+#[derive(Debug)]
+pub struct UploadedFile {
+    file_name: String,
+    content_type: Option<String>,
+    /// Empty once the file has been moved elsewhere
+    path: PathBuf,
+    size: u64,
+}
+
+/// The limits on a form, and where its files are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormConfig {
+    max_fields: usize,
+    max_field_size: usize,
+    max_files: usize,
+    max_file_size: usize,
+    temp_dir: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FormError {
+    /// The request's `Content-Type` isn't one of the form encodings, or is missing its boundary
+    UnsupportedContentType,
+    /// The body isn't encoded as its `Content-Type` says, e.g. it has invalid escapes, UTF-8 or multipart framing
+    InvalidBody(String),
+    TooManyFields {
+        limit: usize,
+    },
+    FieldTooLarge {
+        limit: usize,
+    },
+    TooManyFiles {
+        limit: usize,
+    },
+    FileTooLarge {
+        limit: usize,
+    },
+    /// A field the handler needs wasn't submitted
+    MissingField(String),
+    /// A field couldn't be parsed as the type the handler needs
+    InvalidField(String),
+    /// The body couldn't be read, e.g. the client cut it short, was too slow, or exceeded the server's maximum body size
+    BodyError(ErrorKind),
+    /// An uploaded file couldn't be written to the temporary directory
+    IoError(ErrorKind),
+}
+
+impl Form {
+    /// The first value of the field
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)
+    }
+
+    /// Every value of the field, in the order they were submitted, e.g. for checkboxes sharing a name
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields.get_all(name)
+    }
+
+    /// Every field that isn't a file
+    pub fn fields(&self) -> &Query {
+        &self.fields
+    }
+
+    /// Parse the first value of the field, failing if it's missing or doesn't parse
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        self.get(name)
+            .ok_or_else(|| FormError::MissingField(name.to_string()))?
+            .parse()
+            .map_err(|_| FormError::InvalidField(name.to_string()))
+    }
+
+    /// The first file uploaded with the field name
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, file)| file)
+    }
+
+    /// Every file uploaded with the field name, e.g. from an input accepting multiple files
+    pub fn files<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a UploadedFile> + 'a {
+        self.files
+            .iter()
+            .filter(move |(existing, _)| existing == name)
+            .map(|(_, file)| file)
+    }
+
+    /// Take ownership of the first file uploaded with the field name, so it can be persisted
+    pub fn take_file(&mut self, name: &str) -> Option<UploadedFile> {
+        let index = self
+            .files
+            .iter()
+            .position(|(existing, _)| existing == name)?;
+        Some(self.files.remove(index).1)
+    }
+}
+
+impl UploadedFile {
+    /// Create a new, empty file in the directory, returning it for the content to be written to
+    fn create(
+        directory: &Path,
+        file_name: &str,
+        content_type: Option<&str>,
+    ) -> io::Result<(UploadedFile, File)> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let path = directory.join(format!(
+            "webserver-upload-{}-{nanos}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // returned before anything is written, so the file is removed if a write fails
+        let upload = UploadedFile {
+            file_name: file_name.to_string(),
+            content_type: content_type.map(str::to_string),
+            path,
+            size: 0,
+        };
+        Ok((upload, file))
+    }
+
+    /// The name of the file on the client, without any directories, which mustn't be trusted as a path
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// The type the client gave the file, if any
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Where the file is stored until it's dropped
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// Move the file to the destination, so it's kept once this is dropped
+    pub fn persist(mut self, destination: impl AsRef<Path>) -> io::Result<()> {
+        // renaming fails across file systems, where the file is copied and the temporary one removed on drop
+        if fs::rename(&self.path, destination.as_ref()).is_err() {
+            fs::copy(&self.path, destination)?;
+            return Ok(());
+        }
+        self.path = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl FormConfig {
+    const DEFAULT_MAX_FIELDS: usize = 1000;
+    const DEFAULT_MAX_FIELD_SIZE: usize = 64 * 1024;
+    const DEFAULT_MAX_FILES: usize = 16;
+    const DEFAULT_MAX_FILE_SIZE: usize = 8 * 1024 * 1024;
+
+    /// The default limits, with files written to the system's temporary directory
+    ///
+    /// The server's maximum body size limits the form as a whole, and may need raising to accept large files.
+    pub fn new() -> Self {
+        FormConfig {
+            max_fields: FormConfig::DEFAULT_MAX_FIELDS,
+            max_field_size: FormConfig::DEFAULT_MAX_FIELD_SIZE,
+            max_files: FormConfig::DEFAULT_MAX_FILES,
+            max_file_size: FormConfig::DEFAULT_MAX_FILE_SIZE,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+
+    /// Set the maximum number of fields, counting files, by default 1000
+    pub fn with_max_fields(mut self, max_fields: usize) -> Self {
+        self.max_fields = max_fields;
+        self
+    }
+
+    /// Set the maximum size of a field that isn't a file, by default 64 KiB
+    pub fn with_max_field_size(mut self, max_field_size: usize) -> Self {
+        self.max_field_size = max_field_size;
+        self
+    }
+
+    /// Set the maximum number of files, by default 16
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Set the maximum size of each file written to the temporary directory, by default 8 MiB
+    pub fn with_max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Write uploaded files to the directory, which must exist
+    pub fn with_temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = temp_dir.into();
+        self
+    }
+}
+
+impl Default for FormConfig {
+    fn default() -> Self {
+        FormConfig::new()
+    }
+}
+
+impl FormError {
+    /// The status of the response to a request whose form couldn't be read
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            FormError::UnsupportedContentType => HttpStatus::UnsupportedMediaType415,
+            FormError::TooManyFields { .. }
+            | FormError::FieldTooLarge { .. }
+            | FormError::TooManyFiles { .. }
+            | FormError::FileTooLarge { .. }
+            | FormError::BodyError(ErrorKind::FileTooLarge) => HttpStatus::ContentTooLarge413,
+            FormError::BodyError(ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                HttpStatus::RequestTimeout408
+            }
+            FormError::IoError(_) => HttpStatus::InternalServerError500,
+            _ => HttpStatus::BadRequest400,
+        }
+    }
+}
+
+impl From<io::Error> for FormError {
+    fn from(err: io::Error) -> Self {
+        FormError::IoError(err.kind())
+    }
+}
+
+impl From<FormError> for HttpResponse {
+    fn from(err: FormError) -> Self {
+        let status = err.status();
+        match err {
+            FormError::IoError(_) => {
+                HttpResponse::new(status).body("The server failed to store the upload")
+            }
+            err => HttpResponse::new(status).body(format!("Invalid form: {err:?}")),
+        }
+    }
+}
+
+/// Decode the body as the form encoding given by its `Content-Type`
+pub(crate) fn parse_form(
+    content_type: Option<&str>,
+    body: impl Read,
+    config: &FormConfig,
+) -> Result<Form, FormError> {
+    let (media_type, parameters) =
+        parse_parameters(content_type.ok_or(FormError::UnsupportedContentType)?);
+    if media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        parse_urlencoded(body, config)
+    } else if media_type.eq_ignore_ascii_case("multipart/form-data") {
+        let boundary = find_parameter(&parameters, "boundary")
+            .filter(|boundary| (1..=MAX_BOUNDARY_LENGTH).contains(&boundary.len()))
+            .ok_or(FormError::UnsupportedContentType)?;
+        parse_multipart(body, boundary, config)
+    } else {
+        Err(FormError::UnsupportedContentType)
+    }
+}
+
+/// Whether the `Content-Type` is a multipart form, whose body is best decoded as it arrives
+pub(crate) fn is_multipart_form(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|content_type| {
+        parse_parameters(content_type)
+            .0
+            .eq_ignore_ascii_case("multipart/form-data")
+    })
+}
+
+/// Decode `name=value` pairs joined by `&`, escaped as in a query string
+fn parse_urlencoded(mut body: impl Read, config: &FormConfig) -> Result<Form, FormError> {
+    let mut content = Vec::new();
+    body.read_to_end(&mut content)
+        .map_err(|err| FormError::BodyError(err.kind()))?;
+    let invalid = || FormError::InvalidBody("Invalid escape or UTF-8".to_string());
+    let fields: Query = std::str::from_utf8(&content)
+        .map_err(|_| invalid())?
+        .parse()
+        .map_err(|_| invalid())?;
+    if fields.len() > config.max_fields {
+        return Err(FormError::TooManyFields {
+            limit: config.max_fields,
+        });
+    }
+    if fields
+        .iter()
+        .any(|(_, value)| value.len() > config.max_field_size)
+    {
+        return Err(FormError::FieldTooLarge {
+            limit: config.max_field_size,
+        });
+    }
+    Ok(Form {
+        fields,
+        files: Vec::new(),
+    })
+}
+
+/// Decode the parts between the boundaries (RFC 7578) as they're read, writing files to the temporary directory
+fn parse_multipart(
+    body: impl Read,
+    boundary: &str,
+    config: &FormConfig,
+) -> Result<Form, FormError> {
+    let invalid = |reason: &str| FormError::InvalidBody(reason.to_string());
+    let mut reader = MultipartReader::new(body, boundary);
+    // the first delimiter may follow a preamble, which is ignored
+    if !reader.read_to_delimiter(&mut |_| Ok(()))? {
+        return Err(invalid("Missing boundary"));
+    }
+
+    let mut form = Form::default();
+    let mut field_count = 0;
+    loop {
+        // the last delimiter is followed by `--`, and then an epilogue which is ignored
+        if reader.starts_with(b"--")? {
+            return Ok(form);
+        }
+        let padding = reader
+            .read_line(MAX_PART_HEAD_SIZE)?
+            .ok_or_else(|| invalid("Unterminated boundary"))?;
+        // whitespace is allowed after a delimiter, as transport padding
+        if !padding.iter().all(|byte| matches!(byte, b' ' | b'\t')) {
+            return Err(invalid("Text after boundary"));
+        }
+        field_count += 1;
+        if field_count > config.max_fields {
+            return Err(FormError::TooManyFields {
+                limit: config.max_fields,
+            });
+        }
+        parse_part(&mut reader, config, &mut form)?;
+    }
+}
+
+/// Decode a part's headers, and add its content to the form as a field or file
+fn parse_part(
+    reader: &mut MultipartReader<impl Read>,
+    config: &FormConfig,
+    form: &mut Form,
+) -> Result<(), FormError> {
+    let invalid = |reason: &str| FormError::InvalidBody(reason.to_string());
+    // the headers end with an empty line, which is all a part with no headers starts with
+    let mut headers = HttpHeaders::new();
+    let mut head_size = 0;
+    loop {
+        let line = reader
+            .read_line(MAX_PART_HEAD_SIZE.saturating_sub(head_size))?
+            .ok_or_else(|| invalid("Part headers too large or unterminated"))?;
+        if line.is_empty() {
+            break;
+        }
+        head_size += line.len() + 2;
+        let line = std::str::from_utf8(&line).map_err(|_| invalid("Part headers aren't UTF-8"))?;
+        let (name, value) =
+            HttpHeaders::parse_line(line).map_err(|_| invalid("Invalid part header"))?;
+        headers.append(&name, &value);
+    }
+
+    let (disposition, parameters) = parse_parameters(
+        headers
+            .get("Content-Disposition")
+            .ok_or_else(|| invalid("Part without Content-Disposition"))?,
+    );
+    if !disposition.eq_ignore_ascii_case("form-data") {
+        return Err(invalid("Part isn't form-data"));
+    }
+    let name = find_parameter(&parameters, "name")
+        .ok_or_else(|| invalid("Part without a name"))?
+        .to_string();
+    match find_parameter(&parameters, "filename") {
+        // browsers send an empty, nameless file for a file input left empty
+        Some("") if reader.skip_delimiter()? => Ok(()),
+        Some(file_name) => {
+            if form.files.len() == config.max_files {
+                return Err(FormError::TooManyFiles {
+                    limit: config.max_files,
+                });
+            }
+            // some clients send the full path the file had on their machine
+            let file_name = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+            let (mut upload, file) =
+                UploadedFile::create(&config.temp_dir, file_name, headers.get("Content-Type"))?;
+            let mut writer = BufWriter::new(file);
+            let mut size = 0;
+            reader.read_content(&mut |content| {
+                size += content.len();
+                if size > config.max_file_size {
+                    return Err(FormError::FileTooLarge {
+                        limit: config.max_file_size,
+                    });
+                }
+                writer.write_all(content)?;
+                Ok(())
+            })?;
+            writer.flush()?;
+            upload.size = size as u64;
+            form.files.push((name, upload));
+            Ok(())
+        }
+        None => {
+            let mut value = Vec::new();
+            reader.read_content(&mut |content| {
+                if content.len() > config.max_field_size - value.len() {
+                    return Err(FormError::FieldTooLarge {
+                        limit: config.max_field_size,
+                    });
+                }
+                value.extend_from_slice(content);
+                Ok(())
+            })?;
+            let value = String::from_utf8(value).map_err(|_| invalid("Field isn't UTF-8"))?;
+            form.fields.push(name, value);
+            Ok(())
+        }
+    }
+}
+
+/// Reads a multipart body through a buffer, so delimiters split across reads are still found
+struct MultipartReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    delimiter: Vec<u8>,
+}
+
+impl<R: Read> MultipartReader<R> {
+    fn new(reader: R, boundary: &str) -> Self {
+        MultipartReader {
+            reader,
+            // each delimiter starts a line, and the first may start the body, so the body is read as if it
+            // followed a line ending
+            buffer: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+        }
+    }
+
+    /// Read more of the body onto the end of the buffer, returning false once the body is exhausted
+    fn fill(&mut self) -> Result<bool, FormError> {
+        let start = self.buffer.len();
+        self.buffer.resize(start + READ_SIZE, 0);
+        loop {
+            match self.reader.read(&mut self.buffer[start..]) {
+                Ok(read) => {
+                    self.buffer.truncate(start + read);
+                    return Ok(read > 0);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.buffer.truncate(start);
+                    return Err(FormError::BodyError(err.kind()));
+                }
+            }
+        }
+    }
+
+    /// Read until the buffer holds at least `length` bytes, or the body is exhausted
+    fn fill_to(&mut self, length: usize) -> Result<(), FormError> {
+        while self.buffer.len() < length && self.fill()? {}
+        Ok(())
+    }
+
+    /// Whether the rest of the body starts with the prefix, without consuming it
+    fn starts_with(&mut self, prefix: &[u8]) -> Result<bool, FormError> {
+        self.fill_to(prefix.len())?;
+        Ok(self.buffer.starts_with(prefix))
+    }
+
+    /// Consume a delimiter if it comes next, as it does after a part with no content
+    fn skip_delimiter(&mut self) -> Result<bool, FormError> {
+        self.fill_to(self.delimiter.len())?;
+        if !self.buffer.starts_with(&self.delimiter) {
+            return Ok(false);
+        }
+        self.buffer.drain(..self.delimiter.len());
+        Ok(true)
+    }
+
+    /// Read a line of at most `max_length` bytes, without its CRLF,
+    /// returning None if it's longer or the body ends first
+    fn read_line(&mut self, max_length: usize) -> Result<Option<Vec<u8>>, FormError> {
+        let mut searched = 0;
+        loop {
+            if let Some(end) = find(&self.buffer[searched..], b"\r\n") {
+                let end = searched + end;
+                if end > max_length {
+                    return Ok(None);
+                }
+                let line = self.buffer[..end].to_vec();
+                self.buffer.drain(..end + 2);
+                return Ok(Some(line));
+            }
+            if self.buffer.len() > max_length + 1 {
+                return Ok(None);
+            }
+            // the CR may already be in the buffer, waiting for its LF
+            searched = self.buffer.len().saturating_sub(1);
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Pass the body to the sink up to the next delimiter, which is consumed,
+    /// returning false if the body ends without one
+    fn read_to_delimiter(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<bool, FormError> {
+        loop {
+            if let Some(end) = find(&self.buffer, &self.delimiter) {
+                sink(&self.buffer[..end])?;
+                self.buffer.drain(..end + self.delimiter.len());
+                return Ok(true);
+            }
+            // the end of the buffer may be the start of a delimiter, so it's kept until more is read
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            sink(&self.buffer[..safe])?;
+            self.buffer.drain(..safe);
+            if !self.fill()? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Pass a part's content to the sink as it's read, up to the delimiter ending the part
+    fn read_content(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<(), FormError> {
+        if self.read_to_delimiter(sink)? {
+            Ok(())
+        } else {
+            Err(FormError::InvalidBody(
+                "Missing closing boundary".to_string(),
+            ))
+        }
+    }
+}
+
+/// Split a header value like `form-data; name="a; b"` into its first part and its parameters, with quotes removed
+///
+/// Parameter names are lowercased. Quoted values have no escapes, since browsers percent-encode quotes in them.
+fn parse_parameters(value: &str) -> (&str, Vec<(String, &str)>) {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, byte) in value.bytes().enumerate() {
+        match byte {
+            b'"' => quoted = !quoted,
+            b';' if !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+
+    let first = parts[0].trim();
+    let parameters = parts[1..]
+        .iter()
+        .filter_map(|parameter| {
+            let (name, value) = parameter.split_once('=')?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.trim().to_ascii_lowercase(), value))
+        })
+        .collect();
+    (first, parameters)
+}
+
+fn find_parameter<'a>(parameters: &[(String, &'a str)], name: &str) -> Option<&'a str> {
+    parameters
+        .iter()
+        .find(|(existing, _)| existing == name)
+        .map(|(_, value)| *value)
+}
+
+/// The position of the first occurrence of the needle in the haystack
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::atomic::AtomicU32;
+
+    /// Create a fresh directory for uploaded files
+    fn create_temp_dir() -> PathBuf {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let directory = std::env::temp_dir().join(format!(
+            "webserver_form_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// Hands out the body a byte at a time, so every boundary is split across reads
+    struct Trickle<R>(R);
+
+    impl<R: Read> Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let length = buf.len().min(1);
+            self.0.read(&mut buf[..length])
+        }
+    }
+
+    /// Fails like a client that stopped sending the body
+    struct TimedOut;
+
+    impl Read for TimedOut {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(ErrorKind::TimedOut.into())
+        }
+    }
+
+    fn multipart(body: &str, config: &FormConfig) -> Result<Form, FormError> {
+        parse_form(
+            Some("multipart/form-data; boundary=\"XyZ\""),
+            Trickle(body.replace('\n', "\r\n").as_bytes()),
+            config,
+        )
+    }
+
+    #[test]
+    fn test_urlencoded() {
+        let config = FormConfig::new();
+        let content_type = Some("application/x-www-form-urlencoded; charset=UTF-8");
+        let form = parse_form(
+            content_type,
+            "name=Ann+Lee&age=42&tag=a&tag=%E2%9C%93".as_bytes(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(form.get("name"), Some("Ann Lee"));
+        assert_eq!(form.parse::<u32>("age"), Ok(42));
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(), vec!["a", "✓"]);
+        assert_eq!(
+            form.parse::<u32>("name"),
+            Err(FormError::InvalidField("name".to_string()))
+        );
+        assert_eq!(
+            form.parse::<u32>("missing"),
+            Err(FormError::MissingField("missing".to_string()))
+        );
+
+        assert!(matches!(
+            parse_form(content_type, "bad=%zz".as_bytes(), &config),
+            Err(FormError::InvalidBody(_))
+        ));
+        let limited = FormConfig::new().with_max_fields(2).with_max_field_size(3);
+        assert_eq!(
+            parse_form(content_type, "a=1&b=2&c=3".as_bytes(), &limited).unwrap_err(),
+            FormError::TooManyFields { limit: 2 }
+        );
+        assert_eq!(
+            parse_form(content_type, "a=1234".as_bytes(), &limited).unwrap_err(),
+            FormError::FieldTooLarge { limit: 3 }
+        );
+    }
+
+    #[test]
+    fn test_content_types() {
+        let config = FormConfig::new();
+        for content_type in [None, Some("text/plain"), Some("multipart/form-data")] {
+            assert_eq!(
+                parse_form(content_type, "".as_bytes(), &config).unwrap_err(),
+                FormError::UnsupportedContentType
+            );
+        }
+        let response = HttpResponse::from(FormError::UnsupportedContentType);
+        assert_eq!(response.status, HttpStatus::UnsupportedMediaType415);
+        let response = HttpResponse::from(FormError::FileTooLarge { limit: 1 });
+        assert_eq!(response.status, HttpStatus::ContentTooLarge413);
+        let response = HttpResponse::from(FormError::MissingField("name".to_string()));
+        assert_eq!(response.status, HttpStatus::BadRequest400);
+        let response = HttpResponse::from(FormError::BodyError(ErrorKind::TimedOut));
+        assert_eq!(response.status, HttpStatus::RequestTimeout408);
+    }
+
+    #[test]
+    fn test_multipart() {
+        let directory = create_temp_dir();
+        let config = FormConfig::new().with_temp_dir(&directory);
+        let mut form = multipart(
+            "preamble\n\
+             --XyZ\n\
+             Content-Disposition: form-data; name=\"title\"\n\
+             \n\
+             A note; with \"quotes\"\n\
+             --XyZ  \n\
+             content-disposition: form-data; name=\"upload\"; filename=\"C:\\docs\\a;b.txt\"\n\
+             Content-Type: text/plain\n\
+             \n\
+             line 1\n\
+             line 2\n\
+             --XyZ\n\
+             Content-Disposition: form-data; name=\"empty\"; filename=\"\"\n\
+             Content-Type: application/octet-stream\n\
+             \n\
+             \n\
+             --XyZ--\n\
+             epilogue",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(form.get("title"), Some("A note; with \"quotes\""));
+        assert!(form.file("empty").is_none());
+
+        let file = form.file("upload").unwrap();
+        assert_eq!(file.file_name(), "a;b.txt");
+        assert_eq!(file.content_type(), Some("text/plain"));
+        assert_eq!(file.size(), 14);
+        let mut content = String::new();
+        file.open().unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "line 1\r\nline 2");
+
+        // dropped files are deleted, and persisted ones moved
+        let temp_path = file.path().to_path_buf();
+        let destination = directory.join("kept.txt");
+        form.take_file("upload")
+            .unwrap()
+            .persist(&destination)
+            .unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), content);
+
+        let form = multipart(
+            "--XyZ\nContent-Disposition: form-data; name=\"a\"; filename=\"a\"\n\nA\n--XyZ--",
+            &config,
+        )
+        .unwrap();
+        let temp_path = form.file("a").unwrap().path().to_path_buf();
+        assert!(temp_path.exists());
+        drop(form);
+        assert!(!temp_path.exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_multipart_errors() {
+        let directory = create_temp_dir();
+        let config = FormConfig::new()
+            .with_temp_dir(&directory)
+            .with_max_file_size(4)
+            .with_max_files(1);
+        let file = |name: &str, content: &str| {
+            format!("--XyZ\nContent-Disposition: form-data; name=\"{name}\"; filename=\"f\"\n\n{content}\n")
+        };
+        assert_eq!(
+            multipart(&format!("{}--XyZ--", file("a", "12345")), &config).unwrap_err(),
+            FormError::FileTooLarge { limit: 4 }
+        );
+        assert_eq!(
+            multipart(
+                &format!("{}{}--XyZ--", file("a", "1"), file("b", "2")),
+                &config
+            )
+            .unwrap_err(),
+            FormError::TooManyFiles { limit: 1 }
+        );
+        for body in [
+            "no boundary",
+            "--XyZ\nContent-Disposition: form-data; name=\"a\"\n\nunterminated",
+            "--XyZ\nContent-Type: text/plain\n\nno disposition\n--XyZ--",
+            "--XyZ\nContent-Disposition: form-data\n\nno name\n--XyZ--",
+            "--XyZ text\nContent-Disposition: form-data; name=\"a\"\n\n\n--XyZ--",
+        ] {
+            assert!(
+                matches!(multipart(body, &config), Err(FormError::InvalidBody(_))),
+                "{body}"
+            );
+        }
+        // the body stops arriving part way through a file
+        let cut_short = format!("{}1", file("a", "1")).replace('\n', "\r\n");
+        assert_eq!(
+            parse_form(
+                Some("multipart/form-data; boundary=XyZ"),
+                cut_short.as_bytes().chain(TimedOut),
+                &config
+            )
+            .unwrap_err(),
+            FormError::BodyError(ErrorKind::TimedOut)
+        );
+        // the files written before the error are removed
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+use crate::http::{HttpRequest, HttpResponse};
+
+/// Produces the response to each request received by a [`Server`](crate::http::Server)
+///
+/// Implemented for any thread safe closure taking a request and returning a response
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: HttpRequest) -> HttpResponse;
+}
+
+impl<F: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static> Handler for F {
+    fn handle(&self, request: HttpRequest) -> HttpResponse {
+        self(request)
+    }
+}
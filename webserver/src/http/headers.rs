@@ -0,0 +1,180 @@
+use crate::http::headers::HeaderParseError::*;
+
+/// A case-insensitive multimap of HTTP header names to values, preserving the order they were added
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::HttpHeaders;
+/// let mut headers = HttpHeaders::new();
+/// headers.append("Accept", "text/html");
+/// headers.append("accept", "application/json");
+/// assert_eq!(headers.get("ACCEPT"), Some("text/html"));
+/// assert_eq!(headers.get_all("Accept").collect::<Vec<_>>(), vec!["text/html", "application/json"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpHeaders {
+    entries: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeaderParseError {
+    MissingColon(String),
+    EmptyName,
+    InvalidName(String),
+}
+
+impl HttpHeaders {
+    pub fn new() -> HttpHeaders {
+        HttpHeaders {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Parse a single `Name: value` header line
+    pub fn parse_line(line: &str) -> Result<(String, String), HeaderParseError> {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| MissingColon(line.to_string()))?;
+        if name.is_empty() {
+            return Err(EmptyName);
+        }
+        // whitespace between the name and colon is forbidden (RFC 9112 section 5.1)
+        if !name.bytes().all(is_token_char) {
+            return Err(InvalidName(name.to_string()));
+        }
+        Ok((name.to_string(), value.trim().to_string()))
+    }
+
+    /// Add a value for the header, keeping any existing values
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// Set the value for the header, replacing any existing values
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Remove all values for the header
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+    }
+
+    /// The first value of the header
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the header, in the order they were received
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(existing, _)| existing.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every comma separated element of every value of the header, e.g. `Accept: a, b` gives `a` and `b`
+    pub fn get_list<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromIterator<(String, String)> for HttpHeaders {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        HttpHeaders {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Characters allowed in a header name (the `tchar` rule of RFC 9110)
+pub(crate) fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Whether the name is a token, and the value can't end the header early or be cut short (RFC 9110 section 5.5)
+pub(crate) fn is_valid_field(name: &str, value: &str) -> bool {
+    !name.is_empty()
+        && name.bytes().all(is_token_char)
+        && !value
+            .bytes()
+            .any(|byte| matches!(byte, b'\r' | b'\n' | b'\0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            HttpHeaders::parse_line("Host: localhost:7878"),
+            Ok(("Host".to_string(), "localhost:7878".to_string()))
+        );
+        assert_eq!(
+            HttpHeaders::parse_line("X-Empty:"),
+            Ok(("X-Empty".to_string(), "".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_line_errors() {
+        assert_eq!(
+            HttpHeaders::parse_line("Host localhost"),
+            Err(MissingColon("Host localhost".to_string()))
+        );
+        assert_eq!(HttpHeaders::parse_line(": value"), Err(EmptyName));
+        assert_eq!(
+            HttpHeaders::parse_line("Host : localhost"),
+            Err(InvalidName("Host ".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_case_insensitive_multimap() {
+        let mut headers = HttpHeaders::new();
+        headers.append("Accept", "text/html, text/plain");
+        headers.append("ACCEPT", "application/json");
+        headers.append("Host", "localhost");
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.get("accept"), Some("text/html, text/plain"));
+        assert_eq!(
+            headers.get_list("Accept").collect::<Vec<_>>(),
+            vec!["text/html", "text/plain", "application/json"]
+        );
+
+        headers.insert("accept", "*/*");
+        assert_eq!(headers.get_all("Accept").collect::<Vec<_>>(), vec!["*/*"]);
+
+        headers.remove("HOST");
+        assert!(!headers.contains("Host"));
+        assert_eq!(headers.len(), 1);
+    }
+}
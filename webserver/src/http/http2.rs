@@ -0,0 +1,1629 @@
+mod frame;
+mod hpack;
+mod huffman;
+
+use self::frame::{parse_settings, ErrorCode, Frame};
+use self::hpack::{Decoder, HeaderField, HpackError};
+use crate::http::base64;
+use crate::http::connection::TimedConnection;
+use crate::http::headers::{is_token_char, is_valid_field};
+use crate::http::server::{
+    error_response, handle_request, is_timeout, overloaded_response, ConnectionSettings,
+    SHUTDOWN_POLL_INTERVAL,
+};
+use crate::http::{
+    AccessLog, AccessLogEntry, BodyParseError, Connection, Handler, HttpHeaders, HttpMethod,
+    HttpRequest, HttpResponse, HttpStatus, HttpVersion, RequestParseError, ShutdownHandle,
+};
+use crate::thread_pool::PoolHandle;
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::iter;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Instant, SystemTime};
+
+/// What a client sends first on an HTTP/2 connection, whose start looks like an HTTP/1 head
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// The length of the part of the preface read as an HTTP/1 request line and headers
+const PREFACE_HEAD_LENGTH: usize = 18;
+
+// settings (RFC 9113 section 6.5.2), the rest don't affect a server that never pushes or indexes headers
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// The size of flow control windows until settings change them
+const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+/// The largest frame either side can send until settings change it, which the server never does
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const MAX_FRAME_SIZE_LIMIT: usize = (1 << 24) - 1;
+/// The size of the HPACK dynamic table, which the server leaves at its default
+const HEADER_TABLE_SIZE: usize = 4_096;
+/// The most streams a client can have open at once, each of which is handled as its own job on the pool
+///
+/// Handlers keep running after the client resets their stream, so they count against it until they finish.
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/// The chunks of a streamed body produced ahead of the client, beyond which producing them waits
+const BUFFERED_CHUNKS: usize = 4;
+
+/// The chunks of a streamed body, which ends when the sender hangs up
+type BodyChunks = mpsc::Receiver<io::Result<Vec<u8>>>;
+
+/// What wakes the connection's thread, besides checking for timeouts
+enum Event {
+    /// The handler's response to a stream's request, with the rest of its body if it's streamed
+    Response(u32, HttpResponse, Option<BodyChunks>),
+    /// A streamed body has produced a chunk, or ended
+    BodyChunk,
+    /// The client has sent something, or closed the connection
+    Readable,
+}
+
+/// How a connection came to speak HTTP/2
+pub(super) enum Start {
+    /// The client started with the connection preface, whose HTTP/1 head has already been read
+    PriorKnowledge,
+    /// The client asked to upgrade from HTTP/1.1 with this request, which is answered over HTTP/2
+    Upgrade(Box<HttpRequest>),
+}
+
+/// The state of an HTTP/2 connection, which reads frames and writes responses on one thread
+struct Http2Connection<'a, C: Connection> {
+    connection: TimedConnection<C>,
+    settings: ConnectionSettings,
+    shutdown: &'a ShutdownHandle,
+    access_log: Option<&'a AccessLog>,
+    peer: Option<SocketAddr>,
+    /// The part of the client's preface that hasn't arrived yet
+    preface: &'static [u8],
+    settings_received: bool,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    decoder: Decoder,
+    streams: BTreeMap<u32, Stream>,
+    /// The highest stream the client has opened
+    last_stream_id: u32,
+    /// A header block whose continuation frames haven't all arrived
+    partial_headers: Option<PartialHeaders>,
+    /// Requests ready to be handed to the handler
+    ready: Vec<(u32, HttpRequest)>,
+    /// The number of requests the handler hasn't responded to yet, including ones whose stream was reset
+    handling: usize,
+    send_window: i64,
+    initial_window_size: i64,
+    max_frame_size: usize,
+    goaway_sent: bool,
+    goaway_received: bool,
+    last_activity: Instant,
+}
+
+/// A stream from when its headers arrive until its response has been sent, or either side resets it
+struct Stream {
+    /// Whether the client may still send more of the request
+    receiving: bool,
+    /// The request while its body arrives, until it's passed to the handler or answered early
+    request: Option<HttpRequest>,
+    body: Vec<u8>,
+    content_length: Option<usize>,
+    is_head: bool,
+    opened: Instant,
+    time: SystemTime,
+    /// The details logged with the response, taken as the request is passed to the handler or answered early
+    log: Option<RequestLog>,
+    send_window: i64,
+    /// The response body, which is sent as the client's flow control windows allow
+    response_body: Vec<u8>,
+    sent: usize,
+    /// The rest of a streamed response body, taken a chunk at a time once the last has been sent
+    body_chunks: Option<BodyChunks>,
+}
+
+/// The details of a stream's request for the access log, which are missing if it couldn't be built
+struct RequestLog {
+    method: Option<HttpMethod>,
+    path: Option<String>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+struct PartialHeaders {
+    stream_id: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+    dependency: Option<u32>,
+}
+
+/// Why a stream's request can't be passed to the handler
+enum RequestError {
+    /// The request breaks the rules of HTTP/2, so the stream is reset
+    Malformed,
+    /// The request is answered with an error response, as it would be over HTTP/1
+    Invalid(RequestParseError),
+}
+
+/// Whether the request asks to upgrade to HTTP/2 over cleartext, with valid settings (RFC 7540 section 3.2)
+pub(super) fn is_upgrade(request: &HttpRequest) -> bool {
+    let has_option = |name, option: &str| {
+        request
+            .headers()
+            .get_list(name)
+            .any(|value| value.eq_ignore_ascii_case(option))
+    };
+    has_option("Upgrade", "h2c")
+        && has_option("Connection", "Upgrade")
+        && has_option("Connection", "HTTP2-Settings")
+        && upgrade_settings(request).is_some()
+}
+
+/// The settings sent in the request's `HTTP2-Settings` header, as the payload of a SETTINGS frame
+fn upgrade_settings(request: &HttpRequest) -> Option<Vec<(u16, u32)>> {
+    let mut values = request.headers().get_all("HTTP2-Settings");
+    let (Some(value), None) = (values.next(), values.next()) else {
+        return None;
+    };
+    parse_settings(&base64::decode_url(value)?)
+}
+
+/// Serve HTTP/2 until either side closes the connection, handling each stream's request as a job on the pool
+pub(super) fn serve<C: Connection>(
+    reader: BufReader<TimedConnection<C>>,
+    start: Start,
+    handler: &Arc<impl Handler>,
+    pool: &PoolHandle,
+    settings: ConnectionSettings,
+    shutdown: &ShutdownHandle,
+    access_log: Option<&AccessLog>,
+) {
+    let peer = reader.get_ref().get_ref().peer_addr().ok();
+    // anything read past the HTTP/1 head is already HTTP/2
+    let input = reader.buffer().to_vec();
+    let mut connection = reader.into_inner();
+    connection.set_deadline(None);
+    let mut http2 = Http2Connection {
+        connection,
+        settings,
+        shutdown,
+        access_log,
+        peer,
+        preface: PREFACE,
+        settings_received: false,
+        input,
+        output: Vec::new(),
+        decoder: Decoder::new(HEADER_TABLE_SIZE),
+        streams: BTreeMap::new(),
+        last_stream_id: 0,
+        partial_headers: None,
+        ready: Vec::new(),
+        handling: 0,
+        send_window: DEFAULT_WINDOW_SIZE,
+        initial_window_size: DEFAULT_WINDOW_SIZE,
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        goaway_sent: false,
+        goaway_received: false,
+        last_activity: Instant::now(),
+    };
+    // the server's preface is its settings, which it can send without waiting for the client's
+    http2.queue(Frame::Settings {
+        ack: false,
+        settings: vec![
+            (
+                SETTINGS_MAX_CONCURRENT_STREAMS,
+                MAX_CONCURRENT_STREAMS as u32,
+            ),
+            (
+                SETTINGS_MAX_HEADER_LIST_SIZE,
+                settings.max_header_size.try_into().unwrap_or(u32::MAX),
+            ),
+        ],
+    });
+    match start {
+        Start::PriorKnowledge => http2.preface = &PREFACE[PREFACE_HEAD_LENGTH..],
+        Start::Upgrade(request) => {
+            let response = HttpResponse::new(HttpStatus::SwitchingProtocols101)
+                .header("Connection", "Upgrade")
+                .header("Upgrade", "h2c");
+            if let Err(err) = response.write_head_to(&mut http2.connection) {
+                eprintln!("Failed to write response, received error: {}", err.kind());
+                return;
+            }
+            // the header's settings are acknowledged by the upgrade itself, rather than with a frame
+            let client_settings = upgrade_settings(&request).unwrap_or_default();
+            if let Err(error_code) = http2.apply_settings(&client_settings) {
+                http2.go_away(error_code);
+            } else {
+                http2.open_upgraded(*request);
+            }
+        }
+    }
+    http2.run(handler, pool);
+}
+
+impl<C: Connection> Http2Connection<'_, C> {
+    /// Exchange frames until the connection closes, handing requests to the handler as they complete
+    ///
+    /// The connection's thread sleeps until a handler responds, a streamed body produces a chunk,
+    /// or another thread watching the socket sees the client has sent something.
+    /// Requests are answered with a 503 when the pool's queue has no room for their handler.
+    fn run(&mut self, handler: &Arc<impl Handler>, pool: &PoolHandle) {
+        // the timeout is shared with the connection, and lets the watching thread notice it has closed
+        let socket = self.connection.get_ref().socket();
+        let socket = socket.map_or(Err(ErrorKind::Unsupported.into()), TcpStream::try_clone);
+        let socket = socket.and_then(|socket| {
+            socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+            Ok(socket)
+        });
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(err) => {
+                eprintln!(
+                    "Failed to prepare connection, received error: {}",
+                    err.kind()
+                );
+                return;
+            }
+        };
+        self.connection
+            .set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL));
+        let (sender, receiver) = mpsc::channel();
+        let (read_sender, read_receiver) = mpsc::channel();
+        // the scope waits for the watching thread, while handlers still running on the pool when the connection
+        // closes carry on, but their responses are dropped
+        thread::scope(|scope| {
+            let events = sender.clone();
+            scope.spawn(move || watch(&socket, &events, &read_receiver));
+            // frames may have arrived before the connection switched to HTTP/2
+            if let Err(error_code) = self.handle_input() {
+                self.go_away(error_code);
+            }
+            loop {
+                for (stream_id, request) in std::mem::take(&mut self.ready) {
+                    let sender = sender.clone();
+                    let handler = Arc::clone(handler);
+                    let job = move || respond_on(&sender, stream_id, handler.as_ref(), request);
+                    // blocking on a full queue could leave every thread waiting for a thread
+                    if pool.try_execute(job).is_err() {
+                        self.handling -= 1;
+                        self.respond(stream_id, overloaded_response(), None);
+                    }
+                }
+                if !self.step() {
+                    break;
+                }
+                let Ok(event) = receiver.recv_timeout(SHUTDOWN_POLL_INTERVAL) else {
+                    continue;
+                };
+                let mut is_open = true;
+                for event in iter::once(event).chain(receiver.try_iter()) {
+                    match event {
+                        Event::Response(stream_id, response, body_chunks) => {
+                            self.handling -= 1;
+                            self.respond(stream_id, response, body_chunks);
+                        }
+                        // the chunk is taken once the one before it has been sent
+                        Event::BodyChunk => {}
+                        Event::Readable => {
+                            is_open &= self.receive();
+                            // the socket is only watched again once what woke the connection has been read
+                            let _ = read_sender.send(());
+                        }
+                    }
+                }
+                if !is_open {
+                    break;
+                }
+            }
+            // hang up on streamed bodies and the watching thread, so they stop waiting
+            self.streams.clear();
+            drop(read_sender);
+            drop(receiver);
+        });
+    }
+
+    /// Send what can be sent, returning false once the connection should close
+    fn step(&mut self) -> bool {
+        // chunks produced while the last was being sent can go straight after it
+        loop {
+            self.send_data();
+            if !self.receive_body_chunks() {
+                break;
+            }
+        }
+        self.check_timeouts();
+        if let Err(err) = self.flush() {
+            eprintln!("Failed to write frames, received error: {}", err.kind());
+            return false;
+        }
+        let is_closing = self.goaway_sent || self.goaway_received;
+        !(is_closing && self.streams.is_empty() && self.ready.is_empty())
+    }
+
+    /// Read what the client has sent and handle any complete frames, returning false once the connection should close
+    fn receive(&mut self) -> bool {
+        match self.read() {
+            Ok(is_open) => is_open,
+            Err(error_code) => {
+                self.go_away(error_code);
+                let _ = self.flush();
+                false
+            }
+        }
+    }
+
+    /// Read what the client has sent, returning false if it closed the connection
+    fn read(&mut self) -> Result<bool, ErrorCode> {
+        let mut buffer = [0; DEFAULT_MAX_FRAME_SIZE];
+        match self.connection.read(&mut buffer) {
+            Ok(0) => return Ok(false),
+            Ok(length) => {
+                self.input.extend_from_slice(&buffer[..length]);
+                self.last_activity = Instant::now();
+            }
+            Err(err) if is_timeout(err.kind()) => return Ok(true),
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => return Ok(false),
+            Err(err) => {
+                eprintln!("Failed to read frames, received error: {}", err.kind());
+                return Ok(false);
+            }
+        }
+        self.handle_input()?;
+        Ok(true)
+    }
+
+    /// Check the rest of the client's preface, then handle the complete frames that have arrived
+    fn handle_input(&mut self) -> Result<(), ErrorCode> {
+        if !self.preface.is_empty() {
+            let length = self.preface.len().min(self.input.len());
+            if self.input[..length] != self.preface[..length] {
+                return Err(ErrorCode::ProtocolError);
+            }
+            self.input.drain(..length);
+            self.preface = &self.preface[length..];
+        }
+        let mut offset = 0;
+        while let Some((frame, length)) =
+            Frame::parse(&self.input[offset..], DEFAULT_MAX_FRAME_SIZE)?
+        {
+            offset += length;
+            self.handle_frame(frame)?;
+        }
+        self.input.drain(..offset);
+        Ok(())
+    }
+
+    /// Handle a frame, returning an error if it breaks the rules of the connection as a whole
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), ErrorCode> {
+        // the client's preface ends with its settings
+        if !self.settings_received && !matches!(frame, Frame::Settings { ack: false, .. }) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        // nothing can come between the frames of a header block (RFC 9113 section 6.10)
+        if let Some(partial) = &self.partial_headers {
+            if !matches!(frame, Frame::Continuation { stream_id, .. } if stream_id == partial.stream_id)
+            {
+                return Err(ErrorCode::ProtocolError);
+            }
+        }
+        match frame {
+            Frame::Data {
+                stream_id,
+                data,
+                end_stream,
+                padding,
+            } => self.receive_data(stream_id, data, end_stream, padding),
+            Frame::Headers {
+                stream_id,
+                block,
+                end_stream,
+                end_headers,
+                dependency,
+            } => {
+                let partial = PartialHeaders {
+                    stream_id,
+                    block,
+                    end_stream,
+                    dependency,
+                };
+                self.receive_header_block(partial, end_headers)
+            }
+            Frame::Continuation {
+                block, end_headers, ..
+            } => {
+                let mut partial = self
+                    .partial_headers
+                    .take()
+                    .ok_or(ErrorCode::ProtocolError)?;
+                partial.block.extend_from_slice(&block);
+                self.receive_header_block(partial, end_headers)
+            }
+            Frame::Priority {
+                stream_id,
+                dependency,
+            } => {
+                // priorities are only hints, so they're ignored unless they're invalid
+                if dependency == stream_id {
+                    self.reset(stream_id, ErrorCode::ProtocolError);
+                }
+                Ok(())
+            }
+            Frame::RstStream { stream_id, .. } => {
+                if stream_id > self.last_stream_id {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                self.streams.remove(&stream_id);
+                Ok(())
+            }
+            Frame::Settings {
+                ack: false,
+                settings,
+            } => {
+                self.apply_settings(&settings)?;
+                self.settings_received = true;
+                self.queue(Frame::Settings {
+                    ack: true,
+                    settings: Vec::new(),
+                });
+                Ok(())
+            }
+            Frame::Ping {
+                ack: false,
+                payload,
+            } => {
+                self.queue(Frame::Ping { ack: true, payload });
+                Ok(())
+            }
+            Frame::GoAway { .. } => {
+                self.goaway_received = true;
+                Ok(())
+            }
+            Frame::WindowUpdate {
+                stream_id,
+                increment,
+            } => self.update_window(stream_id, increment),
+            Frame::Settings { ack: true, .. }
+            | Frame::Ping { ack: true, .. }
+            | Frame::Unknown { .. } => Ok(()),
+        }
+    }
+
+    /// Apply the client's settings, which change how the server sends frames
+    fn apply_settings(&mut self, settings: &[(u16, u32)]) -> Result<(), ErrorCode> {
+        for &(id, value) in settings {
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(ErrorCode::ProtocolError),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let window_size = i64::from(value);
+                    if window_size > MAX_WINDOW_SIZE {
+                        return Err(ErrorCode::FlowControlError);
+                    }
+                    // open streams' windows change by the difference (RFC 9113 section 6.9.2)
+                    let change = window_size - self.initial_window_size;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += change;
+                        if stream.send_window > MAX_WINDOW_SIZE {
+                            return Err(ErrorCode::FlowControlError);
+                        }
+                    }
+                    self.initial_window_size = window_size;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    let max_frame_size = value as usize;
+                    if !(DEFAULT_MAX_FRAME_SIZE..=MAX_FRAME_SIZE_LIMIT).contains(&max_frame_size) {
+                        return Err(ErrorCode::ProtocolError);
+                    }
+                    self.max_frame_size = max_frame_size;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn update_window(&mut self, stream_id: u32, increment: u32) -> Result<(), ErrorCode> {
+        let increment = i64::from(increment);
+        if stream_id == 0 {
+            self.send_window += increment;
+            if increment == 0 {
+                return Err(ErrorCode::ProtocolError);
+            } else if self.send_window > MAX_WINDOW_SIZE {
+                return Err(ErrorCode::FlowControlError);
+            }
+        } else if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.send_window += increment;
+            if increment == 0 {
+                self.reset(stream_id, ErrorCode::ProtocolError);
+            } else if stream.send_window > MAX_WINDOW_SIZE {
+                self.reset(stream_id, ErrorCode::FlowControlError);
+            }
+        } else if stream_id > self.last_stream_id {
+            return Err(ErrorCode::ProtocolError);
+        }
+        Ok(())
+    }
+
+    /// Collect a header block until its last frame arrives, then decode it
+    fn receive_header_block(
+        &mut self,
+        partial: PartialHeaders,
+        end_headers: bool,
+    ) -> Result<(), ErrorCode> {
+        // a block has to be decoded to keep the dynamic table in step, so it can't be skipped when it's too large
+        if partial.block.len() > self.settings.max_header_size.saturating_mul(2) {
+            return Err(ErrorCode::EnhanceYourCalm);
+        }
+        if !end_headers {
+            self.partial_headers = Some(partial);
+            return Ok(());
+        }
+        let max_header_size = self.settings.max_header_size;
+        let fields = match self.decoder.decode(&partial.block, max_header_size) {
+            Ok(fields) => Ok(fields),
+            Err(HpackError::HeaderListTooLarge) => Err(RequestParseError::HeadersTooLarge {
+                limit: max_header_size,
+            }),
+            Err(_) => return Err(ErrorCode::CompressionError),
+        };
+        let stream_id = partial.stream_id;
+        if self.streams.contains_key(&stream_id) {
+            self.receive_trailers(stream_id, fields, partial.end_stream);
+            return Ok(());
+        }
+        // streams the client opens have odd identifiers, which only go up
+        if stream_id.is_multiple_of(2) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        // frames can still arrive for a stream that was recently closed, and ones after GOAWAY are ignored
+        if stream_id <= self.last_stream_id || self.goaway_sent {
+            return Ok(());
+        }
+        self.last_stream_id = stream_id;
+        if partial.dependency == Some(stream_id) {
+            self.reset(stream_id, ErrorCode::ProtocolError);
+        } else if self.streams.len() >= MAX_CONCURRENT_STREAMS
+            || self.handling >= MAX_CONCURRENT_STREAMS
+        {
+            self.reset(stream_id, ErrorCode::RefusedStream);
+        } else {
+            self.open(stream_id, fields, partial.end_stream);
+        }
+        Ok(())
+    }
+
+    /// Start a stream for a request whose headers have arrived
+    fn open(
+        &mut self,
+        stream_id: u32,
+        fields: Result<Vec<HeaderField>, RequestParseError>,
+        end_stream: bool,
+    ) {
+        let result = fields
+            .map_err(RequestError::Invalid)
+            .and_then(build_request);
+        let mut stream = Stream::new(!end_stream, self.initial_window_size);
+        match result {
+            Ok(request) => {
+                stream.is_head = request.method() == HttpMethod::Head;
+                stream.content_length = match request.header("Content-Length").map(str::parse) {
+                    Some(Ok(length)) => Some(length),
+                    Some(Err(_)) => return self.reset(stream_id, ErrorCode::ProtocolError),
+                    None => None,
+                };
+                stream.request = Some(request);
+                self.streams.insert(stream_id, stream);
+                let max_body_size = self.settings.max_body_size;
+                if self.streams[&stream_id].content_length > Some(max_body_size) {
+                    let err = BodyParseError::TooLarge {
+                        limit: max_body_size,
+                    };
+                    self.reject(stream_id, RequestParseError::InvalidBody(err));
+                } else if end_stream {
+                    self.end_request(stream_id);
+                }
+            }
+            Err(RequestError::Malformed) => self.reset(stream_id, ErrorCode::ProtocolError),
+            Err(RequestError::Invalid(err)) => {
+                self.streams.insert(stream_id, stream);
+                self.reject(stream_id, err);
+            }
+        }
+    }
+
+    /// Start the stream of a request that upgraded the connection, which has already arrived in full
+    fn open_upgraded(&mut self, request: HttpRequest) {
+        let mut stream = Stream::new(false, self.initial_window_size);
+        stream.is_head = request.method() == HttpMethod::Head;
+        stream.body = request.body().to_vec();
+        stream.request = Some(request);
+        self.last_stream_id = 1;
+        self.streams.insert(1, stream);
+        self.end_request(1);
+    }
+
+    /// Add the headers that follow a request's body, which must end the stream
+    fn receive_trailers(
+        &mut self,
+        stream_id: u32,
+        fields: Result<Vec<HeaderField>, RequestParseError>,
+        end_stream: bool,
+    ) {
+        let stream = self
+            .streams
+            .get_mut(&stream_id)
+            .expect("The stream should be open");
+        if !stream.receiving {
+            return self.reset(stream_id, ErrorCode::StreamClosed);
+        } else if !end_stream {
+            return self.reset(stream_id, ErrorCode::ProtocolError);
+        }
+        let fields = match fields {
+            Ok(fields) => fields,
+            Err(err) => return self.reject(stream_id, err),
+        };
+        let mut trailers = HttpHeaders::new();
+        for (name, value) in fields {
+            match header(name, value) {
+                Some((name, value)) if !name.starts_with(':') => trailers.append(&name, &value),
+                _ => return self.reset(stream_id, ErrorCode::ProtocolError),
+            }
+        }
+        if let Some(request) = &mut stream.request {
+            for (name, value) in trailers.iter() {
+                request.headers_mut().append(name, value);
+            }
+        }
+        stream.receiving = false;
+        self.end_request(stream_id);
+    }
+
+    fn receive_data(
+        &mut self,
+        stream_id: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        padding: usize,
+    ) -> Result<(), ErrorCode> {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return if stream_id > self.last_stream_id {
+                Err(ErrorCode::ProtocolError)
+            } else {
+                // the stream was closed recently, but the data still counts toward the connection's window
+                self.open_window(0, data.len() + padding);
+                Ok(())
+            };
+        };
+        if !stream.receiving {
+            self.open_window(0, data.len() + padding);
+            self.reset(stream_id, ErrorCode::StreamClosed);
+            return Ok(());
+        }
+        stream.receiving = !end_stream;
+        let is_collecting = stream.request.is_some();
+        if is_collecting {
+            stream.body.extend_from_slice(&data);
+        }
+        let body_size = stream.body.len();
+        // the body is buffered as it arrives, so the windows can be opened straight back up
+        self.open_window(0, data.len() + padding);
+        if is_collecting && !end_stream {
+            self.open_window(stream_id, data.len() + padding);
+        }
+        if body_size > self.settings.max_body_size {
+            let err = BodyParseError::TooLarge {
+                limit: self.settings.max_body_size,
+            };
+            self.reject(stream_id, RequestParseError::InvalidBody(err));
+        } else if end_stream {
+            self.end_request(stream_id);
+        }
+        Ok(())
+    }
+
+    /// Pass a request whose body has arrived to the handler
+    fn end_request(&mut self, stream_id: u32) {
+        let stream = self
+            .streams
+            .get_mut(&stream_id)
+            .expect("The stream should be open");
+        let Some(mut request) = stream.request.take() else {
+            return;
+        };
+        // the body must be as long as the request said it would be (RFC 9113 section 8.1.1)
+        if stream
+            .content_length
+            .is_some_and(|length| length != stream.body.len())
+        {
+            return self.reset(stream_id, ErrorCode::ProtocolError);
+        }
+        request.set_body(std::mem::take(&mut stream.body));
+        stream.log = Some(RequestLog::new(Some(&request)));
+        self.handling += 1;
+        self.ready.push((stream_id, request));
+    }
+
+    /// Answer a request that can't be passed to the handler, without waiting for the rest of it
+    fn reject(&mut self, stream_id: u32, err: RequestParseError) {
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.log = Some(RequestLog::new(stream.request.take().as_ref()));
+            stream.body = Vec::new();
+        }
+        self.respond(stream_id, error_response(&err), None);
+    }
+
+    /// Send the response's headers, queueing its body to be sent as flow control allows
+    fn respond(&mut self, stream_id: u32, response: HttpResponse, body_chunks: Option<BodyChunks>) {
+        // the client may have reset the stream while the request was being handled
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        // headers set directly on the response are only checked as it's sent
+        if !response
+            .headers
+            .iter()
+            .all(|(name, value)| is_valid_field(name, value))
+        {
+            eprintln!("Failed to send response, it has an invalid header");
+            return self.reset(stream_id, ErrorCode::InternalError);
+        }
+        let is_streaming = body_chunks.is_some();
+        let has_body = response.status_allows_body()
+            && !stream.is_head
+            && (is_streaming || !response.body.is_empty());
+        let status = response.status.status_code().to_string();
+        let content_length = response.body.len().to_string();
+        // HTTP/2 header names are lower case, and it has its own ways of managing connections
+        let headers: Vec<(String, &str)> = response
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .filter(|(name, _)| !is_connection_specific(name))
+            .collect();
+        let mut fields = vec![(":status", status.as_str())];
+        fields.extend(headers.iter().map(|(name, value)| (name.as_str(), *value)));
+        if response.status_allows_body()
+            && !is_streaming
+            && !response.headers.contains("Content-Length")
+        {
+            fields.push(("content-length", content_length.as_str()));
+        }
+        let mut block = Vec::new();
+        hpack::encode(fields, &mut block);
+
+        if let (Some(access_log), Some(log)) = (self.access_log, stream.log.take()) {
+            access_log.log(&AccessLogEntry {
+                peer: self.peer,
+                time: stream.time,
+                method: log.method,
+                path: log.path,
+                version: Some(HttpVersion::Http2),
+                status: response.status,
+                bytes_sent: if has_body { response.body.len() } else { 0 },
+                latency: stream.opened.elapsed(),
+                referer: log.referer,
+                user_agent: log.user_agent,
+            });
+        }
+        if has_body {
+            stream.response_body = response.body;
+            stream.body_chunks = body_chunks;
+        }
+        let mut blocks = block.chunks(self.max_frame_size);
+        self.queue(Frame::Headers {
+            stream_id,
+            block: blocks.next().unwrap_or_default().to_vec(),
+            end_stream: !has_body,
+            end_headers: blocks.len() == 0,
+            dependency: None,
+        });
+        while let Some(block) = blocks.next() {
+            self.queue(Frame::Continuation {
+                stream_id,
+                block: block.to_vec(),
+                end_headers: blocks.len() == 0,
+            });
+        }
+        if !has_body {
+            self.finish(stream_id);
+        }
+    }
+
+    /// Take the next chunk of each streamed body whose last chunk has been sent, ending those that are finished
+    ///
+    /// Returns whether any chunks were taken.
+    fn receive_body_chunks(&mut self) -> bool {
+        let mut received = false;
+        let mut failed = Vec::new();
+        let mut finished = Vec::new();
+        for (&stream_id, stream) in &mut self.streams {
+            let Some(body_chunks) = &stream.body_chunks else {
+                continue;
+            };
+            if stream.sent < stream.response_body.len() {
+                continue;
+            }
+            match body_chunks.try_recv() {
+                Ok(Ok(chunk)) => {
+                    stream.response_body = chunk;
+                    stream.sent = 0;
+                    received = true;
+                }
+                Ok(Err(err)) => {
+                    eprintln!(
+                        "Failed to read response body, received error: {}",
+                        err.kind()
+                    );
+                    failed.push(stream_id);
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => finished.push(stream_id),
+            }
+        }
+        for stream_id in failed {
+            self.reset(stream_id, ErrorCode::InternalError);
+        }
+        // everything before the end has been sent, so it's marked by an empty frame
+        for stream_id in finished {
+            self.queue(Frame::Data {
+                stream_id,
+                data: Vec::new(),
+                end_stream: true,
+                padding: 0,
+            });
+            self.finish(stream_id);
+        }
+        received
+    }
+
+    /// Send as much of each response body as the flow control windows allow, taking turns between streams
+    fn send_data(&mut self) {
+        loop {
+            let mut finished = Vec::new();
+            let mut is_sending = false;
+            for (&stream_id, stream) in &mut self.streams {
+                let remaining = stream.response_body.len() - stream.sent;
+                let window = stream.send_window.min(self.send_window);
+                if remaining == 0 || window <= 0 {
+                    continue;
+                }
+                let length = remaining.min(window as usize).min(self.max_frame_size);
+                let data = stream.response_body[stream.sent..stream.sent + length].to_vec();
+                stream.sent += length;
+                stream.send_window -= length as i64;
+                self.send_window -= length as i64;
+                let end_stream =
+                    stream.sent == stream.response_body.len() && stream.body_chunks.is_none();
+                Frame::Data {
+                    stream_id,
+                    data,
+                    end_stream,
+                    padding: 0,
+                }
+                .encode(&mut self.output);
+                is_sending = true;
+                if end_stream {
+                    finished.push(stream_id);
+                }
+            }
+            for stream_id in finished {
+                self.finish(stream_id);
+            }
+            if !is_sending {
+                return;
+            }
+        }
+    }
+
+    /// Close connections that have been idle too long or whose server is stopping, and streams whose body is late
+    fn check_timeouts(&mut self) {
+        let is_idle = self.streams.is_empty() && self.handling == 0;
+        let is_expired =
+            is_idle && self.last_activity.elapsed() >= self.settings.keep_alive_timeout;
+        if !self.goaway_sent && (is_expired || self.shutdown.is_shutting_down()) {
+            self.go_away(ErrorCode::NoError);
+        }
+        let body_timeout = self.settings.body_timeout;
+        let late: Vec<u32> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| {
+                stream.request.is_some() && stream.opened.elapsed() >= body_timeout
+            })
+            .map(|(&stream_id, _)| stream_id)
+            .collect();
+        for stream_id in late {
+            let err = RequestParseError::ConnectionError(ErrorKind::TimedOut);
+            self.reject(stream_id, err);
+        }
+    }
+
+    /// Close a stream whose response has been sent, telling the client to stop sending if it hasn't finished
+    fn finish(&mut self, stream_id: u32) {
+        if let Some(stream) = self.streams.remove(&stream_id) {
+            if stream.receiving {
+                self.queue(Frame::RstStream {
+                    stream_id,
+                    error_code: ErrorCode::NoError.code(),
+                });
+            }
+        }
+    }
+
+    fn reset(&mut self, stream_id: u32, error_code: ErrorCode) {
+        self.streams.remove(&stream_id);
+        self.queue(Frame::RstStream {
+            stream_id,
+            error_code: error_code.code(),
+        });
+    }
+
+    /// Stop accepting new streams, closing the connection once the open ones have finished
+    fn go_away(&mut self, error_code: ErrorCode) {
+        if !self.goaway_sent {
+            self.goaway_sent = true;
+            self.queue(Frame::GoAway {
+                last_stream_id: self.last_stream_id,
+                error_code: error_code.code(),
+            });
+        }
+    }
+
+    /// Let the client send more data on the stream, or the connection for stream 0
+    fn open_window(&mut self, stream_id: u32, increment: usize) {
+        if increment > 0 {
+            self.queue(Frame::WindowUpdate {
+                stream_id,
+                increment: increment as u32,
+            });
+        }
+    }
+
+    fn queue(&mut self, frame: Frame) {
+        frame.encode(&mut self.output);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.output.is_empty() {
+            self.connection.write_all(&self.output)?;
+            self.connection.flush()?;
+            self.output.clear();
+        }
+        Ok(())
+    }
+}
+
+impl RequestLog {
+    fn new(request: Option<&HttpRequest>) -> Self {
+        RequestLog {
+            method: request.map(HttpRequest::method),
+            path: request.map(|request| request.raw_target().to_string()),
+            referer: request.and_then(|request| request.header("Referer").map(String::from)),
+            user_agent: request.and_then(|request| request.header("User-Agent").map(String::from)),
+        }
+    }
+}
+
+impl Stream {
+    fn new(receiving: bool, send_window: i64) -> Self {
+        Stream {
+            receiving,
+            request: None,
+            body: Vec::new(),
+            content_length: None,
+            is_head: false,
+            opened: Instant::now(),
+            time: SystemTime::now(),
+            log: None,
+            send_window,
+            response_body: Vec::new(),
+            sent: 0,
+            body_chunks: None,
+        }
+    }
+}
+
+/// Run the handler, sending back its response, then any streamed body a chunk at a time
+fn respond_on(
+    sender: &mpsc::Sender<Event>,
+    stream_id: u32,
+    handler: &impl Handler,
+    request: HttpRequest,
+) {
+    let mut response = handle_request(handler, request);
+    let Some(mut body) = response.stream.take() else {
+        let _ = sender.send(Event::Response(stream_id, response, None));
+        return;
+    };
+    // chunks are produced as the connection sends them, so a slow client holds up the body rather than filling memory
+    let (chunk_sender, body_chunks) = mpsc::sync_channel(BUFFERED_CHUNKS);
+    if sender
+        .send(Event::Response(stream_id, response, Some(body_chunks)))
+        .is_err()
+    {
+        return;
+    }
+    while let Some(chunk) = body.next_chunk() {
+        let failed = chunk.is_err();
+        if chunk_sender.send(chunk).is_err() || sender.send(Event::BodyChunk).is_err() || failed {
+            return;
+        }
+    }
+    // the connection wakes to find the body has ended
+    drop(chunk_sender);
+    let _ = sender.send(Event::BodyChunk);
+}
+
+/// Wake the connection each time the client sends something, then wait for it to be read before watching again
+///
+/// Peeking leaves what was sent in the socket, for the connection to read itself.
+fn watch(socket: &TcpStream, events: &mpsc::Sender<Event>, read: &mpsc::Receiver<()>) {
+    loop {
+        match socket.peek(&mut [0]) {
+            Err(err) if is_timeout(err.kind()) => match read.try_recv() {
+                Err(mpsc::TryRecvError::Disconnected) => return,
+                _ => continue,
+            },
+            // what's been sent, the end of the connection, and errors are all for the connection to read
+            _ => {}
+        }
+        if events.send(Event::Readable).is_err() || read.recv().is_err() {
+            return;
+        }
+    }
+}
+
+/// Turn a request's header fields into a request, checking them against the rules of RFC 9113 section 8.2
+fn build_request(fields: Vec<HeaderField>) -> Result<HttpRequest, RequestError> {
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut headers = HttpHeaders::new();
+    let mut cookies = Vec::new();
+    for (name, value) in fields {
+        let (name, value) = header(name, value).ok_or(RequestError::Malformed)?;
+        if let Some(pseudo_header) = name.strip_prefix(':') {
+            // pseudo-headers come first, and only once each
+            let field = match pseudo_header {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err(RequestError::Malformed),
+            };
+            if !headers.is_empty() || !cookies.is_empty() || field.replace(value).is_some() {
+                return Err(RequestError::Malformed);
+            }
+            continue;
+        }
+        match name.as_str() {
+            _ if is_connection_specific(&name) => return Err(RequestError::Malformed),
+            "te" if value != "trailers" => return Err(RequestError::Malformed),
+            // cookies can be split into separate fields, to compress better (RFC 9113 section 8.2.3)
+            "cookie" => cookies.push(value),
+            _ => headers.append(&name, &value),
+        }
+    }
+    let (Some(method), Some(_), Some(path)) = (method, scheme, path) else {
+        return Err(RequestError::Malformed);
+    };
+    if !cookies.is_empty() {
+        headers.append("cookie", &cookies.join("; "));
+    }
+    if let Some(authority) = authority.filter(|_| !headers.contains("Host")) {
+        headers.append("host", &authority);
+    }
+    HttpRequest::from_parts(&method, &path, HttpVersion::Http2, headers)
+        .map_err(|err| RequestError::Invalid(RequestParseError::InvalidStartLine(err)))
+}
+
+/// A header field as text, or None if it isn't valid in HTTP/2, e.g. because its name isn't lower case
+fn header(name: Vec<u8>, value: Vec<u8>) -> Option<(String, String)> {
+    let name_start = usize::from(name.first() == Some(&b':'));
+    let is_valid_name = name.len() > name_start
+        && name[name_start..]
+            .iter()
+            .all(|&byte| is_token_char(byte) && !byte.is_ascii_uppercase());
+    let is_valid_value = !value.iter().any(|byte| b"\0\r\n".contains(byte));
+    if !is_valid_name || !is_valid_value {
+        return None;
+    }
+    Some((
+        String::from_utf8(name).ok()?,
+        String::from_utf8(value).ok()?,
+    ))
+}
+
+/// Headers for managing HTTP/1 connections, which HTTP/2 doesn't allow (RFC 9113 section 8.2.2)
+fn is_connection_specific(name: &str) -> bool {
+    matches!(
+        name,
+        "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Server;
+    use crate::thread_pool::{PoolConfig, RejectionPolicy};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    /// A client speaking HTTP/2 frame by frame
+    struct Client {
+        stream: TcpStream,
+        input: Vec<u8>,
+        decoder: Decoder,
+    }
+
+    /// A response collected from the frames of a stream
+    #[derive(Debug)]
+    struct Response {
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    fn respond(request: HttpRequest) -> HttpResponse {
+        if request.path() == "/slow" {
+            thread::sleep(Duration::from_millis(300));
+        }
+        if request.path() == "/stream" {
+            return HttpResponse::ok().body_chunks(["Hello", ", world"]);
+        }
+        let body = match request.path().as_str() {
+            "/echo" => request.body().to_vec(),
+            "/large" => vec![b'a'; 25],
+            path => path.as_bytes().to_vec(),
+        };
+        HttpResponse::ok()
+            .header("Content-Type", "text/plain")
+            .header("Connection", "keep-alive")
+            .body(body)
+    }
+
+    /// Start a server, returning its address and a handle to stop it
+    fn start_server() -> (SocketAddr, ShutdownHandle) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(listener, respond)
+            .without_access_log()
+            .with_max_body_size(16);
+        let shutdown = server.shutdown_handle();
+        thread::spawn(move || server.serve());
+        (address, shutdown)
+    }
+
+    impl Client {
+        /// Connect with prior knowledge, sending the preface and settings
+        fn connect(address: SocketAddr, settings: Vec<(u16, u32)>) -> Client {
+            let stream = TcpStream::connect(address).unwrap();
+            let mut client = Client::from_stream(stream);
+            client.stream.write_all(PREFACE).unwrap();
+            client.send(Frame::Settings {
+                ack: false,
+                settings,
+            });
+            client.expect_settings();
+            client
+        }
+
+        fn from_stream(stream: TcpStream) -> Client {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            Client {
+                stream,
+                input: Vec::new(),
+                decoder: Decoder::new(HEADER_TABLE_SIZE),
+            }
+        }
+
+        /// Receive the server's settings, and its acknowledgement of the client's
+        fn expect_settings(&mut self) {
+            assert!(matches!(self.receive(), Frame::Settings { ack: false, .. }));
+            assert_eq!(
+                self.receive(),
+                Frame::Settings {
+                    ack: true,
+                    settings: Vec::new()
+                }
+            );
+        }
+
+        fn send(&mut self, frame: Frame) {
+            let mut output = Vec::new();
+            frame.encode(&mut output);
+            self.stream.write_all(&output).unwrap();
+        }
+
+        fn request(&mut self, stream_id: u32, method: &str, path: &str, end_stream: bool) {
+            let mut block = Vec::new();
+            let fields = [
+                (":method", method),
+                (":scheme", "http"),
+                (":path", path),
+                (":authority", "localhost"),
+            ];
+            hpack::encode(fields, &mut block);
+            self.send(Frame::Headers {
+                stream_id,
+                block,
+                end_stream,
+                end_headers: true,
+                dependency: None,
+            });
+        }
+
+        fn receive(&mut self) -> Frame {
+            loop {
+                if let Some((frame, length)) =
+                    Frame::parse(&self.input, MAX_FRAME_SIZE_LIMIT).unwrap()
+                {
+                    self.input.drain(..length);
+                    return frame;
+                }
+                let mut buffer = [0; 4096];
+                let length = self.stream.read(&mut buffer).unwrap();
+                assert!(length > 0, "The server closed the connection");
+                self.input.extend_from_slice(&buffer[..length]);
+            }
+        }
+
+        /// Receive frames until a response is complete, returning its stream and the response
+        fn response(&mut self) -> (u32, Response) {
+            let mut responses = BTreeMap::new();
+            loop {
+                let (stream_id, end_stream) = match self.receive() {
+                    Frame::Headers {
+                        stream_id,
+                        block,
+                        end_stream,
+                        end_headers,
+                        ..
+                    } => {
+                        assert!(end_headers);
+                        let headers = self
+                            .decoder
+                            .decode(&block, usize::MAX)
+                            .unwrap()
+                            .into_iter()
+                            .map(|(name, value)| {
+                                let name = String::from_utf8(name).unwrap();
+                                (name, String::from_utf8(value).unwrap())
+                            })
+                            .collect();
+                        let response = Response {
+                            headers,
+                            body: Vec::new(),
+                        };
+                        responses.insert(stream_id, response);
+                        (stream_id, end_stream)
+                    }
+                    Frame::Data {
+                        stream_id,
+                        data,
+                        end_stream,
+                        ..
+                    } => {
+                        let response: &mut Response = responses.get_mut(&stream_id).unwrap();
+                        response.body.extend_from_slice(&data);
+                        (stream_id, end_stream)
+                    }
+                    Frame::WindowUpdate { .. } | Frame::Settings { ack: true, .. } => continue,
+                    frame => panic!("Unexpected frame {frame:?}"),
+                };
+                if end_stream {
+                    return (stream_id, responses.remove(&stream_id).unwrap());
+                }
+            }
+        }
+    }
+
+    impl Response {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(existing, _)| existing == name)
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    #[test]
+    fn test_prior_knowledge() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, Vec::new());
+        client.request(1, "GET", "/hello?name=h2", true);
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 1);
+        assert_eq!(
+            response.headers,
+            vec![
+                (":status".to_string(), "200".to_string()),
+                ("content-type".to_string(), "text/plain".to_string()),
+                ("content-length".to_string(), "6".to_string()),
+            ]
+        );
+        assert_eq!(response.body, b"/hello");
+
+        // a HEAD response has headers, but no data
+        client.request(3, "HEAD", "/head", true);
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 3);
+        assert_eq!(response.header("content-length"), Some("5"));
+        assert!(response.body.is_empty());
+
+        client.send(Frame::Ping {
+            ack: false,
+            payload: *b"12345678",
+        });
+        assert_eq!(
+            client.receive(),
+            Frame::Ping {
+                ack: true,
+                payload: *b"12345678"
+            }
+        );
+
+        // shutting down tells the client which streams were handled
+        shutdown.shutdown();
+        assert_eq!(
+            client.receive(),
+            Frame::GoAway {
+                last_stream_id: 3,
+                error_code: ErrorCode::NoError.code()
+            }
+        );
+    }
+
+    #[test]
+    fn test_multiplexing() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, Vec::new());
+        client.request(1, "GET", "/slow", true);
+        client.request(3, "POST", "/echo", false);
+        client.send(Frame::Data {
+            stream_id: 3,
+            data: b"body".to_vec(),
+            end_stream: true,
+            padding: 3,
+        });
+        // the slow response doesn't hold up the one after it
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 3);
+        assert_eq!(response.body, b"body");
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 1);
+        assert_eq!(response.body, b"/slow");
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_reset_streams_count_until_handled() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, Vec::new());
+        // resetting a stream doesn't stop its handler, so it still counts against the limit
+        for stream_id in (1..).step_by(2).take(MAX_CONCURRENT_STREAMS) {
+            client.request(stream_id, "GET", "/slow", true);
+            client.send(Frame::RstStream {
+                stream_id,
+                error_code: ErrorCode::NoError.code(),
+            });
+        }
+        let stream_id = 2 * MAX_CONCURRENT_STREAMS as u32 + 1;
+        client.request(stream_id, "GET", "/refused", true);
+        assert_eq!(
+            client.receive(),
+            Frame::RstStream {
+                stream_id,
+                error_code: ErrorCode::RefusedStream.code()
+            }
+        );
+        // once the handlers finish, new streams are accepted again
+        thread::sleep(Duration::from_millis(500));
+        client.request(stream_id + 2, "GET", "/after", true);
+        assert_eq!(client.response().1.body, b"/after");
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_handlers_share_the_pool() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // one thread serves the connection, leaving one to run handlers and room for one more to wait
+        let config = PoolConfig::new(2).with_queue(1, RejectionPolicy::Error);
+        let server = Server::with_pool_config(listener, respond, config).without_access_log();
+        let shutdown = server.shutdown_handle();
+        thread::spawn(move || server.serve());
+
+        let mut client = Client::connect(address, Vec::new());
+        client.request(1, "GET", "/slow", true);
+        thread::sleep(Duration::from_millis(100));
+        client.request(3, "GET", "/slow", true);
+        client.request(5, "GET", "/busy", true);
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 5);
+        assert_eq!(response.header(":status"), Some("503"));
+        assert_eq!(response.header("retry-after"), Some("1"));
+        assert_eq!(client.response().0, 1);
+        assert_eq!(client.response().0, 3);
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_streamed_body() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, Vec::new());
+        client.request(1, "GET", "/stream", true);
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 1);
+        // the length isn't known up front, so the body ends with the stream
+        assert_eq!(response.header("content-length"), None);
+        assert_eq!(response.body, b"Hello, world");
+
+        client.request(3, "HEAD", "/stream", true);
+        let (_, response) = client.response();
+        assert!(response.body.is_empty());
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, vec![(SETTINGS_INITIAL_WINDOW_SIZE, 10)]);
+        client.request(1, "GET", "/large", true);
+        assert!(matches!(client.receive(), Frame::Headers { .. }));
+        assert_eq!(
+            client.receive(),
+            Frame::Data {
+                stream_id: 1,
+                data: vec![b'a'; 10],
+                end_stream: false,
+                padding: 0
+            }
+        );
+        // nothing more is sent until the window opens, so the ping is answered first
+        client.send(Frame::Ping {
+            ack: false,
+            payload: [0; 8],
+        });
+        assert!(matches!(client.receive(), Frame::Ping { ack: true, .. }));
+        client.send(Frame::WindowUpdate {
+            stream_id: 1,
+            increment: 100,
+        });
+        assert_eq!(
+            client.receive(),
+            Frame::Data {
+                stream_id: 1,
+                data: vec![b'a'; 15],
+                end_stream: true,
+                padding: 0
+            }
+        );
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let (address, shutdown) = start_server();
+        let mut stream = TcpStream::connect(address).unwrap();
+        // the settings are SETTINGS_MAX_CONCURRENT_STREAMS = 100
+        stream
+            .write_all(
+                b"GET /upgraded HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+                  Upgrade: h2c\r\nHTTP2-Settings: AAMAAABk\r\n\r\n",
+            )
+            .unwrap();
+        let switching =
+            b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+        let mut head = vec![0; switching.len()];
+        stream.read_exact(&mut head).unwrap();
+        assert_eq!(head, switching);
+
+        let mut client = Client::from_stream(stream);
+        client.stream.write_all(PREFACE).unwrap();
+        client.send(Frame::Settings {
+            ack: false,
+            settings: Vec::new(),
+        });
+        // the upgraded request may be answered before the client's settings are acknowledged
+        assert!(matches!(
+            client.receive(),
+            Frame::Settings { ack: false, .. }
+        ));
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 1);
+        assert_eq!(response.body, b"/upgraded");
+        client.request(3, "GET", "/next", true);
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 3);
+        assert_eq!(response.body, b"/next");
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, Vec::new());
+        // header names must be lowercase
+        let mut block = Vec::new();
+        let fields = [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            ("Accept", "*/*"),
+        ];
+        hpack::encode(fields, &mut block);
+        client.send(Frame::Headers {
+            stream_id: 1,
+            block,
+            end_stream: true,
+            end_headers: true,
+            dependency: None,
+        });
+        assert_eq!(
+            client.receive(),
+            Frame::RstStream {
+                stream_id: 1,
+                error_code: ErrorCode::ProtocolError.code()
+            }
+        );
+
+        // a body over the limit is refused, leaving the connection usable
+        client.request(3, "POST", "/echo", false);
+        client.send(Frame::Data {
+            stream_id: 3,
+            data: vec![0; 20],
+            end_stream: true,
+            padding: 0,
+        });
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 3);
+        assert_eq!(response.header(":status"), Some("413"));
+        client.request(5, "GET", "/after", true);
+        assert_eq!(client.response().1.body, b"/after");
+
+        // a small block can repeat a table entry into a header list over the limit
+        let mut block = Vec::new();
+        let fields = [(":method", "GET"), (":scheme", "http"), (":path", "/")];
+        hpack::encode(fields, &mut block);
+        block.extend([0x40, 0x01, b'x', 100]);
+        block.extend([b'a'; 100]);
+        block.extend([0xbe; 300]);
+        client.send(Frame::Headers {
+            stream_id: 7,
+            block,
+            end_stream: true,
+            end_headers: true,
+            dependency: None,
+        });
+        let (stream_id, response) = client.response();
+        assert_eq!(stream_id, 7);
+        assert_eq!(response.header(":status"), Some("431"));
+        client.request(9, "GET", "/after", true);
+        assert_eq!(client.response().1.body, b"/after");
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_connection_error() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, Vec::new());
+        client.send(Frame::Data {
+            stream_id: 0,
+            data: b"data".to_vec(),
+            end_stream: false,
+            padding: 0,
+        });
+        assert_eq!(
+            client.receive(),
+            Frame::GoAway {
+                last_stream_id: 0,
+                error_code: ErrorCode::ProtocolError.code()
+            }
+        );
+        let mut buffer = [0; 16];
+        assert_eq!(client.stream.read(&mut buffer).unwrap(), 0);
+        shutdown.shutdown();
+    }
+}
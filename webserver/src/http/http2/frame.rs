@@ -0,0 +1,452 @@
+/// The size of the header every frame starts with
+const HEADER_LENGTH: usize = 9;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+/// Stream identifiers and window increments are 31 bits, with the top bit reserved
+const U31_MASK: u32 = 0x7fff_ffff;
+
+/// A frame of an HTTP/2 connection (RFC 9113 section 6)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Frame {
+    Data {
+        stream_id: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        /// Bytes of padding, including the byte giving its length, which count toward flow control
+        padding: usize,
+    },
+    Headers {
+        stream_id: u32,
+        block: Vec<u8>,
+        end_stream: bool,
+        end_headers: bool,
+        /// The stream this one depends on, if the frame carries priority information
+        dependency: Option<u32>,
+    },
+    Priority {
+        stream_id: u32,
+        dependency: u32,
+    },
+    RstStream {
+        stream_id: u32,
+        error_code: u32,
+    },
+    Settings {
+        ack: bool,
+        settings: Vec<(u16, u32)>,
+    },
+    Ping {
+        ack: bool,
+        payload: [u8; 8],
+    },
+    GoAway {
+        last_stream_id: u32,
+        error_code: u32,
+    },
+    WindowUpdate {
+        stream_id: u32,
+        increment: u32,
+    },
+    Continuation {
+        stream_id: u32,
+        block: Vec<u8>,
+        end_headers: bool,
+    },
+    /// A frame of an extension, which is ignored
+    Unknown {
+        frame_type: u8,
+        stream_id: u32,
+    },
+}
+
+/// Why a stream or the connection was closed, sent in RST_STREAM and GOAWAY frames (RFC 9113 section 7)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    CompressionError = 0x9,
+    EnhanceYourCalm = 0xb,
+}
+
+impl Frame {
+    /// Parse the frame at the start of the input, returning it and its length or None if it hasn't all arrived
+    ///
+    /// Frames that break the rules of their type are errors for the whole connection.
+    pub fn parse(input: &[u8], max_frame_size: usize) -> Result<Option<(Frame, usize)>, ErrorCode> {
+        let Some(header) = input.get(..HEADER_LENGTH) else {
+            return Ok(None);
+        };
+        let length =
+            usize::from(header[0]) << 16 | usize::from(header[1]) << 8 | usize::from(header[2]);
+        let (frame_type, flags) = (header[3], header[4]);
+        let stream_id = read_u32(&header[5..]) & U31_MASK;
+        if length > max_frame_size {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        let Some(payload) = input.get(HEADER_LENGTH..HEADER_LENGTH + length) else {
+            return Ok(None);
+        };
+        let is_connection_frame = matches!(frame_type, SETTINGS | PING | GOAWAY);
+        let allows_stream_zero = is_connection_frame || frame_type == WINDOW_UPDATE;
+        if (stream_id == 0 && !allows_stream_zero) || (stream_id != 0 && is_connection_frame) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        let frame = match frame_type {
+            DATA => {
+                let data = strip_padding(payload, flags)?;
+                Frame::Data {
+                    stream_id,
+                    padding: payload.len() - data.len(),
+                    data: data.to_vec(),
+                    end_stream: flags & END_STREAM != 0,
+                }
+            }
+            HEADERS => {
+                let mut block = strip_padding(payload, flags)?;
+                let mut dependency = None;
+                if flags & PRIORITY_FLAG != 0 {
+                    // the dependency is followed by a weight, which is ignored
+                    let priority = block.get(..5).ok_or(ErrorCode::FrameSizeError)?;
+                    dependency = Some(read_u32(priority) & U31_MASK);
+                    block = &block[5..];
+                }
+                Frame::Headers {
+                    stream_id,
+                    block: block.to_vec(),
+                    end_stream: flags & END_STREAM != 0,
+                    end_headers: flags & END_HEADERS != 0,
+                    dependency,
+                }
+            }
+            PRIORITY if length == 5 => Frame::Priority {
+                stream_id,
+                dependency: read_u32(payload) & U31_MASK,
+            },
+            RST_STREAM if length == 4 => Frame::RstStream {
+                stream_id,
+                error_code: read_u32(payload),
+            },
+            SETTINGS if flags & ACK == 0 || length == 0 => Frame::Settings {
+                ack: flags & ACK != 0,
+                settings: parse_settings(payload).ok_or(ErrorCode::FrameSizeError)?,
+            },
+            // clients can't push streams to servers
+            PUSH_PROMISE => return Err(ErrorCode::ProtocolError),
+            PING if length == 8 => Frame::Ping {
+                ack: flags & ACK != 0,
+                payload: payload.try_into().expect("The payload should be 8 bytes"),
+            },
+            GOAWAY if length >= 8 => Frame::GoAway {
+                last_stream_id: read_u32(payload) & U31_MASK,
+                error_code: read_u32(&payload[4..]),
+            },
+            WINDOW_UPDATE if length == 4 => Frame::WindowUpdate {
+                stream_id,
+                increment: read_u32(payload) & U31_MASK,
+            },
+            CONTINUATION => Frame::Continuation {
+                stream_id,
+                block: payload.to_vec(),
+                end_headers: flags & END_HEADERS != 0,
+            },
+            PRIORITY | RST_STREAM | SETTINGS | PING | GOAWAY | WINDOW_UPDATE => {
+                return Err(ErrorCode::FrameSizeError)
+            }
+            _ => Frame::Unknown {
+                frame_type,
+                stream_id,
+            },
+        };
+        Ok(Some((frame, HEADER_LENGTH + length)))
+    }
+
+    /// Append the frame to the output, which must fit within the peer's maximum frame size
+    pub fn encode(&self, output: &mut Vec<u8>) {
+        let mut payload = Vec::new();
+        let (frame_type, flags, stream_id) = match self {
+            Frame::Data {
+                stream_id,
+                data,
+                end_stream,
+                padding,
+            } => {
+                let mut flags = flag(*end_stream, END_STREAM);
+                if *padding > 0 {
+                    flags |= PADDED;
+                    payload.push((padding - 1) as u8);
+                }
+                payload.extend_from_slice(data);
+                payload.resize(payload.len() + padding.saturating_sub(1), 0);
+                (DATA, flags, *stream_id)
+            }
+            Frame::Headers {
+                stream_id,
+                block,
+                end_stream,
+                end_headers,
+                dependency,
+            } => {
+                let mut flags = flag(*end_stream, END_STREAM) | flag(*end_headers, END_HEADERS);
+                if let Some(dependency) = dependency {
+                    flags |= PRIORITY_FLAG;
+                    payload.extend_from_slice(&dependency.to_be_bytes());
+                    // the default weight, 16
+                    payload.push(15);
+                }
+                payload.extend_from_slice(block);
+                (HEADERS, flags, *stream_id)
+            }
+            Frame::Priority {
+                stream_id,
+                dependency,
+            } => {
+                payload.extend_from_slice(&dependency.to_be_bytes());
+                payload.push(15);
+                (PRIORITY, 0, *stream_id)
+            }
+            Frame::RstStream {
+                stream_id,
+                error_code,
+            } => {
+                payload.extend_from_slice(&error_code.to_be_bytes());
+                (RST_STREAM, 0, *stream_id)
+            }
+            Frame::Settings { ack, settings } => {
+                for (id, value) in settings {
+                    payload.extend_from_slice(&id.to_be_bytes());
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+                (SETTINGS, flag(*ack, ACK), 0)
+            }
+            Frame::Ping { ack, payload: data } => {
+                payload.extend_from_slice(data);
+                (PING, flag(*ack, ACK), 0)
+            }
+            Frame::GoAway {
+                last_stream_id,
+                error_code,
+            } => {
+                payload.extend_from_slice(&last_stream_id.to_be_bytes());
+                payload.extend_from_slice(&error_code.to_be_bytes());
+                (GOAWAY, 0, 0)
+            }
+            Frame::WindowUpdate {
+                stream_id,
+                increment,
+            } => {
+                payload.extend_from_slice(&increment.to_be_bytes());
+                (WINDOW_UPDATE, 0, *stream_id)
+            }
+            Frame::Continuation {
+                stream_id,
+                block,
+                end_headers,
+            } => {
+                payload.extend_from_slice(block);
+                (CONTINUATION, flag(*end_headers, END_HEADERS), *stream_id)
+            }
+            Frame::Unknown {
+                frame_type,
+                stream_id,
+            } => (*frame_type, 0, *stream_id),
+        };
+        output.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        output.extend_from_slice(&[frame_type, flags]);
+        output.extend_from_slice(&stream_id.to_be_bytes());
+        output.extend_from_slice(&payload);
+    }
+}
+
+impl ErrorCode {
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Parse the identifiers and values of a SETTINGS payload, returning None if it isn't a whole number of settings
+pub(super) fn parse_settings(payload: &[u8]) -> Option<Vec<(u16, u32)>> {
+    if !payload.len().is_multiple_of(6) {
+        return None;
+    }
+    let settings = payload
+        .chunks(6)
+        .map(|setting| {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            (id, read_u32(&setting[2..]))
+        })
+        .collect();
+    Some(settings)
+}
+
+fn flag(is_set: bool, flag: u8) -> u8 {
+    if is_set {
+        flag
+    } else {
+        0
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The payload of a frame without its padding, if the PADDED flag is set
+fn strip_padding(payload: &[u8], flags: u8) -> Result<&[u8], ErrorCode> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let (&pad_length, rest) = payload.split_first().ok_or(ErrorCode::FrameSizeError)?;
+    // the padding can't take up the whole frame
+    rest.len()
+        .checked_sub(usize::from(pad_length))
+        .map(|length| &rest[..length])
+        .ok_or(ErrorCode::ProtocolError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_FRAME_SIZE: usize = 16_384;
+
+    #[test]
+    fn test_round_trip() {
+        let frames = [
+            Frame::Data {
+                stream_id: 1,
+                data: b"hello".to_vec(),
+                end_stream: true,
+                padding: 4,
+            },
+            Frame::Headers {
+                stream_id: 3,
+                block: vec![0x82, 0x84],
+                end_stream: false,
+                end_headers: true,
+                dependency: Some(1),
+            },
+            Frame::RstStream {
+                stream_id: 5,
+                error_code: ErrorCode::RefusedStream.code(),
+            },
+            Frame::Settings {
+                ack: false,
+                settings: vec![(0x3, 100), (0x4, 65_535)],
+            },
+            Frame::Ping {
+                ack: true,
+                payload: *b"pingpong",
+            },
+            Frame::GoAway {
+                last_stream_id: 7,
+                error_code: ErrorCode::NoError.code(),
+            },
+            Frame::WindowUpdate {
+                stream_id: 0,
+                increment: 1 << 20,
+            },
+            Frame::Continuation {
+                stream_id: 3,
+                block: vec![0x86],
+                end_headers: true,
+            },
+            Frame::Unknown {
+                frame_type: 0xfa,
+                stream_id: 9,
+            },
+        ];
+        let mut encoded = Vec::new();
+        for frame in &frames {
+            frame.encode(&mut encoded);
+        }
+        let mut input = encoded.as_slice();
+        for frame in frames {
+            let (parsed, length) = Frame::parse(input, MAX_FRAME_SIZE).unwrap().unwrap();
+            assert_eq!(parsed, frame);
+            input = &input[length..];
+        }
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let mut encoded = Vec::new();
+        Frame::Ping {
+            ack: false,
+            payload: [0; 8],
+        }
+        .encode(&mut encoded);
+        for length in 0..encoded.len() {
+            assert_eq!(Frame::parse(&encoded[..length], MAX_FRAME_SIZE), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        let frame = |length: u32, frame_type: u8, flags: u8, stream_id: u32, payload: &[u8]| {
+            let mut frame = length.to_be_bytes()[1..].to_vec();
+            frame.extend_from_slice(&[frame_type, flags]);
+            frame.extend_from_slice(&stream_id.to_be_bytes());
+            frame.extend_from_slice(payload);
+            Frame::parse(&frame, MAX_FRAME_SIZE)
+        };
+        let too_large = vec![0; MAX_FRAME_SIZE + 1];
+        assert_eq!(
+            frame(too_large.len() as u32, DATA, 0, 1, &too_large),
+            Err(ErrorCode::FrameSizeError)
+        );
+        assert_eq!(frame(1, DATA, 0, 0, b"a"), Err(ErrorCode::ProtocolError));
+        assert_eq!(frame(8, PING, 0, 1, &[0; 8]), Err(ErrorCode::ProtocolError));
+        assert_eq!(
+            frame(4, PING, 0, 0, &[0; 4]),
+            Err(ErrorCode::FrameSizeError)
+        );
+        assert_eq!(
+            frame(6, SETTINGS, ACK, 0, &[0; 6]),
+            Err(ErrorCode::FrameSizeError)
+        );
+        assert_eq!(
+            frame(5, SETTINGS, 0, 0, &[0; 5]),
+            Err(ErrorCode::FrameSizeError)
+        );
+        // padding longer than the rest of the frame
+        assert_eq!(
+            frame(3, DATA, PADDED, 1, &[3, 0, 0]),
+            Err(ErrorCode::ProtocolError)
+        );
+        assert_eq!(
+            frame(0, PUSH_PROMISE, 0, 1, &[]),
+            Err(ErrorCode::ProtocolError)
+        );
+        assert_eq!(
+            frame(3, DATA, PADDED, 1, &[1, b'a', 0]).unwrap().unwrap().0,
+            Frame::Data {
+                stream_id: 1,
+                data: b"a".to_vec(),
+                end_stream: false,
+                padding: 2,
+            }
+        );
+    }
+}
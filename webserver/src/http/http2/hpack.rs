@@ -0,0 +1,513 @@
+use super::huffman;
+use std::collections::VecDeque;
+
+/// A header field's name and value, as bytes since HPACK doesn't require them to be text
+pub(super) type HeaderField = (Vec<u8>, Vec<u8>);
+
+/// Why a header block couldn't be decoded, which leaves the connection's compression state unknown
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum HpackError {
+    Truncated,
+    IntegerOverflow,
+    InvalidIndex(usize),
+    InvalidHuffman,
+    /// A table size update was larger than the limit the server set, or came after a header field
+    InvalidTableSizeUpdate(usize),
+    /// The decoded fields were larger than the limit, though the block was still decoded to keep the table in step
+    HeaderListTooLarge,
+}
+
+/// Decodes header blocks, keeping the dynamic table shared by every block on the connection
+#[derive(Debug)]
+pub(super) struct Decoder {
+    table: VecDeque<HeaderField>,
+    table_size: usize,
+    max_table_size: usize,
+    /// The largest table the encoder is allowed to ask for
+    table_size_limit: usize,
+}
+
+/// The static table (RFC 7541 appendix A), where index 1 is the first entry
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The size each dynamic table entry counts for on top of its name and value (RFC 7541 section 4.1)
+const ENTRY_OVERHEAD: usize = 32;
+
+impl Decoder {
+    /// Create a decoder whose dynamic table can grow to at most `table_size_limit` bytes
+    pub fn new(table_size_limit: usize) -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            table_size: 0,
+            max_table_size: table_size_limit,
+            table_size_limit,
+        }
+    }
+
+    /// Decode a complete header block, in the order the fields were sent
+    ///
+    /// Once the fields add up to more than `max_list_size`, counted as in `SETTINGS_MAX_HEADER_LIST_SIZE`,
+    /// the rest of the block is only decoded to update the dynamic table, and the fields are dropped.
+    pub fn decode(
+        &mut self,
+        block: &[u8],
+        max_list_size: usize,
+    ) -> Result<Vec<HeaderField>, HpackError> {
+        let mut input = block;
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        let mut has_fields = false;
+        while let Some(&first) = input.first() {
+            let field = if first & 0x80 != 0 {
+                // indexed field
+                let index = decode_integer(&mut input, 7)?;
+                self.entry(index)?
+            } else if first & 0x40 != 0 {
+                // literal field added to the dynamic table
+                let field = self.decode_literal(&mut input, 6)?;
+                self.insert(field.clone());
+                field
+            } else if first & 0x20 != 0 {
+                let size = decode_integer(&mut input, 5)?;
+                // size updates must come before the block's first field (RFC 7541 section 4.2)
+                if size > self.table_size_limit || has_fields {
+                    return Err(HpackError::InvalidTableSizeUpdate(size));
+                }
+                self.max_table_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // literal field that isn't indexed, or must never be
+                self.decode_literal(&mut input, 4)?
+            };
+            has_fields = true;
+            // indexed fields can repeat a large entry many times, so the list can be far larger than the block
+            list_size = entry_size(&field).saturating_add(list_size);
+            if list_size > max_list_size {
+                fields = Vec::new();
+            } else {
+                fields.push(field);
+            }
+        }
+        if list_size > max_list_size {
+            return Err(HpackError::HeaderListTooLarge);
+        }
+        Ok(fields)
+    }
+
+    fn decode_literal(
+        &self,
+        input: &mut &[u8],
+        prefix_bits: u8,
+    ) -> Result<HeaderField, HpackError> {
+        let name = match decode_integer(input, prefix_bits)? {
+            0 => decode_string(input)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, decode_string(input)?))
+    }
+
+    /// The entry at the index, counting through the static table and then the dynamic table, newest first
+    fn entry(&self, index: usize) -> Result<HeaderField, HpackError> {
+        match index {
+            0 => Err(HpackError::InvalidIndex(index)),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            _ => self
+                .table
+                .get(index - STATIC_TABLE.len() - 1)
+                .cloned()
+                .ok_or(HpackError::InvalidIndex(index)),
+        }
+    }
+
+    fn insert(&mut self, field: HeaderField) {
+        let size = entry_size(&field);
+        // an entry larger than the whole table empties it instead
+        self.evict(size);
+        if size <= self.max_table_size {
+            self.table_size += size;
+            self.table.push_front(field);
+        }
+    }
+
+    /// Evict the oldest entries until there's room for an entry of the given size
+    fn evict(&mut self, size: usize) {
+        while self.table_size + size > self.max_table_size {
+            match self.table.pop_back() {
+                Some(field) => self.table_size -= entry_size(&field),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Encode the fields as literals that aren't indexed, so the peer's dynamic table is never used
+///
+/// Fields in the static table are referred to by index, and strings are Huffman coded when that's shorter.
+pub(super) fn encode<'a>(
+    fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    output: &mut Vec<u8>,
+) {
+    for (name, value) in fields {
+        let name_index = STATIC_TABLE
+            .iter()
+            .position(|&(static_name, _)| static_name == name);
+        let field_index = STATIC_TABLE
+            .iter()
+            .position(|&entry| entry == (name, value));
+        match (field_index, name_index) {
+            (Some(index), _) => encode_integer(index + 1, 7, 0x80, output),
+            (None, Some(index)) => {
+                encode_integer(index + 1, 4, 0x00, output);
+                encode_string(value.as_bytes(), output);
+            }
+            (None, None) => {
+                output.push(0x00);
+                encode_string(name.as_bytes(), output);
+                encode_string(value.as_bytes(), output);
+            }
+        }
+    }
+}
+
+fn entry_size((name, value): &HeaderField) -> usize {
+    name.len() + value.len() + ENTRY_OVERHEAD
+}
+
+/// Decode an integer whose first byte has `prefix_bits` bits available (RFC 7541 section 5.1)
+fn decode_integer(input: &mut &[u8], prefix_bits: u8) -> Result<usize, HpackError> {
+    let (&first, rest) = input.split_first().ok_or(HpackError::Truncated)?;
+    *input = rest;
+    let max_prefix = u8::MAX >> (8 - prefix_bits);
+    let mut value = usize::from(first & max_prefix);
+    if value < usize::from(max_prefix) {
+        return Ok(value);
+    }
+    // larger values continue in 7 bit groups, least significant first
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first().ok_or(HpackError::Truncated)?;
+        *input = rest;
+        if shift > 28 {
+            return Err(HpackError::IntegerOverflow);
+        }
+        value = value
+            .checked_add(usize::from(byte & 0x7f) << shift)
+            .ok_or(HpackError::IntegerOverflow)?;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn encode_integer(value: usize, prefix_bits: u8, flags: u8, output: &mut Vec<u8>) {
+    let max_prefix = (1 << prefix_bits) - 1;
+    if value < max_prefix {
+        output.push(flags | value as u8);
+        return;
+    }
+    output.push(flags | max_prefix as u8);
+    let mut remaining = value - max_prefix;
+    while remaining >= 0x80 {
+        output.push(0x80 | (remaining & 0x7f) as u8);
+        remaining >>= 7;
+    }
+    output.push(remaining as u8);
+}
+
+/// Decode a string literal, which starts with a flag for Huffman coding and its length (RFC 7541 section 5.2)
+fn decode_string(input: &mut &[u8]) -> Result<Vec<u8>, HpackError> {
+    let is_huffman = input.first().ok_or(HpackError::Truncated)? & 0x80 != 0;
+    let length = decode_integer(input, 7)?;
+    if length > input.len() {
+        return Err(HpackError::Truncated);
+    }
+    let (string, rest) = input.split_at(length);
+    *input = rest;
+    if is_huffman {
+        huffman::decode(string).ok_or(HpackError::InvalidHuffman)
+    } else {
+        Ok(string.to_vec())
+    }
+}
+
+fn encode_string(string: &[u8], output: &mut Vec<u8>) {
+    let huffman_length = huffman::encoded_len(string);
+    if huffman_length < string.len() {
+        encode_integer(huffman_length, 7, 0x80, output);
+        huffman::encode(string, output);
+    } else {
+        encode_integer(string.len(), 7, 0x00, output);
+        output.extend_from_slice(string);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(decoded: Vec<HeaderField>) -> Vec<(String, String)> {
+        decoded
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    String::from_utf8(name).unwrap(),
+                    String::from_utf8(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn expected(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_integers() {
+        // examples from RFC 7541 appendix C.1
+        for (value, prefix_bits, encoded) in [
+            (10, 5, vec![0x0a]),
+            (1337, 5, vec![0x1f, 0x9a, 0x0a]),
+            (42, 8, vec![0x2a]),
+        ] {
+            let mut output = Vec::new();
+            encode_integer(value, prefix_bits, 0, &mut output);
+            assert_eq!(output, encoded);
+            assert_eq!(
+                decode_integer(&mut encoded.as_slice(), prefix_bits),
+                Ok(value)
+            );
+        }
+        assert_eq!(
+            decode_integer(&mut [0x1f, 0x9a].as_slice(), 5),
+            Err(HpackError::Truncated)
+        );
+        assert_eq!(
+            decode_integer(
+                &mut [0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01].as_slice(),
+                5
+            ),
+            Err(HpackError::IntegerOverflow)
+        );
+    }
+
+    /// The requests of RFC 7541 appendix C.3 and C.4, which share a dynamic table
+    #[test]
+    fn test_request_examples() {
+        let blocks: [&[u8]; 6] = [
+            &[
+                0x82, 0x86, 0x84, 0x41, 0x0f, 0x77, 0x77, 0x77, 0x2e, 0x65, 0x78, 0x61, 0x6d, 0x70,
+                0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d,
+            ],
+            &[
+                0x82, 0x86, 0x84, 0xbe, 0x58, 0x08, 0x6e, 0x6f, 0x2d, 0x63, 0x61, 0x63, 0x68, 0x65,
+            ],
+            &[
+                0x82, 0x87, 0x85, 0xbf, 0x40, 0x0a, 0x63, 0x75, 0x73, 0x74, 0x6f, 0x6d, 0x2d, 0x6b,
+                0x65, 0x79, 0x0c, 0x63, 0x75, 0x73, 0x74, 0x6f, 0x6d, 0x2d, 0x76, 0x61, 0x6c, 0x75,
+                0x65,
+            ],
+            &[
+                0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab,
+                0x90, 0xf4, 0xff,
+            ],
+            &[
+                0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf,
+            ],
+            &[
+                0x82, 0x87, 0x85, 0xbf, 0x40, 0x88, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xa9, 0x7d, 0x7f,
+                0x89, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xb8, 0xe8, 0xb4, 0xbf,
+            ],
+        ];
+        let first = [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ];
+        let second = [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache"),
+        ];
+        let third = [
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ];
+        for examples in blocks.chunks(3) {
+            let mut decoder = Decoder::new(4096);
+            assert_eq!(
+                fields(decoder.decode(examples[0], 4096).unwrap()),
+                expected(&first)
+            );
+            assert_eq!(decoder.table_size, 57);
+            assert_eq!(
+                fields(decoder.decode(examples[1], 4096).unwrap()),
+                expected(&second)
+            );
+            assert_eq!(decoder.table_size, 110);
+            assert_eq!(
+                fields(decoder.decode(examples[2], 4096).unwrap()),
+                expected(&third)
+            );
+            assert_eq!(decoder.table_size, 164);
+            assert_eq!(decoder.table.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut decoder = Decoder::new(100);
+        // each entry is 32 + 2 bytes, so only two fit
+        let block = [
+            0x40, 0x01, b'a', 0x01, b'1', 0x40, 0x01, b'b', 0x01, b'2', 0x40, 0x01, b'c', 0x01,
+            b'3',
+        ];
+        decoder.decode(&block, 4096).unwrap();
+        assert_eq!(decoder.table_size, 68);
+        // the newest entry is 62, and `a` has been evicted
+        assert_eq!(
+            fields(decoder.decode(&[0xbe, 0xbf], 4096).unwrap()),
+            expected(&[("c", "3"), ("b", "2")])
+        );
+        assert_eq!(
+            decoder.decode(&[0xc0], 4096),
+            Err(HpackError::InvalidIndex(64))
+        );
+
+        // shrinking the table evicts entries, and it can't grow past the limit
+        decoder.decode(&[0x20], 4096).unwrap();
+        assert_eq!(decoder.table.len(), 0);
+        assert_eq!(
+            decoder.decode(&[0x3f, 0x46], 4096),
+            Err(HpackError::InvalidTableSizeUpdate(101))
+        );
+        assert_eq!(
+            decoder.decode(&[0x82, 0x20], 4096),
+            Err(HpackError::InvalidTableSizeUpdate(0))
+        );
+    }
+
+    #[test]
+    fn test_header_list_limit() {
+        let mut decoder = Decoder::new(4096);
+        // `a: 1` is 34 bytes, added to the table and then repeated by index
+        let mut block = vec![0x40, 0x01, b'a', 0x01, b'1'];
+        assert_eq!(decoder.decode(&block, 34).unwrap().len(), 1);
+        block.extend([0xbe; 1000]);
+        assert_eq!(
+            decoder.decode(&block, 34 * 100),
+            Err(HpackError::HeaderListTooLarge)
+        );
+        // the block was still decoded, so the table is in step with the encoder's
+        assert_eq!(decoder.table.len(), 2);
+        assert_eq!(
+            fields(decoder.decode(&[0xbe], 34).unwrap()),
+            expected(&[("a", "1")])
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let headers = [
+            (":status", "200"),
+            (":status", "503"),
+            ("content-type", "text/html; charset=utf-8"),
+            ("x-custom", "value"),
+            ("empty", ""),
+        ];
+        let mut block = Vec::new();
+        encode(headers, &mut block);
+        // the first field is indexed, and the second names an indexed field
+        assert_eq!(&block[..3], &[0x88, 0x08, 0x03]);
+        let mut decoder = Decoder::new(4096);
+        assert_eq!(
+            fields(decoder.decode(&block, 4096).unwrap()),
+            expected(&headers)
+        );
+        // nothing was added to the dynamic table
+        assert_eq!(decoder.table_size, 0);
+        assert_eq!(
+            decoder.decode(&[0x00, 0x81], 4096),
+            Err(HpackError::Truncated)
+        );
+        assert_eq!(
+            decoder.decode(&[0x00, 0x81, 0x00, 0x00], 4096),
+            Err(HpackError::InvalidHuffman)
+        );
+    }
+}
@@ -0,0 +1,179 @@
+/// The length of each symbol's code in the Huffman code of HPACK (RFC 7541 appendix B), with 256 being EOS
+///
+/// The code is canonical, so the codes themselves follow from the lengths.
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, //
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28, //
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, //
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, //
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, //
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, //
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5, //
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, //
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23, //
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, //
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, //
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23, //
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, //
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, //
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23, //
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, //
+    30,
+];
+
+/// The end of string symbol, which must never appear in encoded data
+const EOS: u16 = 256;
+const MAX_CODE_LENGTH: usize = 30;
+
+/// The canonical code, laid out for encoding by symbol and decoding by length
+struct CanonicalCode {
+    codes: [u32; 257],
+    /// Symbols ordered by their codes
+    symbols: [u16; 257],
+    /// For each length, the first code of that length and where its symbols start
+    first_code: [u32; MAX_CODE_LENGTH + 1],
+    first_index: [usize; MAX_CODE_LENGTH + 1],
+    count: [u32; MAX_CODE_LENGTH + 1],
+}
+
+const CANONICAL_CODE: CanonicalCode = canonical_code();
+
+/// Assign codes in order of length then symbol, each one more than the last and shifted as the length grows
+const fn canonical_code() -> CanonicalCode {
+    let mut table = CanonicalCode {
+        codes: [0; 257],
+        symbols: [0; 257],
+        first_code: [0; MAX_CODE_LENGTH + 1],
+        first_index: [0; MAX_CODE_LENGTH + 1],
+        count: [0; MAX_CODE_LENGTH + 1],
+    };
+    let mut code = 0;
+    let mut index = 0;
+    let mut length = 1;
+    while length <= MAX_CODE_LENGTH {
+        table.first_code[length] = code;
+        table.first_index[length] = index;
+        let mut symbol = 0;
+        while symbol < CODE_LENGTHS.len() {
+            if CODE_LENGTHS[symbol] as usize == length {
+                table.codes[symbol] = code;
+                table.symbols[index] = symbol as u16;
+                table.count[length] += 1;
+                code += 1;
+                index += 1;
+            }
+            symbol += 1;
+        }
+        code <<= 1;
+        length += 1;
+    }
+    table
+}
+
+/// The length of the input once encoded
+pub(super) fn encoded_len(input: &[u8]) -> usize {
+    let bits: usize = input
+        .iter()
+        .map(|&byte| CODE_LENGTHS[byte as usize] as usize)
+        .sum();
+    bits.div_ceil(8)
+}
+
+/// Encode the input, padding the last byte with the start of the EOS code
+pub(super) fn encode(input: &[u8], output: &mut Vec<u8>) {
+    // bits above the pending ones are shifted out and ignored
+    let mut bits: u64 = 0;
+    let mut pending = 0;
+    for &byte in input {
+        let length = CODE_LENGTHS[byte as usize] as u32;
+        bits = (bits << length) | u64::from(CANONICAL_CODE.codes[byte as usize]);
+        pending += length;
+        while pending >= 8 {
+            pending -= 8;
+            output.push((bits >> pending) as u8);
+        }
+    }
+    if pending > 0 {
+        output.push(((bits << (8 - pending)) | (0xff >> pending)) as u8);
+    }
+}
+
+/// Decode the input, returning None if it contains EOS or isn't padded with at most 7 bits of EOS
+pub(super) fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 8 / 5);
+    let mut code = 0;
+    let mut length = 0;
+    for &byte in encoded {
+        for shift in (0..8).rev() {
+            code = (code << 1) | u32::from((byte >> shift) & 1);
+            length += 1;
+            // codes of each length are consecutive, so a code is complete once it's in its length's range
+            let offset = code.wrapping_sub(CANONICAL_CODE.first_code[length]);
+            if offset < CANONICAL_CODE.count[length] {
+                let symbol =
+                    CANONICAL_CODE.symbols[CANONICAL_CODE.first_index[length] + offset as usize];
+                if symbol == EOS {
+                    return None;
+                }
+                decoded.push(symbol as u8);
+                code = 0;
+                length = 0;
+            } else if length == MAX_CODE_LENGTH {
+                return None;
+            }
+        }
+    }
+    let is_eos_prefix = code == (1 << length) - 1;
+    (length < 8 && is_eos_prefix).then_some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Examples from RFC 7541 appendix C.4 and C.6
+    const EXAMPLES: [(&str, &[u8]); 4] = [
+        (
+            "www.example.com",
+            &[
+                0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff,
+            ],
+        ),
+        ("no-cache", &[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf]),
+        (
+            "custom-value",
+            &[0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xb8, 0xe8, 0xb4, 0xbf],
+        ),
+        ("302", &[0x64, 0x02]),
+    ];
+
+    #[test]
+    fn test_examples() {
+        for (text, encoded) in EXAMPLES {
+            let mut output = Vec::new();
+            encode(text.as_bytes(), &mut output);
+            assert_eq!(output, encoded, "{text}");
+            assert_eq!(encoded_len(text.as_bytes()), encoded.len());
+            assert_eq!(decode(encoded), Some(text.as_bytes().to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input: Vec<u8> = (0..=255).chain((0..=255).rev()).collect();
+        let mut output = Vec::new();
+        encode(&input, &mut output);
+        assert_eq!(decode(&output), Some(input));
+    }
+
+    #[test]
+    fn test_invalid_padding() {
+        // '0' is 00000, so padding with zeros instead of ones is invalid
+        assert_eq!(decode(&[0b0000_0111]), Some(b"0".to_vec()));
+        assert_eq!(decode(&[0b0000_0000]), None);
+        // a whole byte of padding is too much
+        assert_eq!(decode(&[0b0000_0111, 0xff]), None);
+        // EOS itself, 30 ones, is never allowed
+        assert_eq!(decode(&[0xff, 0xff, 0xff, 0xfc]), None);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Somewhere to write log lines, shared by every thread handling requests
+pub trait LogSink: Send + Sync + 'static {
+    /// Write the line, adding the line ending
+    fn write_line(&self, line: &str) -> io::Result<()>;
+}
+
+/// Writes to standard output
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StdoutSink;
+
+/// Appends to a file, moving it aside once it reaches a maximum size
+///
+/// When `access.log` is full it is renamed to `access.log.1`, the old `access.log.1` to `access.log.2`
+/// and so on, with the oldest file deleted once there are more than `max_files` old files.
+pub struct RotatingFileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: Mutex<OpenFile>,
+}
+
+struct OpenFile {
+    file: File,
+    size: u64,
+}
+
+impl<F: Fn(&str) -> io::Result<()> + Send + Sync + 'static> LogSink for F {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        self(line)
+    }
+}
+
+impl LogSink for StdoutSink {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        writeln!(io::stdout().lock(), "{line}")
+    }
+}
+
+impl RotatingFileSink {
+    /// Append to the file at the path, creating it if it doesn't exist
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_bytes` is zero.
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        assert!(max_bytes > 0, "The log files must be able to hold lines");
+        let path = path.into();
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFileSink {
+            path,
+            max_bytes,
+            max_files,
+            file: Mutex::new(OpenFile { file, size }),
+        })
+    }
+
+    /// The path of an old log file, numbered from 1 for the most recent
+    fn rotated_path(&self, number: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{number}"));
+        path.into()
+    }
+
+    /// Move the current file and each old file along a number, and start a new file
+    fn rotate(&self, open_file: &mut OpenFile) -> io::Result<()> {
+        if self.max_files == 0 {
+            open_file.file = File::create(&self.path)?;
+            open_file.size = 0;
+            return Ok(());
+        }
+        match fs::remove_file(self.rotated_path(self.max_files)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        for number in (1..self.max_files).rev() {
+            let path = self.rotated_path(number);
+            if path.exists() {
+                fs::rename(path, self.rotated_path(number + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        open_file.file = open_append(&self.path)?;
+        open_file.size = 0;
+        Ok(())
+    }
+}
+
+impl LogSink for RotatingFileSink {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut open_file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        let length = line.len() as u64 + 1;
+        // a line longer than the maximum still gets a file to itself
+        if open_file.size > 0 && open_file.size + length > self.max_bytes {
+            self.rotate(&mut open_file)?;
+        }
+        writeln!(open_file.file, "{line}")?;
+        open_file.size += length;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_rotating_file_sink() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let directory = std::env::temp_dir().join(format!("webserver-log-{nanos}"));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("access.log");
+
+        // each line is 6 bytes with its line ending, so two fit in a file
+        let sink = RotatingFileSink::new(&path, 12, 2).unwrap();
+        for line in [
+            "line1", "line2", "line3", "line4", "line5", "line6", "line7",
+        ] {
+            sink.write_line(line).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line7\n");
+        assert_eq!(
+            fs::read_to_string(directory.join("access.log.1")).unwrap(),
+            "line5\nline6\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.join("access.log.2")).unwrap(),
+            "line3\nline4\n"
+        );
+        assert!(!directory.join("access.log.3").exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 /// Represents the HTTP Methods
@@ -9,8 +10,9 @@ use std::str::FromStr;
 /// use webserver::http::HttpMethod;
 /// assert_eq!("get".parse(), Ok(HttpMethod::Get));
 /// assert_eq!("post".parse(), Ok(HttpMethod::Post));
+/// assert_eq!(HttpMethod::Delete.to_string(), "DELETE");
 /// ```
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum HttpMethod {
     Get,
     Head,
@@ -41,3 +43,23 @@ impl FromStr for HttpMethod {
         }
     }
 }
+
+impl Display for HttpMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                HttpMethod::Get => "GET",
+                HttpMethod::Head => "HEAD",
+                HttpMethod::Post => "POST",
+                HttpMethod::Put => "PUT",
+                HttpMethod::Patch => "PATCH",
+                HttpMethod::Delete => "DELETE",
+                HttpMethod::Connect => "CONNECT",
+                HttpMethod::Options => "OPTIONS",
+                HttpMethod::Trace => "TRACE",
+            }
+        )
+    }
+}
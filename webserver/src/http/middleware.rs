@@ -0,0 +1,142 @@
+use crate::http::{Handler, HttpRequest, HttpResponse};
+
+/// Wraps the handling of every request, for concerns shared by all routes like authentication or CORS
+///
+/// Middleware can change the request before passing it on with [`Next::run`], change the response afterwards,
+/// or return its own response without running the rest of the stack at all.
+/// Implemented for any thread safe closure taking a request and [`Next`], and returning a response.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{HttpRequest, HttpResponse, HttpStatus, MiddlewareStack, Next};
+/// let stack = MiddlewareStack::new(|_request: HttpRequest| HttpResponse::ok().body("Hello"))
+///     .wrap(|request: HttpRequest, next: Next| {
+///         if request.header("Authorization").is_none() {
+///             return HttpResponse::new(HttpStatus::Unauthorized401);
+///         }
+///         next.run(request).header("Cache-Control", "no-store")
+///     });
+/// ```
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse;
+}
+
+/// The rest of a middleware stack, ending with its handler
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+/// A handler wrapped in layers of middleware, which run in the order they were added
+pub struct MiddlewareStack<H: Handler> {
+    middleware: Vec<Box<dyn Middleware>>,
+    handler: H,
+}
+
+impl<F> Middleware for F
+where
+    F: for<'a> Fn(HttpRequest, Next<'a>) -> HttpResponse + Send + Sync + 'static,
+{
+    fn handle(&self, request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        self(request, next)
+    }
+}
+
+impl Next<'_> {
+    /// Pass the request on to the next middleware, or the handler if this is the last layer
+    pub fn run(self, request: HttpRequest) -> HttpResponse {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                request,
+                Next {
+                    middleware: rest,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(request),
+        }
+    }
+}
+
+impl<H: Handler> MiddlewareStack<H> {
+    pub fn new(handler: H) -> Self {
+        MiddlewareStack {
+            middleware: Vec::new(),
+            handler,
+        }
+    }
+
+    /// Add a layer of middleware, inside the layers that were already added
+    pub fn wrap(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+}
+
+impl<H: Handler> Handler for MiddlewareStack<H> {
+    fn handle(&self, request: HttpRequest) -> HttpResponse {
+        Next {
+            middleware: &self.middleware,
+            handler: &self.handler,
+        }
+        .run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpStatus;
+
+    fn request(path: &str) -> HttpRequest {
+        HttpRequest::from_lines(std::iter::once(format!("GET {path} HTTP/1.1"))).unwrap()
+    }
+
+    /// Records the order the layers run in, in the `Trace` header
+    fn trace(name: &'static str) -> impl Middleware {
+        move |mut request: HttpRequest, next: Next| {
+            request
+                .headers_mut()
+                .append("Trace", &format!("{name} before"));
+            let response = next.run(request);
+            response.header("Trace", &format!("{name} after"))
+        }
+    }
+
+    fn echo_trace(request: HttpRequest) -> HttpResponse {
+        let trace: Vec<&str> = request.headers().get_all("Trace").collect();
+        HttpResponse::ok().body(trace.join(", "))
+    }
+
+    #[test]
+    fn test_middleware_order() {
+        let stack = MiddlewareStack::new(echo_trace)
+            .wrap(trace("outer"))
+            .wrap(trace("inner"));
+        let response = stack.handle(request("/"));
+        assert_eq!(response.body, b"outer before, inner before");
+        assert_eq!(
+            response.headers.get_all("Trace").collect::<Vec<_>>(),
+            vec!["inner after", "outer after"]
+        );
+    }
+
+    #[test]
+    fn test_middleware_short_circuit() {
+        let stack = MiddlewareStack::new(echo_trace)
+            .wrap(|request: HttpRequest, next: Next| {
+                if request.path().starts_with("/admin") {
+                    return HttpResponse::new(HttpStatus::Forbidden403);
+                }
+                next.run(request)
+            })
+            .wrap(trace("inner"));
+        assert_eq!(
+            stack.handle(request("/admin")).status,
+            HttpStatus::Forbidden403
+        );
+        assert_eq!(stack.handle(request("/")).body, b"inner before");
+    }
+}
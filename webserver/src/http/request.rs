@@ -1,18 +1,42 @@
+use crate::http::body::{read_body, read_line, BodyDecoder, BodyParseError, BodyReader, LineError};
+use crate::http::cookie::parse_cookies;
+use crate::http::form::parse_form;
+use crate::http::headers::HeaderParseError;
 use crate::http::request::{RequestParseError::*, StartLineParseError::*};
+use crate::http::url::ParsedTarget;
 use crate::http::version::HttpVersion;
-use crate::http::HttpMethod;
+use crate::http::{
+    Form, FormConfig, FormError, HttpHeaders, HttpMethod, Query, RequestTarget, Session,
+};
+use std::collections::HashMap;
+use std::io::{BufRead, ErrorKind, Read};
 
 #[derive(Debug, PartialEq)]
 pub struct HttpRequest {
     method: HttpMethod,
+    /// The target exactly as it appeared in the request line
+    raw_target: String,
+    target: RequestTarget,
     path: String,
+    query: Query,
     version: HttpVersion,
+    headers: HttpHeaders,
+    body: Vec<u8>,
+    /// The body left on the connection, to be decoded as it arrives
+    body_reader: Option<BodyReader>,
+    params: HashMap<String, String>,
+    session: Option<Session>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum StartLineParseError {
     InvalidHttpMethod,
+    InvalidRequestTarget,
     InvalidHttpVersion,
+    /// A version the server knows, but can't speak in a text request line, e.g. HTTP/2
+    UnsupportedHttpVersion,
+    /// The start of the HTTP/2 connection preface, sent by clients that know the server speaks HTTP/2
+    Http2Preface,
     MissingInformation(String),
 }
 
@@ -20,24 +44,41 @@ pub enum StartLineParseError {
 pub enum RequestParseError {
     InvalidStartLine(StartLineParseError),
     MissingStartLine,
+    InvalidHeader(HeaderParseError),
+    InvalidBody(BodyParseError),
+    ConnectionError(ErrorKind),
+    StartLineTooLong {
+        limit: usize,
+    },
+    /// A header line was longer than the line limit, or the whole head was larger than its limit
+    HeadersTooLarge {
+        limit: usize,
+    },
 }
 
+/// The request line of the HTTP/2 connection preface, which is followed by an empty line (RFC 9113 section 3.4)
+const HTTP2_PREFACE_LINE: &str = "PRI * HTTP/2.0";
+
+/// The longest request line or header line accepted by [`HttpRequest::read_from`], not counting the line ending
+pub(crate) const DEFAULT_MAX_LINE_LENGTH: usize = 8 * 1024;
+/// The largest head, the request line and headers, accepted by [`HttpRequest::read_from`]
+pub(crate) const DEFAULT_MAX_HEAD_SIZE: usize = 32 * 1024;
+
 impl HttpRequest {
     fn from(line: &str) -> Result<HttpRequest, StartLineParseError> {
+        if line == HTTP2_PREFACE_LINE {
+            return Err(Http2Preface);
+        }
         let parts: Vec<&str> = line.split(' ').take(4).collect();
         match parts.as_slice() {
-            [method, path, version] => {
-                let method = method
-                    .parse::<HttpMethod>()
-                    .map_err(|_| InvalidHttpMethod)?;
-                let version = version
-                    .parse::<HttpVersion>()
-                    .map_err(|_| InvalidHttpVersion)?;
-                Ok(HttpRequest {
-                    method,
-                    path: path.to_string(),
-                    version,
-                })
+            [method, target, version] => {
+                // checked first, so an unknown method isn't reported for a version the server can't parse
+                let version = match version.parse::<HttpVersion>() {
+                    Ok(HttpVersion::Http2 | HttpVersion::Http3) => Err(UnsupportedHttpVersion),
+                    Ok(version) => Ok(version),
+                    Err(()) => Err(InvalidHttpVersion),
+                }?;
+                HttpRequest::from_parts(method, target, version, HttpHeaders::new())
             }
             _ => Err(MissingInformation(format!(
                 "Wrong number of parts, expected 3 found: {}",
@@ -46,29 +87,239 @@ impl HttpRequest {
         }
     }
 
+    /// Create a request without a body from its parts, as they arrive in protocols without a request line
+    pub(crate) fn from_parts(
+        method: &str,
+        target: &str,
+        version: HttpVersion,
+        headers: HttpHeaders,
+    ) -> Result<HttpRequest, StartLineParseError> {
+        let method = method
+            .parse::<HttpMethod>()
+            .map_err(|_| InvalidHttpMethod)?;
+        let ParsedTarget { form, path, query } =
+            ParsedTarget::parse(target, method == HttpMethod::Options)
+                .map_err(|_| InvalidRequestTarget)?;
+        Ok(HttpRequest {
+            method,
+            raw_target: target.to_string(),
+            target: form,
+            path,
+            query,
+            version,
+            headers,
+            body: Vec::new(),
+            body_reader: None,
+            params: HashMap::new(),
+            session: None,
+        })
+    }
+
     pub fn from_lines(
         http_request_lines: impl Iterator<Item = String>,
     ) -> Result<HttpRequest, RequestParseError> {
         // stop parsing after the request ends with an empty line
         let mut lines = http_request_lines.take_while(|line| !line.is_empty());
-        lines
+        let mut request = lines
             .next()
             .map(|start_line| HttpRequest::from(&start_line))
             .ok_or(MissingStartLine)?
-            .map_err(|err| InvalidStartLine(err))
+            .map_err(InvalidStartLine)?;
+        request.headers = lines
+            .map(|line| HttpHeaders::parse_line(&line))
+            .collect::<Result<HttpHeaders, HeaderParseError>>()
+            .map_err(InvalidHeader)?;
+        Ok(request)
+    }
+
+    /// Read a full request from the reader, including a body of at most `max_body_size` bytes
+    ///
+    /// Lines longer than 8 KiB, or a request line and headers adding up to more than 32 KiB, are rejected
+    pub fn read_from(
+        reader: &mut impl BufRead,
+        max_body_size: usize,
+    ) -> Result<HttpRequest, RequestParseError> {
+        let mut request =
+            HttpRequest::read_head(reader, DEFAULT_MAX_LINE_LENGTH, DEFAULT_MAX_HEAD_SIZE)?;
+        request.read_body(reader, max_body_size)?;
+        Ok(request)
+    }
+
+    /// Read the request line and headers, leaving the body to be read by [`HttpRequest::read_body`]
+    pub(crate) fn read_head(
+        reader: &mut impl BufRead,
+        max_line_length: usize,
+        max_head_size: usize,
+    ) -> Result<HttpRequest, RequestParseError> {
+        let mut head_lines = Vec::new();
+        let mut head_size = 0;
+        loop {
+            let line = read_line(reader, max_line_length).map_err(|err| match err {
+                LineError::TooLong { .. } if head_lines.is_empty() => StartLineTooLong {
+                    limit: max_line_length,
+                },
+                LineError::TooLong { .. } => HeadersTooLarge {
+                    limit: max_line_length,
+                },
+                LineError::ConnectionError(kind) => ConnectionError(kind),
+            })?;
+            let Some(line) = line.filter(|line| !line.is_empty()) else {
+                break;
+            };
+            // count the line ending too
+            head_size += line.len() + 2;
+            if head_size > max_head_size {
+                return Err(HeadersTooLarge {
+                    limit: max_head_size,
+                });
+            }
+            head_lines.push(line);
+        }
+        HttpRequest::from_lines(head_lines.into_iter())
+    }
+
+    /// Read the body of a request whose head has been read, using the framing its headers describe
+    pub(crate) fn read_body(
+        &mut self,
+        reader: &mut impl BufRead,
+        max_body_size: usize,
+    ) -> Result<(), RequestParseError> {
+        self.body = read_body(reader, &mut self.headers, max_body_size).map_err(InvalidBody)?;
+        Ok(())
+    }
+
+    /// Prepare to read the body as it's used, rather than reading it into memory up front
+    pub(crate) fn body_decoder(
+        &mut self,
+        max_body_size: usize,
+    ) -> Result<BodyDecoder, RequestParseError> {
+        BodyDecoder::new(&mut self.headers, max_body_size).map_err(InvalidBody)
+    }
+
+    pub(crate) fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    /// Leave the body to be read from the reader when the form is decoded
+    pub(crate) fn set_body_reader(&mut self, reader: impl Read + Send + 'static) {
+        self.body_reader = Some(BodyReader::new(reader));
     }
 
     pub fn method(&self) -> HttpMethod {
         self.method
     }
 
+    /// The path, with escapes decoded and `.` and `..` segments resolved, without the query string
     pub fn path(&self) -> &String {
         &self.path
     }
 
+    /// The target exactly as the client sent it, including any query string
+    pub fn raw_target(&self) -> &str {
+        &self.raw_target
+    }
+
+    pub fn target(&self) -> &RequestTarget {
+        &self.target
+    }
+
+    /// The parameters of the query string
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
     pub fn version(&self) -> HttpVersion {
         self.version
     }
+
+    pub fn headers(&self) -> &HttpHeaders {
+        &self.headers
+    }
+
+    /// The headers, for middleware to change before the request is handled
+    pub fn headers_mut(&mut self) -> &mut HttpHeaders {
+        &mut self.headers
+    }
+
+    /// The first value of the header, matching the name case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The body, which the server leaves empty for a `multipart/form-data` request over HTTP/1,
+    /// so the form can be decoded by [`HttpRequest::form`] as it arrives
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Decode the body as a form, with the default [`FormConfig`]
+    pub fn form(&mut self) -> Result<Form, FormError> {
+        self.form_with(&FormConfig::new())
+    }
+
+    /// Decode an `application/x-www-form-urlencoded` or `multipart/form-data` body, within the config's limits
+    ///
+    /// Over HTTP/1, a multipart body is read from the connection as it's decoded, writing uploaded files as they arrive,
+    /// while the server's maximum body size limits the body as a whole.
+    /// The body is taken from the request, so uploaded files are only written once and later calls find it empty.
+    pub fn form_with(&mut self, config: &FormConfig) -> Result<Form, FormError> {
+        let content_type = self.headers.get("Content-Type");
+        match self.body_reader.take() {
+            Some(reader) => parse_form(content_type, reader, config),
+            None => parse_form(
+                content_type,
+                std::mem::take(&mut self.body).as_slice(),
+                config,
+            ),
+        }
+    }
+
+    /// The value of a parameter captured from the path by a [`Router`](crate::http::Router)
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    pub(crate) fn set_params(&mut self, params: HashMap<String, String>) {
+        self.params = params;
+    }
+
+    /// The value of the first cookie with the name, from the `Cookie` headers
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies()
+            .find(|(cookie, _)| *cookie == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The name and value of every cookie the client sent, in order
+    pub fn cookies(&self) -> impl Iterator<Item = (&str, &str)> {
+        // HTTP/2 clients may split the cookies across several headers
+        self.headers.get_all("Cookie").flat_map(parse_cookies)
+    }
+
+    /// The client's session, when the request passed through a [`Sessions`](crate::http::Sessions) layer
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    pub(crate) fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    /// Whether the client wants the connection to stay open after this request
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`,
+    /// while older versions must opt in with `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option| {
+            self.headers
+                .get_list("Connection")
+                .any(|value| value.eq_ignore_ascii_case(option))
+        };
+        match self.version {
+            HttpVersion::Http1 => has_option("keep-alive"),
+            _ => !has_option("close"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -78,37 +329,100 @@ mod tests {
     #[test]
     fn test_from() {
         assert_eq!(
-            HttpRequest::from("GET /path HTTP/2"),
+            HttpRequest::from("GET /path HTTP/1.0"),
             Ok(HttpRequest {
                 method: HttpMethod::Get,
+                raw_target: "/path".to_string(),
+                target: RequestTarget::Origin,
                 path: "/path".to_string(),
-                version: HttpVersion::Http2
+                query: Query::new(),
+                version: HttpVersion::Http1,
+                headers: HttpHeaders::new(),
+                body: Vec::new(),
+                body_reader: None,
+                params: HashMap::new(),
+                session: None,
             })
         );
         assert_eq!(
             HttpRequest::from("POST /code HTTP/1.1"),
             Ok(HttpRequest {
                 method: HttpMethod::Post,
+                raw_target: "/code".to_string(),
+                target: RequestTarget::Origin,
                 path: "/code".to_string(),
-                version: HttpVersion::Http1_1
+                query: Query::new(),
+                version: HttpVersion::Http1_1,
+                headers: HttpHeaders::new(),
+                body: Vec::new(),
+                body_reader: None,
+                params: HashMap::new(),
+                session: None,
             })
         );
     }
 
+    #[test]
+    fn test_from_targets() {
+        let request = HttpRequest::from("GET /a/./b/../c%20d?x=1&x=2 HTTP/1.1").unwrap();
+        assert_eq!(request.path(), "/a/c d");
+        assert_eq!(request.raw_target(), "/a/./b/../c%20d?x=1&x=2");
+        assert_eq!(
+            request.query().get_all("x").collect::<Vec<_>>(),
+            vec!["1", "2"]
+        );
+
+        let request = HttpRequest::from("GET http://example.com/page?q HTTP/1.1").unwrap();
+        assert_eq!(
+            request.target(),
+            &RequestTarget::Absolute {
+                scheme: "http".to_string(),
+                authority: "example.com".to_string()
+            }
+        );
+        assert_eq!(request.path(), "/page");
+        assert!(request.query().contains("q"));
+
+        let request = HttpRequest::from("OPTIONS * HTTP/1.1").unwrap();
+        assert_eq!(request.target(), &RequestTarget::Asterisk);
+        assert_eq!(
+            HttpRequest::from("GET * HTTP/1.1"),
+            Err(InvalidRequestTarget)
+        );
+        assert_eq!(
+            HttpRequest::from("GET /%zz HTTP/1.1"),
+            Err(InvalidRequestTarget)
+        );
+    }
+
     #[test]
     fn test_from_errors() {
         assert_eq!(
-            HttpRequest::from("HELLO /path HTTP/2"),
+            HttpRequest::from("HELLO /path HTTP/1.1"),
             Err(InvalidHttpMethod)
         );
         assert_eq!(
             HttpRequest::from("GET /path HTTP/4"),
             Err(InvalidHttpVersion)
         );
+        assert_eq!(
+            HttpRequest::from("GET /path HTTP/2"),
+            Err(UnsupportedHttpVersion)
+        );
+        assert_eq!(
+            HttpRequest::from("GET /path HTTP/3"),
+            Err(UnsupportedHttpVersion)
+        );
+        // the start of the HTTP/2 connection preface
+        assert_eq!(HttpRequest::from("PRI * HTTP/2.0"), Err(Http2Preface));
         assert!(matches!(
             HttpRequest::from("GET /path"),
             Err(MissingInformation(_))
         ));
+        assert!(matches!(
+            HttpRequest::from("GET  /path HTTP/1.1"),
+            Err(MissingInformation(_))
+        ));
     }
 
     fn call_from_lines(start_line: &str) -> Result<HttpRequest, RequestParseError> {
@@ -123,16 +437,123 @@ mod tests {
             result,
             HttpRequest {
                 method: HttpMethod::Post,
+                raw_target: "/code".to_string(),
+                target: RequestTarget::Origin,
                 path: "/code".to_string(),
-                version: HttpVersion::Http1_1
+                query: Query::new(),
+                version: HttpVersion::Http1_1,
+                headers: HttpHeaders::new(),
+                body: Vec::new(),
+                body_reader: None,
+                params: HashMap::new(),
+                session: None,
             }
         );
     }
 
+    #[test]
+    fn test_from_lines_headers() {
+        let lines = vec![
+            "GET / HTTP/1.1",
+            "Host: localhost:7878",
+            "Accept: text/html",
+            "accept: application/json",
+            "",
+            "ignored: body",
+        ];
+        let request = HttpRequest::from_lines(lines.into_iter().map(String::from))
+            .expect("Should parse correctly");
+        assert_eq!(request.header("host"), Some("localhost:7878"));
+        assert_eq!(
+            request.headers().get_all("Accept").collect::<Vec<_>>(),
+            vec!["text/html", "application/json"]
+        );
+        assert_eq!(request.header("ignored"), None);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let parse = |lines: &[&str]| {
+            HttpRequest::from_lines(lines.iter().map(|line| line.to_string())).unwrap()
+        };
+        assert!(parse(&["GET / HTTP/1.1"]).keep_alive());
+        assert!(!parse(&["GET / HTTP/1.1", "Connection: Close"]).keep_alive());
+        assert!(!parse(&["GET / HTTP/1"]).keep_alive());
+        assert!(parse(&["GET / HTTP/1", "Connection: Keep-Alive"]).keep_alive());
+    }
+
+    #[test]
+    fn test_cookies() {
+        let lines = ["GET / HTTP/1.1", "Cookie: a=1; theme=dark", "Cookie: a=2"];
+        let request = HttpRequest::from_lines(lines.iter().map(|line| line.to_string())).unwrap();
+        assert_eq!(request.cookie("theme"), Some("dark"));
+        assert_eq!(request.cookie("a"), Some("1"));
+        assert_eq!(request.cookie("missing"), None);
+        assert_eq!(
+            request.cookies().collect::<Vec<_>>(),
+            vec![("a", "1"), ("theme", "dark"), ("a", "2")]
+        );
+    }
+
+    #[test]
+    fn test_read_from() {
+        let raw_request =
+            "POST /submit HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n";
+        let mut reader = raw_request.as_bytes();
+        let request = HttpRequest::read_from(&mut reader, 1024).expect("Should parse correctly");
+        assert_eq!(request.method(), HttpMethod::Post);
+        assert_eq!(request.body(), b"hello");
+        // only the first request is consumed
+        assert_eq!(reader, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn test_form() {
+        let raw_request = "POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+                           Content-Length: 7\r\n\r\nname=Jo";
+        let mut request = HttpRequest::read_from(&mut raw_request.as_bytes(), 1024).unwrap();
+        assert_eq!(request.form().unwrap().get("name"), Some("Jo"));
+        // the body is taken by the first call
+        assert!(request.body().is_empty());
+        assert_eq!(request.form().unwrap().get("name"), None);
+    }
+
+    #[test]
+    fn test_read_from_errors() {
+        let raw_request = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        assert_eq!(
+            HttpRequest::read_from(&mut raw_request.as_bytes(), 4),
+            Err(InvalidBody(BodyParseError::TooLarge { limit: 4 }))
+        );
+        assert_eq!(
+            HttpRequest::read_from(&mut "".as_bytes(), 4),
+            Err(MissingStartLine)
+        );
+    }
+
+    #[test]
+    fn test_read_head_limits() {
+        let read_head =
+            |raw_request: &str| HttpRequest::read_head(&mut raw_request.as_bytes(), 20, 40);
+        assert!(read_head("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").is_ok());
+        assert_eq!(
+            read_head("GET /a-very-long-path HTTP/1.1\r\n\r\n"),
+            Err(StartLineTooLong { limit: 20 })
+        );
+        assert_eq!(
+            read_head("GET / HTTP/1.1\r\nUser-Agent: a long agent\r\n\r\n"),
+            Err(HeadersTooLarge { limit: 20 })
+        );
+        assert_eq!(
+            read_head("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\nE: 5\r\n\r\n"),
+            Err(HeadersTooLarge { limit: 40 })
+        );
+    }
+
     #[test]
     fn test_from_lines_errors() {
         assert_eq!(
-            call_from_lines("HELLO /path HTTP/2"),
+            call_from_lines("HELLO /path HTTP/1.1"),
             Err(InvalidStartLine(InvalidHttpMethod))
         );
         assert_eq!(
@@ -146,5 +567,13 @@ mod tests {
 
         let result = HttpRequest::from_lines(Vec::new().into_iter());
         assert_eq!(result, Err(MissingStartLine));
+
+        let lines = vec!["GET / HTTP/1.1".to_string(), "Host localhost".to_string()];
+        assert_eq!(
+            HttpRequest::from_lines(lines.into_iter()),
+            Err(InvalidHeader(HeaderParseError::MissingColon(
+                "Host localhost".to_string()
+            )))
+        );
     }
 }
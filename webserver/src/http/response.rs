@@ -1,23 +1,242 @@
-use crate::http::{HttpStatus, HttpVersion};
-use std::fmt::{Display, Formatter};
+use crate::http::headers::{is_token_char, is_valid_field};
+use crate::http::websocket::Upgrade;
+use crate::http::{Cookie, HttpHeaders, HttpStatus, HttpVersion};
+use crate::thread_pool::panic_message;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
 
+/// How much of a streamed body is read at a time
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// A response to send back to the client
+///
+/// # Examples
+///
+/// Responses can be built up from a status
+/// ```
+/// use webserver::http::{HttpResponse, HttpStatus};
+/// let response = HttpResponse::ok()
+///     .header("Content-Type", "text/plain")
+///     .body("Hello!");
+/// assert_eq!(response.status, HttpStatus::Ok200);
+/// assert_eq!(response.body, b"Hello!");
+/// ```
 #[derive(Debug)]
 pub struct HttpResponse {
     pub status: HttpStatus,
-    pub content: String,
+    pub headers: HttpHeaders,
+    pub body: Vec<u8>,
     pub version: HttpVersion,
+    /// A body produced as it's sent, in place of `body`
+    pub(crate) stream: Option<BodyStream>,
+    /// Takes over the connection once this response switches it to WebSockets
+    pub(crate) upgrade: Option<Upgrade>,
 }
 
-impl Display for HttpResponse {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{} {}\nContent-Length: {}\n\n{}",
-            self.version,
-            self.status,
-            self.content.len(),
-            self.content
-        )
+impl HttpResponse {
+    /// Create an empty HTTP/1.1 response with the given status
+    pub fn new(status: HttpStatus) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: HttpHeaders::new(),
+            body: Vec::new(),
+            version: HttpVersion::Http1_1,
+            stream: None,
+            upgrade: None,
+        }
+    }
+
+    pub fn ok() -> HttpResponse {
+        HttpResponse::new(HttpStatus::Ok200)
+    }
+
+    pub fn bad_request() -> HttpResponse {
+        HttpResponse::new(HttpStatus::BadRequest400)
+    }
+
+    pub fn not_found() -> HttpResponse {
+        HttpResponse::new(HttpStatus::NotFound404)
+    }
+
+    /// Add a header, keeping any existing values for it
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name isn't a token, or the value contains CR, LF or NUL.
+    pub fn header(mut self, name: &str, value: &str) -> HttpResponse {
+        assert!(
+            !name.is_empty() && name.bytes().all(is_token_char),
+            "Invalid header name {name:?}"
+        );
+        assert!(
+            is_valid_field(name, value),
+            "Invalid header value {value:?}"
+        );
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Add a `Set-Cookie` header, asking the client to store the cookie
+    pub fn set_cookie(self, cookie: &Cookie) -> HttpResponse {
+        self.header("Set-Cookie", &cookie.to_string())
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> HttpResponse {
+        self.body = body.into();
+        self.stream = None;
+        self
+    }
+
+    /// Send the body as it's read from the reader, rather than holding it all in memory
+    ///
+    /// Each read is sent to the client as soon as it's made, with chunked transfer coding unless a
+    /// `Content-Length` header is set, in which case the reader must produce exactly that many bytes.
+    pub fn body_reader(mut self, reader: impl Read + Send + 'static) -> HttpResponse {
+        self.body = Vec::new();
+        self.stream = Some(BodyStream::Reader(Box::new(reader)));
+        self
+    }
+
+    /// Send each chunk of the body as soon as the iterator produces it, e.g. for events pushed to the client
+    ///
+    /// The body is framed the same way as [`HttpResponse::body_reader`]'s.
+    pub fn body_chunks<I>(mut self, chunks: I) -> HttpResponse
+    where
+        I: IntoIterator,
+        I::Item: Into<Vec<u8>> + 'static,
+        I::IntoIter: Send + 'static,
+    {
+        self.body = Vec::new();
+        let chunks = chunks.into_iter().map(Into::into);
+        self.stream = Some(BodyStream::Chunks(Box::new(chunks)));
+        self
+    }
+
+    /// Whether the body is streamed from a reader or iterator, leaving `body` empty
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn with_version(mut self, version: HttpVersion) -> HttpResponse {
+        self.version = version;
+        self
+    }
+
+    /// Write the status line and headers, followed by the body
+    ///
+    /// A streamed body can only be read once, so is left for the server to send.
+    pub fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        self.write_head_to(writer)?;
+        if self.status_allows_body() {
+            writer.write_all(&self.body)?;
+        }
+        Ok(())
+    }
+
+    /// Write the status line and headers only, as is needed to respond to a HEAD request
+    ///
+    /// A `Content-Length` header is added based on the body, unless the headers already frame the body or it's
+    /// streamed.
+    /// Fails with [`ErrorKind::InvalidData`] without writing anything if a header set directly on `headers` is invalid,
+    /// so it can't split the response.
+    pub fn write_head_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        // buffer the head so it is sent in a single write, rather than one per header
+        let mut head = Vec::new();
+        write!(head, "{} {}\r\n", self.version, self.status)?;
+        for (name, value) in self.headers.iter() {
+            if !is_valid_field(name, value) {
+                let message = format!("Invalid header {name:?}: {value:?}");
+                return Err(io::Error::new(ErrorKind::InvalidData, message));
+            }
+            write!(head, "{name}: {value}\r\n")?;
+        }
+        if self.status_allows_body()
+            && !self.is_streaming()
+            && !self.headers.contains("Content-Length")
+            && !self.headers.contains("Transfer-Encoding")
+        {
+            write!(head, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        head.extend_from_slice(b"\r\n");
+        writer.write_all(&head)
+    }
+
+    /// 1xx, 204 and 304 responses never have a body (RFC 9112 section 6.3)
+    pub(crate) fn status_allows_body(&self) -> bool {
+        !(self.status.is_informational()
+            || self.status == HttpStatus::NoContent204
+            || self.status == HttpStatus::NotModified304)
+    }
+}
+
+/// A response body produced as it's sent
+pub(crate) enum BodyStream {
+    Reader(Box<dyn Read + Send>),
+    Chunks(Box<dyn Iterator<Item = Vec<u8>> + Send>),
+}
+
+impl BodyStream {
+    /// The next part of the body, skipping empty chunks, or None once it has all been produced
+    ///
+    /// A reader or iterator that panics is treated as failing, so the response is cut short.
+    pub(crate) fn next_chunk(&mut self) -> Option<io::Result<Vec<u8>>> {
+        panic::catch_unwind(AssertUnwindSafe(|| match self {
+            BodyStream::Reader(reader) => {
+                let mut buffer = vec![0; READ_CHUNK_SIZE];
+                loop {
+                    match reader.read(&mut buffer) {
+                        Ok(0) => return None,
+                        Ok(length) => {
+                            buffer.truncate(length);
+                            return Some(Ok(buffer));
+                        }
+                        Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+            }
+            BodyStream::Chunks(chunks) => chunks.find(|chunk| !chunk.is_empty()).map(Ok),
+        }))
+        .unwrap_or_else(|payload| {
+            let message = panic_message(payload.as_ref());
+            eprintln!("The response body panicked with: {message}");
+            Some(Err(io::Error::other("The response body panicked")))
+        })
+    }
+
+    /// Write the whole body, flushing each chunk as it's produced, and return its length
+    ///
+    /// With `chunked`, each chunk is framed with chunked transfer coding (RFC 9112 section 7.1), otherwise the
+    /// body is written as it is.
+    pub(crate) fn write_to(mut self, writer: &mut impl Write, chunked: bool) -> io::Result<usize> {
+        let mut length = 0;
+        while let Some(chunk) = self.next_chunk() {
+            let chunk = chunk?;
+            if chunked {
+                write!(writer, "{:x}\r\n", chunk.len())?;
+                writer.write_all(&chunk)?;
+                writer.write_all(b"\r\n")?;
+            } else {
+                writer.write_all(&chunk)?;
+            }
+            writer.flush()?;
+            length += chunk.len();
+        }
+        if chunked {
+            writer.write_all(b"0\r\n\r\n")?;
+            writer.flush()?;
+        }
+        Ok(length)
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyStream::Reader(_) => f.write_str("Reader"),
+            BodyStream::Chunks(_) => f.write_str("Chunks"),
+        }
     }
 }
 
@@ -25,16 +244,118 @@ impl Display for HttpResponse {
 mod tests {
     use crate::http::{HttpResponse, HttpStatus, HttpVersion};
 
+    fn serialise(response: &HttpResponse) -> String {
+        let mut output = Vec::new();
+        response.write_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
     #[test]
     fn test_serialisation() {
-        let response1 = HttpResponse {
-            status: HttpStatus::Ok200,
-            content: "Content".to_string(),
-            version: HttpVersion::Http2,
-        };
+        let response1 = HttpResponse::ok()
+            .with_version(HttpVersion::Http2)
+            .body("Content");
+        assert_eq!(
+            serialise(&response1),
+            "HTTP/2 200 OK\r\nContent-Length: 7\r\n\r\nContent"
+        );
+
+        let response2 = HttpResponse::new(HttpStatus::MethodNotAllowed405)
+            .header("Allow", "GET")
+            .header("Allow", "HEAD");
+        assert_eq!(
+            serialise(&response2),
+            "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nAllow: HEAD\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_binary_body() {
+        let response = HttpResponse::ok()
+            .header("Content-Type", "application/octet-stream")
+            .body(vec![0, 159, 146, 150]);
+        let mut output = Vec::new();
+        response.write_to(&mut output).unwrap();
+        assert!(output.ends_with(b"Content-Length: 4\r\n\r\n\x00\x9f\x92\x96"));
+    }
+
+    #[test]
+    fn test_write_head_to() {
+        let response = HttpResponse::ok().body("Content");
+        let mut output = Vec::new();
+        response.write_head_to(&mut output).unwrap();
+        assert_eq!(output, b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_header_name() {
+        let _ = HttpResponse::ok().header("Bad Name", "value");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_header_value() {
+        let _ = HttpResponse::ok().header("Location", "/\r\nSet-Cookie: a=b");
+    }
+
+    #[test]
+    fn test_invalid_header_not_written() {
+        let mut response = HttpResponse::ok();
+        response.headers.append("Location", "/\r\nSet-Cookie: a=b");
+        let mut output = Vec::new();
+        let err = response.write_head_to(&mut output).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_body_stream() {
+        let mut output = Vec::new();
+        let response = HttpResponse::ok().body_reader(&b"streamed"[..]);
+        assert!(response.is_streaming());
+        let length = response
+            .stream
+            .unwrap()
+            .write_to(&mut output, true)
+            .unwrap();
+        assert_eq!(length, 8);
+        assert_eq!(output, b"8\r\nstreamed\r\n0\r\n\r\n");
+
+        let mut output = Vec::new();
+        let response = HttpResponse::ok().body_chunks(vec!["a", "b"]);
+        response
+            .stream
+            .unwrap()
+            .write_to(&mut output, false)
+            .unwrap();
+        assert_eq!(output, b"ab");
+
+        // a panicking body cuts the response short
+        let mut output = Vec::new();
+        let chunks = (0..3).map(|i| {
+            if i < 2 {
+                "chunk"
+            } else {
+                panic!("body failed")
+            }
+        });
+        let response = HttpResponse::ok().body_chunks(chunks);
+        let result = response.stream.unwrap().write_to(&mut output, true);
+        assert!(result.is_err());
+        assert_eq!(output, b"5\r\nchunk\r\n5\r\nchunk\r\n");
+    }
+
+    #[test]
+    fn test_statuses_without_body() {
+        let response = HttpResponse::new(HttpStatus::NotModified304)
+            .header("ETag", "\"abc\"")
+            .body("ignored");
         assert_eq!(
-            response1.to_string(),
-            "HTTP/2 200 OK\nContent-Length: 7\n\nContent"
+            serialise(&response),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"abc\"\r\n\r\n"
         );
+        let response = HttpResponse::new(HttpStatus::NoContent204);
+        assert_eq!(serialise(&response), "HTTP/1.1 204 No Content\r\n\r\n");
     }
 }
@@ -0,0 +1,304 @@
+use crate::http::{Handler, HttpMethod, HttpRequest, HttpResponse, HttpStatus};
+use std::collections::HashMap;
+
+/// Dispatches requests to handlers registered by method and path pattern
+///
+/// Patterns are made of `/` separated segments, which are either literal text,
+/// a `:name` parameter matching any single segment, or a trailing `*name` wildcard matching the rest of the path.
+/// Captured values are available from [`HttpRequest::param`].
+/// Routes are tried in the order they were registered, and GET routes also answer HEAD requests.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{HttpRequest, HttpResponse, Router};
+/// let router = Router::new().get("/users/:id", |request: HttpRequest| {
+///     HttpResponse::ok().body(format!("User {}", request.param("id").unwrap()))
+/// });
+/// ```
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Option<Box<dyn Handler>>,
+}
+
+struct Route {
+    method: HttpMethod,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: None,
+        }
+    }
+
+    /// Register a handler for requests with the method and a path matching the pattern
+    ///
+    /// # Panics
+    ///
+    /// Panics if a wildcard is not the last segment of the pattern.
+    pub fn route(mut self, method: HttpMethod, pattern: &str, handler: impl Handler) -> Router {
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler) -> Router {
+        self.route(HttpMethod::Get, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler) -> Router {
+        self.route(HttpMethod::Post, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler) -> Router {
+        self.route(HttpMethod::Put, pattern, handler)
+    }
+
+    pub fn patch(self, pattern: &str, handler: impl Handler) -> Router {
+        self.route(HttpMethod::Patch, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler) -> Router {
+        self.route(HttpMethod::Delete, pattern, handler)
+    }
+
+    /// Handle requests that match no route, instead of the default empty 404 response
+    pub fn not_found(mut self, handler: impl Handler) -> Router {
+        self.not_found = Some(Box::new(handler));
+        self
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, mut request: HttpRequest) -> HttpResponse {
+        let path = request.path().clone();
+        let mut allowed_methods = Vec::new();
+        let mut get_route = None;
+        for route in &self.routes {
+            let Some(params) = match_pattern(&route.pattern, &path) else {
+                continue;
+            };
+            if route.method == request.method() {
+                request.set_params(params);
+                return route.handler.handle(request);
+            }
+            if route.method == HttpMethod::Get && get_route.is_none() {
+                get_route = Some((route, params));
+            }
+            let methods = match route.method {
+                HttpMethod::Get => vec![HttpMethod::Get, HttpMethod::Head],
+                method => vec![method],
+            };
+            for method in methods {
+                if !allowed_methods.contains(&method) {
+                    allowed_methods.push(method);
+                }
+            }
+        }
+
+        // without a route of its own, HEAD is answered by the GET route, and the server drops the body
+        if let Some((route, params)) = get_route.filter(|_| request.method() == HttpMethod::Head) {
+            request.set_params(params);
+            return route.handler.handle(request);
+        }
+        if !allowed_methods.is_empty() {
+            let allow = allowed_methods
+                .iter()
+                .map(HttpMethod::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return HttpResponse::new(HttpStatus::MethodNotAllowed405).header("Allow", &allow);
+        }
+        match &self.not_found {
+            Some(handler) => handler.handle(request),
+            None => HttpResponse::not_found(),
+        }
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let segments: Vec<Segment> = split_path(pattern)
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Literal(segment.to_string())
+            }
+        })
+        .collect();
+    let wildcard_position = segments
+        .iter()
+        .position(|segment| matches!(segment, Segment::Wildcard(_)));
+    if let Some(position) = wildcard_position {
+        assert_eq!(
+            position,
+            segments.len() - 1,
+            "A wildcard must be the last segment of the pattern: {pattern}"
+        );
+    }
+    segments
+}
+
+/// Match the path against the pattern, returning the captured parameters if it matches
+fn match_pattern(pattern: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut path_segments = split_path(path);
+    for (index, segment) in pattern.iter().enumerate() {
+        match segment {
+            Segment::Wildcard(name) => {
+                let rest = split_path(path).skip(index).collect::<Vec<_>>().join("/");
+                params.insert(name.clone(), rest);
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if path_segments.next()? != literal {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), path_segments.next()?.to_string());
+            }
+        }
+    }
+    match path_segments.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(start_line: &str) -> HttpRequest {
+        HttpRequest::from_lines(vec![start_line.to_string()].into_iter()).unwrap()
+    }
+
+    fn respond_with(content: &'static str) -> impl Handler {
+        move |request: HttpRequest| {
+            HttpResponse::ok().body(format!("{content} {:?}", request.param("id")))
+        }
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", respond_with("index"))
+            .get("/users/:id", respond_with("get user"))
+            .delete("/users/:id", respond_with("delete user"))
+            .get("/static/*path", |request: HttpRequest| {
+                HttpResponse::ok().body(request.param("path").unwrap())
+            })
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(
+            parse_pattern("/users/:id/*rest"),
+            vec![
+                Segment::Literal("users".to_string()),
+                Segment::Param("id".to_string()),
+                Segment::Wildcard("rest".to_string())
+            ]
+        );
+        assert_eq!(parse_pattern("/"), vec![]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_pattern_wildcard_not_last() {
+        parse_pattern("/*rest/users");
+    }
+
+    #[test]
+    fn test_routing() {
+        let router = router();
+        assert_eq!(router.handle(request("GET / HTTP/1.1")).body, b"index None");
+        assert_eq!(
+            router
+                .handle(request("GET /users/42?verbose=true HTTP/1.1"))
+                .body,
+            b"get user Some(\"42\")"
+        );
+        assert_eq!(
+            router.handle(request("DELETE /users/42/ HTTP/1.1")).body,
+            b"delete user Some(\"42\")"
+        );
+        assert_eq!(
+            router
+                .handle(request("GET /static/css/site.css HTTP/1.1"))
+                .body,
+            b"css/site.css"
+        );
+        assert_eq!(router.handle(request("GET /static HTTP/1.1")).body, b"");
+        assert_eq!(
+            router.handle(request("HEAD / HTTP/1.1")).body,
+            b"index None"
+        );
+    }
+
+    #[test]
+    fn test_not_found() {
+        let response = router().handle(request("GET /users/42/posts HTTP/1.1"));
+        assert_eq!(response.status, HttpStatus::NotFound404);
+
+        let custom = router().not_found(respond_with("custom"));
+        assert_eq!(
+            custom.handle(request("GET /missing HTTP/1.1")).body,
+            b"custom None"
+        );
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let response = router().handle(request("PUT /users/42 HTTP/1.1"));
+        assert_eq!(response.status, HttpStatus::MethodNotAllowed405);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD, DELETE"));
+        let response = router().handle(request("HEAD /missing HTTP/1.1"));
+        assert_eq!(response.status, HttpStatus::NotFound404);
+    }
+
+    #[test]
+    fn test_head() {
+        assert_eq!(
+            router().handle(request("HEAD /users/42 HTTP/1.1")).body,
+            b"get user Some(\"42\")"
+        );
+        // a route for HEAD itself is preferred, even when registered after the GET route
+        let router = router().route(HttpMethod::Head, "/users/:id", respond_with("head user"));
+        assert_eq!(
+            router.handle(request("HEAD /users/42 HTTP/1.1")).body,
+            b"head user Some(\"42\")"
+        );
+
+        let post_only = Router::new().post("/form", respond_with("post"));
+        let response = post_only.handle(request("HEAD /form HTTP/1.1"));
+        assert_eq!(response.status, HttpStatus::MethodNotAllowed405);
+        assert_eq!(response.headers.get("Allow"), Some("POST"));
+    }
+}
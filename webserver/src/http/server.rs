@@ -1,66 +1,1170 @@
-use crate::http::{HttpRequest, HttpResponse, HttpStatus, HttpVersion};
-use crate::thread_pool::ThreadPool;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
-
-/// Handles all connections to a TcpListener and sends responses based on the response_fn
-pub struct Server<F: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static> {
+use crate::http::body::BodyDecoder;
+use crate::http::connection::TimedConnection;
+use crate::http::form::is_multipart_form;
+use crate::http::http2;
+use crate::http::request::{DEFAULT_MAX_HEAD_SIZE, DEFAULT_MAX_LINE_LENGTH};
+use crate::http::websocket;
+use crate::http::{
+    Acceptor, AccessLog, AccessLogEntry, BodyParseError, Connection, Handler, HttpMethod,
+    HttpRequest, HttpResponse, HttpStatus, HttpVersion, LogFormat, Middleware, MiddlewareStack,
+    RequestParseError, StartLineParseError, StdoutSink, TcpAcceptor,
+};
+use crate::thread_pool::{
+    panic_message, ExecuteError, PoolConfig, PoolHandle, RejectionPolicy, ShutdownSummary,
+    ThreadPool,
+};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Handles all connections to a TcpListener and sends responses based on the handler
+///
+/// Each request passes through the server's middleware, in the order it was added, before reaching the handler.
+/// Connections are plain HTTP unless another [`Acceptor`] is set with [`Server::with_acceptor`], e.g. for TLS.
+pub struct Server<H: Handler, A: Acceptor = TcpAcceptor> {
     listener: TcpListener,
+    acceptor: Arc<A>,
     thread_pool: ThreadPool,
-    response_fn: Arc<F>,
+    handler: MiddlewareStack<H>,
+    settings: ConnectionSettings,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Duration,
+    access_log: Option<Arc<AccessLog>>,
+}
+
+/// Stops a running [`Server`], can be cloned and sent to other threads
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutting_down: Arc<AtomicBool>,
+    address: SocketAddr,
+}
+
+/// What the server did before it shut down
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ServerSummary {
+    pub connections_accepted: usize,
+    pub threads: ShutdownSummary,
+}
+
+/// Limits applied to every connection the server handles
+#[derive(Debug, Copy, Clone)]
+pub(super) struct ConnectionSettings {
+    pub(super) max_body_size: usize,
+    pub(super) max_line_length: usize,
+    pub(super) max_header_size: usize,
+    pub(super) keep_alive_timeout: Duration,
+    pub(super) read_timeout: Duration,
+    pub(super) header_timeout: Duration,
+    pub(super) body_timeout: Duration,
+    pub(super) write_timeout: Duration,
+    /// Whether clients can switch to HTTP/2 on unencrypted connections
+    pub(super) http2: bool,
+}
+
+/// The default maximum size of a request body, 1 MiB
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+/// The default time to wait for the next request on a persistent connection
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The default time to wait for each read once a request has started
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// The default time allowed for the request line and headers to arrive
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default time allowed for a request body to arrive
+const DEFAULT_BODY_TIMEOUT: Duration = Duration::from_secs(30);
+/// The default time to wait for the client to accept more of a response
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default time to wait for in-flight requests to finish when shutting down
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often idle connections check whether the server is shutting down
+pub(super) const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The number of threads kept ready to handle connections
+const MIN_THREADS: u32 = 8;
+/// The most threads started to handle connections when busy
+const MAX_THREADS: u32 = 32;
+/// The number of accepted connections that can wait for a free thread, beyond which clients receive a 503
+const CONNECTION_QUEUE_CAPACITY: usize = 64;
+/// The body of the response to a connection turned away because the server is too busy
+const OVERLOADED_BODY: &str = "The server is too busy, please try again later";
+
+impl ShutdownHandle {
+    /// Stop accepting connections, causing [`Server::serve`] to finish in-flight requests and return
+    pub fn shutdown(&self) {
+        if !self.shutting_down.swap(true, Ordering::SeqCst) {
+            // wake up the listener, which is blocked waiting for the next connection
+            let _ = TcpStream::connect(self.address);
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
 }
 
-impl<F: Fn(HttpRequest) -> HttpResponse + Send + Sync + 'static> Server<F> {
-    pub fn new(listener: TcpListener, response_fn: F) -> Self {
+impl<H: Handler> Server<H, TcpAcceptor> {
+    /// Create a server for the listener
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listener's address can't be read.
+    pub fn new(listener: TcpListener, handler: H) -> Self {
+        let config = PoolConfig::new(MIN_THREADS)
+            .with_max_threads(MAX_THREADS)
+            .with_queue(CONNECTION_QUEUE_CAPACITY, RejectionPolicy::Error);
+        Server::with_pool_config(listener, handler, config)
+    }
+
+    /// Create a server that handles connections on a thread pool with the given config
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listener's address can't be read, or the config is invalid.
+    pub fn with_pool_config(listener: TcpListener, handler: H, config: PoolConfig) -> Self {
+        Server::with_thread_pool(listener, handler, ThreadPool::from_config(config))
+    }
+
+    /// Create a server that handles connections on the given thread pool
+    ///
+    /// Connections are answered with a 503 when the pool's queue is full, if its [`RejectionPolicy`] is `Error`.
+    /// The handlers of HTTP/2 streams also run on the pool, alongside their connection's thread,
+    /// and their requests are answered with a 503 when the queue is full whatever the policy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listener's address can't be read.
+    pub fn with_thread_pool(listener: TcpListener, handler: H, thread_pool: ThreadPool) -> Self {
+        let mut address = listener
+            .local_addr()
+            .expect("The listener should be bound to an address");
+        // connecting to the unspecified address isn't portable, so wake the listener through loopback instead
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
         Server {
             listener,
-            thread_pool: ThreadPool::new(8),
-            response_fn: Arc::new(response_fn),
+            acceptor: Arc::new(TcpAcceptor),
+            thread_pool,
+            handler: MiddlewareStack::new(handler),
+            settings: ConnectionSettings::default(),
+            shutdown: ShutdownHandle {
+                shutting_down: Arc::new(AtomicBool::new(false)),
+                address,
+            },
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            access_log: Some(Arc::new(AccessLog::new(LogFormat::Common, StdoutSink))),
         }
     }
+}
+
+impl<H: Handler, A: Acceptor> Server<H, A> {
+    /// Set up each accepted connection with the acceptor, e.g. [`TlsAcceptor`](crate::http::TlsAcceptor) to serve HTTPS
+    pub fn with_acceptor<B: Acceptor>(self, acceptor: B) -> Server<H, B> {
+        Server {
+            listener: self.listener,
+            acceptor: Arc::new(acceptor),
+            thread_pool: self.thread_pool,
+            handler: self.handler,
+            settings: self.settings,
+            shutdown: self.shutdown,
+            shutdown_timeout: self.shutdown_timeout,
+            access_log: self.access_log,
+        }
+    }
+
+    /// Set the maximum size of a request body, larger requests receive a 413 response
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.settings.max_body_size = max_body_size;
+        self
+    }
+
+    /// Set how long an idle persistent connection is kept open waiting for the next request
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn with_keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> Self {
+        assert!(
+            !keep_alive_timeout.is_zero(),
+            "The keep alive timeout must be non-zero"
+        );
+        self.settings.keep_alive_timeout = keep_alive_timeout;
+        self
+    }
+
+    /// Set the longest request line or header line, longer request lines receive a 414 response
+    /// and longer header lines a 431
+    pub fn with_max_line_length(mut self, max_line_length: usize) -> Self {
+        self.settings.max_line_length = max_line_length;
+        self
+    }
+
+    /// Set the maximum size of the request line and headers together, larger requests receive a 431 response
+    pub fn with_max_header_size(mut self, max_header_size: usize) -> Self {
+        self.settings.max_header_size = max_header_size;
+        self
+    }
+
+    /// Set how long each read waits for more of a request once it has started
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        assert!(!read_timeout.is_zero(), "The read timeout must be non-zero");
+        self.settings.read_timeout = read_timeout;
+        self
+    }
+
+    /// Set how long a client has to send the request line and headers, otherwise it receives a 408 response
+    ///
+    /// This stops clients holding a thread by sending their headers slowly, however often each piece arrives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn with_header_timeout(mut self, header_timeout: Duration) -> Self {
+        assert!(
+            !header_timeout.is_zero(),
+            "The header timeout must be non-zero"
+        );
+        self.settings.header_timeout = header_timeout;
+        self
+    }
+
+    /// Set how long a client has to send a request body, otherwise it receives a 408 response
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn with_body_timeout(mut self, body_timeout: Duration) -> Self {
+        assert!(!body_timeout.is_zero(), "The body timeout must be non-zero");
+        self.settings.body_timeout = body_timeout;
+        self
+    }
+
+    /// Set how long writing a response waits for the client to accept more data before the connection is closed
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        assert!(
+            !write_timeout.is_zero(),
+            "The write timeout must be non-zero"
+        );
+        self.settings.write_timeout = write_timeout;
+        self
+    }
+
+    /// Set whether clients can speak HTTP/2 over unencrypted connections, which they can by default
+    ///
+    /// Clients either start with the HTTP/2 connection preface, or ask to upgrade an HTTP/1.1 request with
+    /// `Upgrade: h2c`. Each request on an HTTP/2 connection is handled as its own job on the thread pool,
+    /// so slow responses don't hold up others on the same connection.
+    pub fn with_http2(mut self, http2: bool) -> Self {
+        self.settings.http2 = http2;
+        self
+    }
+
+    /// Set how long shutting down waits for in-flight requests to finish
+    pub fn with_shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Add a layer of middleware, inside the layers that were already added
+    pub fn with_middleware(mut self, middleware: impl Middleware) -> Self {
+        self.handler = self.handler.wrap(middleware);
+        self
+    }
 
-    /// Start listening and responding to messages
-    pub fn serve(self) {
+    /// Set where and how requests are logged, by default they are written to stdout in the Common Log Format
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// Stop logging requests
+    pub fn without_access_log(mut self) -> Self {
+        self.access_log = None;
+        self
+    }
+
+    /// A handle that can stop the server once it is serving
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Start listening and responding to messages, until shut down by a [`ShutdownHandle`]
+    pub fn serve(self) -> ServerSummary {
+        let handler = Arc::new(self.handler);
+        let mut connections_accepted = 0;
         // Each stream is a connection between the client and the server
-        // In TCP, for each request received from client we need to send a response, then close the connection
+        // HTTP/1.1 connections stay open for further requests until either side asks to close them
         for stream in self.listener.incoming() {
-            println!("Received new tcpstream");
+            if self.shutdown.is_shutting_down() {
+                break;
+            }
             match stream {
                 Ok(stream) => {
-                    let cloned_fn = Arc::clone(&self.response_fn);
-                    self.thread_pool
-                        .execute(move || Server::handle_connection(stream, cloned_fn.as_ref()))
+                    connections_accepted += 1;
+                    let handler = Arc::clone(&handler);
+                    let pool = self.thread_pool.handle();
+                    let acceptor = Arc::clone(&self.acceptor);
+                    let settings = self.settings;
+                    let shutdown = self.shutdown.clone();
+                    let access_log = self.access_log.clone();
+                    // keep a handle to the connection, to tell the client if there's no room for it
+                    let overflow_stream = stream.try_clone();
+                    let result = self.thread_pool.execute(move || {
+                        let connection = match acceptor.accept(stream) {
+                            Ok(connection) => connection,
+                            Err(err) => {
+                                eprintln!("Failed to accept connection, received error: {err}");
+                                return;
+                            }
+                        };
+                        handle_connection(
+                            connection,
+                            &handler,
+                            &pool,
+                            settings,
+                            &shutdown,
+                            access_log.as_deref(),
+                        )
+                    });
+                    if let (Err(ExecuteError::QueueFull), Ok(stream)) = (result, overflow_stream) {
+                        let peer = stream.peer_addr().ok();
+                        let time = SystemTime::now();
+                        self.acceptor.reject(stream);
+                        if let Some(access_log) = &self.access_log {
+                            access_log.log(&AccessLogEntry {
+                                peer,
+                                time,
+                                method: None,
+                                path: None,
+                                version: None,
+                                status: HttpStatus::ServiceUnavailable503,
+                                bytes_sent: OVERLOADED_BODY.len(),
+                                latency: time.elapsed().unwrap_or_default(),
+                                referer: None,
+                                user_agent: None,
+                            });
+                        }
+                    }
                 }
-                Err(err) => println!("Failed to read connection, received error: {}", err.kind()),
+                Err(err) => eprintln!("Failed to read connection, received error: {}", err.kind()),
             }
         }
-    }
 
-    fn handle_connection(mut stream: TcpStream, response_fn: &F) {
-        println!("Connection established!");
-
-        let buf_reader = BufReader::new(&stream);
-        let request_contents = buf_reader
-            .lines()
-            .map(|result| result.unwrap())
-            .take_while(|line| !line.is_empty());
+        println!("Shutting down, waiting for in-flight requests to finish");
+        ServerSummary {
+            connections_accepted,
+            threads: self.thread_pool.shutdown(self.shutdown_timeout),
+        }
+    }
+}
 
-        match HttpRequest::from_lines(request_contents) {
-            Ok(request) => {
-                println!("Request: {request:#?}");
-                let response = response_fn(request);
-                stream.write_all(response.to_string().as_bytes()).unwrap();
+fn handle_connection(
+    connection: impl Connection,
+    handler: &Arc<impl Handler>,
+    pool: &PoolHandle,
+    settings: ConnectionSettings,
+    shutdown: &ShutdownHandle,
+    access_log: Option<&AccessLog>,
+) {
+    let peer = connection.peer_addr().ok();
+    let allows_http2 = settings.http2 && !connection.is_secure() && connection.socket().is_some();
+    if let Err(err) = connection.set_write_timeout(Some(settings.write_timeout)) {
+        eprintln!(
+            "Failed to prepare connection, received error: {}",
+            err.kind()
+        );
+        return;
+    }
+    // Requests are handled one at a time, so pipelined requests are answered in the order they were sent
+    // Responses are written straight to the connection, bypassing the read buffer
+    let mut buf_reader = BufReader::new(TimedConnection::new(connection));
+    let mut is_first_request = true;
+    loop {
+        if !wait_for_request(&mut buf_reader, settings.keep_alive_timeout, shutdown) {
+            return;
+        }
+        let started = Instant::now();
+        let time = SystemTime::now();
+        let keep_alive = match read_request(&mut buf_reader, settings) {
+            // the rest of the connection is HTTP/2, starting with the response to this request
+            Ok((request, None)) if allows_http2 && http2::is_upgrade(&request) => {
+                let start = http2::Start::Upgrade(Box::new(request));
+                return http2::serve(
+                    buf_reader, start, handler, pool, settings, shutdown, access_log,
+                );
             }
-            Err(err) => {
-                let error_message = format!("Invalid request: {err:?}");
-                let response = HttpResponse {
-                    version: HttpVersion::Http1_1,
-                    status: HttpStatus::BadRequest400,
-                    content: error_message,
+            Ok((request, decoder)) => {
+                let request_keep_alive = request.keep_alive();
+                let version = request.version();
+                let method = request.method();
+                let path = request.raw_target().to_string();
+                let referer = request.header("Referer").map(String::from);
+                let user_agent = request.header("User-Agent").map(String::from);
+                let (mut response, is_body_read) = match decoder {
+                    Some(decoder) => {
+                        let (response, reader, is_body_read) =
+                            handle_streamed(handler.as_ref(), request, buf_reader, decoder);
+                        buf_reader = reader;
+                        (response, is_body_read)
+                    }
+                    None => (handle_request(handler.as_ref(), request), true),
                 };
-                stream.write_all(response.to_string().as_bytes()).unwrap();
+                // only a response switching protocols can hand over the connection
+                let upgrade = response
+                    .upgrade
+                    .take()
+                    .filter(|_| response.status == HttpStatus::SwitchingProtocols101);
+                let is_framed = frame_streamed_body(&mut response, version);
+                let keep_alive = request_keep_alive
+                    && is_body_read
+                    && is_framed
+                    && !closes_connection(&response)
+                    && !shutdown.is_shutting_down();
+                if upgrade.is_none() {
+                    set_connection_header(&mut response, version, keep_alive);
+                }
+                let result = write_response(
+                    buf_reader.get_mut(),
+                    &mut response,
+                    method == HttpMethod::Head,
+                );
+                if let (Some(access_log), Ok(bytes_sent)) = (access_log, &result) {
+                    access_log.log(&AccessLogEntry {
+                        peer,
+                        time,
+                        method: Some(method),
+                        path: Some(path),
+                        version: Some(version),
+                        status: response.status,
+                        bytes_sent: *bytes_sent,
+                        latency: started.elapsed(),
+                        referer,
+                        user_agent,
+                    });
+                }
+                match (upgrade, result) {
+                    // the rest of the connection belongs to the WebSocket handler
+                    (Some(upgrade), Ok(_)) => {
+                        return websocket::serve(
+                            buf_reader,
+                            upgrade,
+                            settings.max_body_size,
+                            shutdown,
+                        );
+                    }
+                    (_, result) => result.map(|_| keep_alive),
+                }
+            }
+            // the client knows the server speaks HTTP/2, so it started with the connection preface
+            Err(RequestParseError::InvalidStartLine(StartLineParseError::Http2Preface))
+                if allows_http2 && is_first_request =>
+            {
+                let start = http2::Start::PriorKnowledge;
+                return http2::serve(
+                    buf_reader, start, handler, pool, settings, shutdown, access_log,
+                );
+            }
+            // the client closed the connection
+            Err(RequestParseError::MissingStartLine) => return,
+            Err(
+                RequestParseError::ConnectionError(kind)
+                | RequestParseError::InvalidBody(BodyParseError::ConnectionError(kind)),
+            ) if !is_timeout(kind) => {
+                eprintln!("Failed to read request, received error: {kind}");
+                return;
+            }
+            Err(err) => {
+                // the rest of the stream can't be framed after an invalid request, so close the connection
+                let mut response = error_response(&err).header("Connection", "close");
+                let result = write_response(buf_reader.get_mut(), &mut response, false);
+                if let (Some(access_log), Ok(bytes_sent)) = (access_log, &result) {
+                    access_log.log(&AccessLogEntry {
+                        peer,
+                        time,
+                        method: None,
+                        path: None,
+                        version: None,
+                        status: response.status,
+                        bytes_sent: *bytes_sent,
+                        latency: started.elapsed(),
+                        referer: None,
+                        user_agent: None,
+                    });
+                }
+                result.map(|_| false)
+            }
+        };
+        is_first_request = false;
+        match keep_alive {
+            Ok(true) => continue,
+            Ok(false) => return,
+            Err(err) => {
+                eprintln!("Failed to write response, received error: {}", err.kind());
+                return;
             }
         }
     }
 }
+
+/// Read the next request, which must arrive within the header and body timeouts
+///
+/// A multipart form's body may hold large files, so it's left on the connection, to be read by the returned
+/// decoder as the handler decodes the form.
+fn read_request(
+    reader: &mut BufReader<TimedConnection<impl Connection>>,
+    settings: ConnectionSettings,
+) -> Result<(HttpRequest, Option<BodyDecoder>), RequestParseError> {
+    let connection = reader.get_mut();
+    connection.set_read_timeout(Some(settings.read_timeout));
+    connection.set_deadline(Some(Instant::now() + settings.header_timeout));
+    let mut request =
+        HttpRequest::read_head(reader, settings.max_line_length, settings.max_header_size)?;
+    reader
+        .get_mut()
+        .set_deadline(Some(Instant::now() + settings.body_timeout));
+    if is_multipart_form(request.header("Content-Type")) {
+        let decoder = request.body_decoder(settings.max_body_size)?;
+        return Ok((request, Some(decoder)));
+    }
+    request.read_body(reader, settings.max_body_size)?;
+    Ok((request, None))
+}
+
+/// The connection, lent to a request whose body is read as it's used
+struct LentBody<R> {
+    /// Taken back once the handler has responded
+    reader: Option<R>,
+    decoder: BodyDecoder,
+    failed: bool,
+}
+
+/// Reads the body of a request from the connection it's lent
+struct LentBodyReader<R>(Arc<Mutex<LentBody<R>>>);
+
+impl<R: BufRead> Read for LentBodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut lent = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let LentBody {
+            reader,
+            decoder,
+            failed,
+        } = &mut *lent;
+        // the request was kept after its response, when the connection has moved on
+        let reader = reader.as_mut().ok_or(ErrorKind::NotConnected)?;
+        decoder.read(reader, buf).map_err(|err| {
+            *failed = true;
+            err.into()
+        })
+    }
+}
+
+/// Handle a request whose body is read by the handler as it's used, lending it the connection until it responds
+///
+/// The connection is returned along with whether the whole body was read, skipping any the handler left,
+/// so the next request can be read from it.
+fn handle_streamed<R: BufRead + Send + 'static>(
+    handler: &impl Handler,
+    mut request: HttpRequest,
+    reader: R,
+    decoder: BodyDecoder,
+) -> (HttpResponse, R, bool) {
+    let lent = Arc::new(Mutex::new(LentBody {
+        reader: Some(reader),
+        decoder,
+        failed: false,
+    }));
+    request.set_body_reader(LentBodyReader(Arc::clone(&lent)));
+    let response = handle_request(handler, request);
+
+    let mut lent = lent.lock().unwrap_or_else(PoisonError::into_inner);
+    let LentBody {
+        reader,
+        decoder,
+        failed,
+    } = &mut *lent;
+    let mut reader = reader
+        .take()
+        .expect("the connection is only taken back once");
+    let mut buf = [0; 8 * 1024];
+    let is_read = !*failed
+        && loop {
+            match decoder.read(&mut reader, &mut buf) {
+                Ok(0) => break true,
+                Ok(_) => {}
+                Err(_) => break false,
+            }
+        };
+    (response, reader, is_read)
+}
+
+/// The response to a request that couldn't be read
+pub(super) fn error_response(err: &RequestParseError) -> HttpResponse {
+    let status = match err {
+        RequestParseError::InvalidBody(BodyParseError::TooLarge { .. }) => {
+            HttpStatus::ContentTooLarge413
+        }
+        RequestParseError::StartLineTooLong { .. } => HttpStatus::UriTooLong414,
+        RequestParseError::InvalidStartLine(
+            StartLineParseError::UnsupportedHttpVersion | StartLineParseError::Http2Preface,
+        ) => HttpStatus::HttpVersionNotSupported505,
+        RequestParseError::HeadersTooLarge { .. } => HttpStatus::RequestHeaderFieldsTooLarge431,
+        RequestParseError::InvalidBody(BodyParseError::UnsupportedTransferEncoding(_)) => {
+            HttpStatus::NotImplemented501
+        }
+        // the client was too slow to send the request
+        RequestParseError::ConnectionError(_)
+        | RequestParseError::InvalidBody(BodyParseError::ConnectionError(_)) => {
+            HttpStatus::RequestTimeout408
+        }
+        _ => HttpStatus::BadRequest400,
+    };
+    HttpResponse::new(status).body(format!("Invalid request: {err:?}"))
+}
+
+/// Run the handler, responding with a 500 if it panics
+///
+/// The connection is closed after a panic, in case the handler left it in an unknown state
+pub(super) fn handle_request(handler: &impl Handler, request: HttpRequest) -> HttpResponse {
+    panic::catch_unwind(AssertUnwindSafe(|| handler.handle(request))).unwrap_or_else(|payload| {
+        eprintln!(
+            "The handler panicked with: {}",
+            panic_message(payload.as_ref())
+        );
+        HttpResponse::new(HttpStatus::InternalServerError500)
+            .header("Connection", "close")
+            .body("The server failed to handle the request")
+    })
+}
+
+/// Tell a client the server is too busy to handle its connection
+pub(super) fn reject_overloaded(stream: &mut impl Write) {
+    eprintln!("Rejecting connection, all threads are busy and the queue is full");
+    let mut response = overloaded_response().header("Connection", "close");
+    if let Err(err) = write_response(stream, &mut response, false) {
+        eprintln!("Failed to write response, received error: {}", err.kind());
+    }
+}
+
+/// The response to a request there's no thread to handle
+pub(super) fn overloaded_response() -> HttpResponse {
+    HttpResponse::new(HttpStatus::ServiceUnavailable503)
+        .header("Retry-After", "1")
+        .body(OVERLOADED_BODY)
+}
+
+/// Wait for the next request to start arriving, returning false if the connection should be closed instead
+///
+/// Connections are closed when the client closes them, they are idle for the keep alive timeout,
+/// or the server is shutting down
+fn wait_for_request(
+    reader: &mut BufReader<TimedConnection<impl Connection>>,
+    keep_alive_timeout: Duration,
+    shutdown: &ShutdownHandle,
+) -> bool {
+    let deadline = Instant::now() + keep_alive_timeout;
+    let connection = reader.get_mut();
+    connection.set_deadline(Some(deadline));
+    // wake up periodically to check whether the server is shutting down
+    connection.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL));
+    // a pipelined request may already be buffered
+    while reader.buffer().is_empty() {
+        if shutdown.is_shutting_down() || Instant::now() >= deadline {
+            return false;
+        }
+        match reader.fill_buf() {
+            Ok([]) => return false,
+            Ok(_) => break,
+            Err(err) if is_timeout(err.kind()) => {}
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// Whether a read failed because it timed out, which is reported differently on different platforms
+pub(super) fn is_timeout(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Whether the handler asked for the connection to be closed after its response
+fn closes_connection(response: &HttpResponse) -> bool {
+    response
+        .headers
+        .get_list("Connection")
+        .any(|option| option.eq_ignore_ascii_case("close"))
+}
+
+/// Tell the client whether the connection will stay open, where that differs from the default for its version
+fn set_connection_header(response: &mut HttpResponse, version: HttpVersion, keep_alive: bool) {
+    match (version, keep_alive) {
+        (HttpVersion::Http1, true) => response.headers.insert("Connection", "keep-alive"),
+        (HttpVersion::Http1, false) => {}
+        (_, false) => response.headers.insert("Connection", "close"),
+        (_, true) => {}
+    }
+}
+
+/// Mark where a streamed body ends, returning false if only closing the connection can
+///
+/// Bodies of unknown length are sent with chunked transfer coding, which HTTP/1.0 clients don't understand.
+fn frame_streamed_body(response: &mut HttpResponse, version: HttpVersion) -> bool {
+    if !response.is_streaming()
+        || !response.status_allows_body()
+        || response.headers.contains("Content-Length")
+    {
+        return true;
+    }
+    if version == HttpVersion::Http1 {
+        return false;
+    }
+    response.headers.insert("Transfer-Encoding", "chunked");
+    true
+}
+
+/// Write the response, returning the length of the body sent
+fn write_response(
+    stream: &mut impl Write,
+    response: &mut HttpResponse,
+    is_head: bool,
+) -> std::io::Result<usize> {
+    let bytes_sent = if is_head || !response.status_allows_body() {
+        response.write_head_to(stream)?;
+        0
+    } else if response.is_streaming() {
+        // send the head straight away, since the body may take a while to start
+        response.write_head_to(stream)?;
+        stream.flush()?;
+        let chunked = response
+            .headers
+            .get_list("Transfer-Encoding")
+            .any(|coding| coding.eq_ignore_ascii_case("chunked"));
+        let body = response.stream.take();
+        body.map_or(Ok(0), |body| body.write_to(stream, chunked))?
+    } else {
+        response.write_to(stream)?;
+        response.body.len()
+    };
+    stream.flush()?;
+    Ok(bytes_sent)
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        ConnectionSettings {
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            max_header_size: DEFAULT_MAX_HEAD_SIZE,
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            body_timeout: DEFAULT_BODY_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            http2: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Read;
+    use std::thread;
+
+    fn respond(mut request: HttpRequest) -> HttpResponse {
+        match request.path().as_str() {
+            "/panic" => panic!("the handler failed"),
+            "/stream" => HttpResponse::ok().body_chunks(["Hello", "", ", world"]),
+            "/form" => match request.form() {
+                Ok(form) => {
+                    let file = form.file("upload").unwrap();
+                    let content = fs::read_to_string(file.path()).unwrap();
+                    HttpResponse::ok().body(format!("{}: {content}", file.file_name()))
+                }
+                Err(err) => err.into(),
+            },
+            path => HttpResponse::ok().body(path),
+        }
+    }
+
+    /// Serve a single connection on a background thread, returning the client side of the connection
+    fn connect(settings: ConnectionSettings) -> TcpStream {
+        connect_with_log(settings, None)
+    }
+
+    fn connect_with_log(settings: ConnectionSettings, access_log: Option<AccessLog>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let shutdown = ShutdownHandle {
+                shutting_down: Arc::new(AtomicBool::new(false)),
+                address,
+            };
+            let pool = ThreadPool::new(2);
+            handle_connection(
+                stream,
+                &Arc::new(respond),
+                &pool.handle(),
+                settings,
+                &shutdown,
+                access_log.as_ref(),
+            );
+        });
+        TcpStream::connect(address).unwrap()
+    }
+
+    fn settings() -> ConnectionSettings {
+        ConnectionSettings::default()
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let mut client = connect(settings());
+        client
+            .write_all(
+                b"GET /first HTTP/1.1\r\n\r\nGET /second HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut responses = String::new();
+        // the server closes the connection after the second request
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(
+            responses,
+            "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\n/first\
+             HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 7\r\n\r\n/second"
+        );
+    }
+
+    #[test]
+    fn test_multipart_upload() {
+        let body =
+            "--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\r\n\
+                    hello\r\n--XyZ--";
+        let upload = |path: &str| {
+            format!(
+                "POST {path} HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+        };
+        // the body a handler ignores is skipped, to reach the next request
+        let mut client = connect(settings());
+        client
+            .write_all(
+                format!(
+                    "{}{}GET /last HTTP/1.1\r\nConnection: close\r\n\r\n",
+                    upload("/form"),
+                    upload("/ignored")
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(
+            responses,
+            "HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\na.txt: hello\
+             HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\n/ignored\
+             HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\n/last"
+        );
+
+        // a chunked body is only found to be too large as the form is read
+        let mut client = connect(ConnectionSettings {
+            max_body_size: 16,
+            ..settings()
+        });
+        client
+            .write_all(
+                format!(
+                    "POST /form HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n\
+                     Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{body}\r\n0\r\n\r\n",
+                    body.len()
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 413 Content Too Large\r\nConnection: close\r\n"),
+            "{response}"
+        );
+    }
+
+    #[test]
+    fn test_streamed_body() {
+        let mut client = connect(settings());
+        client
+            .write_all(
+                b"GET /stream HTTP/1.1\r\n\r\nHEAD /stream HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        // empty chunks are skipped, since one would end the body
+        assert_eq!(
+            responses,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n7\r\n, world\r\n0\r\n\r\n\
+             HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+        );
+
+        // HTTP/1.0 clients don't understand chunked transfer coding, so the body ends with the connection
+        let mut client = connect(settings());
+        client
+            .write_all(b"GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "HTTP/1.1 200 OK\r\n\r\nHello, world");
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let mut client = connect(settings());
+        client
+            .write_all(b"GET /panic HTTP/1.1\r\n\r\nGET /unanswered HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(
+            responses,
+            "HTTP/1.1 500 Internal Server Error\r\nConnection: close\r\nContent-Length: 39\r\n\r\n\
+             The server failed to handle the request"
+        );
+    }
+
+    #[test]
+    fn test_transfer_codings() {
+        let mut client = connect(settings());
+        client
+            .write_all(b"POST /gzip HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\nConnection: close\r\n"));
+
+        // a request framed both ways is answered, but nothing after it is trusted
+        let mut client = connect(settings());
+        client
+            .write_all(
+                b"POST /both HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n\
+                  0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\n/both"
+        );
+    }
+
+    #[test]
+    fn test_access_log() {
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_lines = Arc::clone(&lines);
+        let sink = move |line: &str| {
+            sink_lines.lock().unwrap().push(line.to_string());
+            Ok(())
+        };
+        let mut client =
+            connect_with_log(settings(), Some(AccessLog::new(LogFormat::Combined, sink)));
+        client
+            .write_all(
+                b"GET /logged HTTP/1.1\r\nUser-Agent: test\r\n\r\n\
+                  HEAD /head HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        client.read_to_end(&mut Vec::new()).unwrap();
+
+        {
+            let lines = lines.lock().unwrap();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].starts_with("127.0.0.1 - - ["));
+            assert!(lines[0].ends_with("] \"GET /logged HTTP/1.1\" 200 7 \"-\" \"test\""));
+            assert!(lines[1].ends_with("] \"HEAD /head HTTP/1.1\" 200 - \"-\" \"-\""));
+        }
+
+        // requests that couldn't be read are logged too, without a request line
+        let sink_lines = Arc::clone(&lines);
+        let sink = move |line: &str| {
+            sink_lines.lock().unwrap().push(line.to_string());
+            Ok(())
+        };
+        let access_log = AccessLog::new(LogFormat::Common, sink).with_errors_only(true);
+        let mut client = connect_with_log(settings(), Some(access_log));
+        client.write_all(b"BAD\r\n\r\n").unwrap();
+        client.read_to_end(&mut Vec::new()).unwrap();
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].contains("] \"-\" 400 "));
+    }
+
+    #[test]
+    fn test_slow_headers_time_out() {
+        let mut client = connect(ConnectionSettings {
+            header_timeout: Duration::from_millis(300),
+            ..settings()
+        });
+        let started = Instant::now();
+        // each byte arrives well within the read timeout, but the headers never finish
+        for byte in b"GET /slow HTTP/1.1\r\nX" {
+            client.write_all(&[*byte]).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\nConnection: close\r\n"));
+        assert!(started.elapsed() < DEFAULT_READ_TIMEOUT);
+    }
+
+    #[test]
+    fn test_slow_body_times_out() {
+        let mut client = connect(ConnectionSettings {
+            body_timeout: Duration::from_millis(100),
+            ..settings()
+        });
+        client
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let settings = ConnectionSettings {
+            max_line_length: 32,
+            max_header_size: 64,
+            ..settings()
+        };
+        let respond_to = |raw_request: &str| {
+            let mut client = connect(settings);
+            client.write_all(raw_request.as_bytes()).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+        let long_path = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(40));
+        assert!(respond_to(&long_path).starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+        let long_header = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(40));
+        assert!(respond_to(&long_header).starts_with("HTTP/1.1 431 Request Header Fields"));
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "Accept: */*\r\n".repeat(6));
+        assert!(respond_to(&many_headers).starts_with("HTTP/1.1 431 Request Header Fields"));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut client = connect(ConnectionSettings {
+            http2: false,
+            ..settings()
+        });
+        client
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+    }
+
+    #[test]
+    fn test_keep_alive_timeout() {
+        let mut client = connect(ConnectionSettings {
+            keep_alive_timeout: Duration::from_millis(50),
+            ..settings()
+        });
+        client.write_all(b"GET /idle HTTP/1.1\r\n\r\n").unwrap();
+        let mut responses = String::new();
+        // the server closes the connection once it has been idle for the timeout
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(
+            responses,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n/idle"
+        );
+    }
+
+    #[test]
+    fn test_http1_keep_alive_and_head() {
+        let mut client = connect(settings());
+        client
+            .write_all(
+                b"HEAD /head HTTP/1\r\nConnection: keep-alive\r\n\r\nGET /get HTTP/1\r\n\r\n",
+            )
+            .unwrap();
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(
+            responses,
+            "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 5\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n/get"
+        );
+    }
+
+    #[test]
+    fn test_full_pool_rejects_connections() {
+        let config = PoolConfig::new(1).with_queue(1, RejectionPolicy::Error);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::with_pool_config(listener, respond, config);
+        let address = server.shutdown.address;
+        let shutdown = server.shutdown_handle();
+        let serving = thread::spawn(move || server.serve());
+
+        // the only thread is held by a keep alive connection, and the next connection fills the queue
+        let mut busy = TcpStream::connect(address).unwrap();
+        busy.write_all(b"GET /busy HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = [0; 64];
+        let _ = busy.read(&mut response).unwrap();
+        let _queued = TcpStream::connect(address).unwrap();
+
+        let mut rejected = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\n"));
+
+        shutdown.shutdown();
+        assert_eq!(serving.join().unwrap().connections_accepted, 3);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let server = Server::new(TcpListener::bind("127.0.0.1:0").unwrap(), respond);
+        let address = server.shutdown.address;
+        let shutdown = server.shutdown_handle();
+        let serving = thread::spawn(move || server.serve());
+
+        // an idle keep alive connection doesn't hold up the shutdown
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"GET /before HTTP/1.1\r\n\r\n").unwrap();
+        let expected = b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n/before";
+        let mut response = [0; 64];
+        client.read_exact(&mut response[..expected.len()]).unwrap();
+        assert_eq!(&response[..expected.len()], expected);
+
+        shutdown.shutdown();
+        let summary = serving.join().unwrap();
+        assert_eq!(summary.connections_accepted, 1);
+        assert_eq!(
+            summary.threads,
+            ShutdownSummary {
+                joined: 8,
+                abandoned: 0
+            }
+        );
+        // the idle connection was closed
+        assert_eq!(client.read(&mut response).unwrap(), 0);
+    }
+}
@@ -0,0 +1,454 @@
+mod sha256;
+
+use crate::http::base64;
+use crate::http::headers::is_token_char;
+use crate::http::{Cookie, HttpRequest, HttpResponse, Middleware, Next, SameSite};
+use sha256::hmac_sha256;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How often expired sessions are swept from the store, rather than waiting for their clients to come back
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Middleware giving each client a [`Session`], kept in memory and found again with a signed cookie
+///
+/// The cookie holds only a random session id, signed with HMAC-SHA256 so that ids the server didn't issue are
+/// rejected. Sessions are created once a handler stores something in one, and are dropped after going unused for
+/// the idle timeout or when the server stops.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{HttpRequest, HttpResponse, MiddlewareStack, Sessions};
+/// let stack = MiddlewareStack::new(|request: HttpRequest| {
+///     let session = request.session().unwrap();
+///     let visits = session
+///         .get("visits")
+///         .and_then(|visits| visits.parse::<u32>().ok())
+///         .unwrap_or(0);
+///     session.insert("visits", (visits + 1).to_string());
+///     HttpResponse::ok().body(format!("Visits before this one: {visits}"))
+/// })
+/// .wrap(Sessions::new(b"a secret key of at least 32 bytes").with_secure(true));
+/// ```
+pub struct Sessions {
+    key: Vec<u8>,
+    cookie_name: String,
+    idle_timeout: Duration,
+    secure: bool,
+    store: Mutex<Store>,
+}
+
+/// The data stored for a client between requests, which handlers find with [`HttpRequest::session`]
+///
+/// Changes are saved once the response has been produced.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    values: HashMap<String, String>,
+    changed: bool,
+    /// Whether the data moves to a new id
+    renew: bool,
+}
+
+#[derive(Debug)]
+struct Store {
+    sessions: HashMap<String, StoredSession>,
+    next_sweep: Instant,
+}
+
+#[derive(Debug)]
+struct StoredSession {
+    values: HashMap<String, String>,
+    expires: Instant,
+}
+
+impl Sessions {
+    const DEFAULT_COOKIE_NAME: &str = "session";
+    const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+    /// The shortest key accepted, as long as the signatures it makes
+    const MIN_KEY_LENGTH: usize = 32;
+
+    /// Sessions whose ids are signed with the key, which must be kept secret and should be random
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is shorter than 32 bytes.
+    pub fn new(key: &[u8]) -> Self {
+        assert!(
+            key.len() >= Sessions::MIN_KEY_LENGTH,
+            "The session key must be at least {} bytes",
+            Sessions::MIN_KEY_LENGTH
+        );
+        Sessions {
+            key: key.to_vec(),
+            cookie_name: Sessions::DEFAULT_COOKIE_NAME.to_string(),
+            idle_timeout: Sessions::DEFAULT_IDLE_TIMEOUT,
+            secure: false,
+            store: Mutex::new(Store {
+                sessions: HashMap::new(),
+                next_sweep: Instant::now() + SWEEP_INTERVAL,
+            }),
+        }
+    }
+
+    /// Set the name of the cookie holding the session id, `session` by default
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name isn't a valid cookie name.
+    pub fn with_cookie_name(mut self, cookie_name: &str) -> Self {
+        assert!(
+            !cookie_name.is_empty() && cookie_name.bytes().all(is_token_char),
+            "Invalid cookie name {cookie_name:?}"
+        );
+        self.cookie_name = cookie_name.to_string();
+        self
+    }
+
+    /// Drop sessions that haven't been used for this long, by default a day
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timeout is zero.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        assert!(
+            !idle_timeout.is_zero(),
+            "The session idle timeout must be non-zero"
+        );
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Whether the cookie is only sent over HTTPS, which should be set whenever clients connect with it
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The cookie value for the id, which is followed by its signature
+    fn sign(&self, id: &str) -> String {
+        let signature = hmac_sha256(&self.key, id.as_bytes());
+        format!("{id}.{}", base64::encode_url(&signature))
+    }
+
+    /// The id in the cookie value, if it was signed with the key
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, signature) = value.rsplit_once('.')?;
+        let signature = base64::decode_url(signature)?;
+        constant_time_eq(&hmac_sha256(&self.key, id.as_bytes()), &signature).then_some(id)
+    }
+
+    /// The id and data of the session the cookie value refers to, unless it's forged, unknown or expired
+    fn load(&self, value: &str) -> Option<(String, HashMap<String, String>)> {
+        let id = self.verify(value)?;
+        let mut store = self.store();
+        let stored = store.sessions.get(id)?;
+        if stored.expires <= Instant::now() {
+            store.sessions.remove(id);
+            return None;
+        }
+        Some((id.to_string(), stored.values.clone()))
+    }
+
+    /// Save the session's data once the request has been handled, telling the client about any new id
+    fn save(
+        &self,
+        id: Option<String>,
+        state: SessionState,
+        had_cookie: bool,
+        response: HttpResponse,
+    ) -> HttpResponse {
+        let now = Instant::now();
+        let expires = now + self.idle_timeout;
+        let mut store = self.store();
+        if store.next_sweep <= now {
+            store.sessions.retain(|_, stored| stored.expires > now);
+            store.next_sweep = now + SWEEP_INTERVAL;
+        }
+
+        // an empty session isn't worth keeping, and the client doesn't need a cookie for it
+        if state.values.is_empty() {
+            if let Some(id) = id {
+                store.sessions.remove(&id);
+            }
+            return if had_cookie {
+                response.set_cookie(&self.cookie(Cookie::removal(&self.cookie_name)))
+            } else {
+                response
+            };
+        }
+        match id {
+            Some(id) if !state.renew => {
+                if state.changed {
+                    let values = state.values;
+                    store.sessions.insert(id, StoredSession { values, expires });
+                } else if let Some(stored) = store.sessions.get_mut(&id) {
+                    stored.expires = expires;
+                }
+                response
+            }
+            old_id => {
+                if let Some(old_id) = old_id {
+                    store.sessions.remove(&old_id);
+                }
+                let id = new_session_id();
+                let cookie = Cookie::new(&self.cookie_name, &self.sign(&id));
+                let values = state.values;
+                store.sessions.insert(id, StoredSession { values, expires });
+                response.set_cookie(&self.cookie(cookie))
+            }
+        }
+    }
+
+    /// Add the attributes every session cookie is sent with
+    fn cookie(&self, cookie: Cookie) -> Cookie {
+        let cookie = cookie.path("/").http_only().same_site(SameSite::Lax);
+        if self.secure {
+            cookie.secure()
+        } else {
+            cookie
+        }
+    }
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the key is left out, so it doesn't end up in logs
+        f.debug_struct("Sessions")
+            .field("cookie_name", &self.cookie_name)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("secure", &self.secure)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, mut request: HttpRequest, next: Next<'_>) -> HttpResponse {
+        let values: Vec<&str> = request
+            .cookies()
+            .filter(|(name, _)| *name == self.cookie_name)
+            .map(|(_, value)| value)
+            .collect();
+        let had_cookie = !values.is_empty();
+        let (id, values) = values
+            .into_iter()
+            .find_map(|value| self.load(value))
+            .unzip();
+        let session = Session::new(values.unwrap_or_default());
+        request.set_session(session.clone());
+        let response = next.run(request);
+        let state = mem::take(&mut *session.state());
+        self.save(id, state, had_cookie, response)
+    }
+}
+
+impl Session {
+    fn new(values: HashMap<String, String>) -> Session {
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                values,
+                ..SessionState::default()
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().values.get(key).cloned()
+    }
+
+    /// Store the value, replacing any already stored with the key
+    pub fn insert(&self, key: &str, value: impl Into<String>) {
+        let mut state = self.state();
+        state.values.insert(key.to_string(), value.into());
+        state.changed = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state();
+        state.changed = true;
+        state.values.remove(key)
+    }
+
+    /// End the session, removing its data and the client's cookie, e.g. when logging out
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.values.clear();
+        state.changed = true;
+    }
+
+    /// Move the data to a new id, which should be done when logging in so an id an attacker planted is left behind
+    pub fn renew(&self) {
+        self.state().renew = true;
+    }
+}
+
+impl PartialEq for Session {
+    /// Handles are equal when they share their data
+    fn eq(&self, other: &Session) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl Eq for Session {}
+
+/// A new session id of 128 bits, as hex digits
+///
+/// The bits are hashes of a counter, with std's SipHash keyed once from the operating system's random source.
+fn new_session_id() -> String {
+    static HASHER: OnceLock<RandomState> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let hasher = HASHER.get_or_init(RandomState::new);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!(
+        "{:016x}{:016x}",
+        hasher.hash_one((count, 0)),
+        hasher.hash_one((count, 1))
+    )
+}
+
+/// Compare in a time that doesn't depend on where the inputs differ, so a forger can't learn a signature byte by byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Handler, MiddlewareStack};
+    use std::thread;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    /// Stores the `user` query parameter, `?logout` destroys the session, and `?login` renews it
+    fn stack(sessions: Sessions) -> impl Handler {
+        MiddlewareStack::new(|request: HttpRequest| {
+            let session = request.session().unwrap();
+            if let Some(user) = request.query().get("user") {
+                session.insert("user", user);
+            }
+            if request.query().contains("login") {
+                session.renew();
+            }
+            if request.query().contains("logout") {
+                session.destroy();
+            }
+            HttpResponse::ok().body(session.get("user").unwrap_or_default())
+        })
+        .wrap(sessions)
+    }
+
+    /// Send a request with the session cookie, returning the body and the cookie set, if any
+    fn get(handler: &impl Handler, target: &str, cookie: Option<&str>) -> (String, Option<String>) {
+        let mut lines = vec![format!("GET {target} HTTP/1.1")];
+        if let Some(cookie) = cookie {
+            lines.push(format!("Cookie: other=1; {cookie}"));
+        }
+        let response = handler.handle(HttpRequest::from_lines(lines.into_iter()).unwrap());
+        let set_cookie = response.headers.get("Set-Cookie").map(str::to_string);
+        (String::from_utf8(response.body).unwrap(), set_cookie)
+    }
+
+    /// The `name=value` part of a `Set-Cookie` header
+    fn cookie_pair(set_cookie: &str) -> &str {
+        set_cookie.split(';').next().unwrap()
+    }
+
+    #[test]
+    fn test_sessions() {
+        let handler = stack(Sessions::new(KEY));
+        // no session is created until there's something in it
+        assert_eq!(get(&handler, "/", None), (String::new(), None));
+
+        let (_, set_cookie) = get(&handler, "/?user=ann", None);
+        let set_cookie = set_cookie.unwrap();
+        assert!(set_cookie.starts_with("session="));
+        assert!(set_cookie.ends_with("; Path=/; HttpOnly; SameSite=Lax"));
+        let cookie = cookie_pair(&set_cookie);
+        assert_eq!(get(&handler, "/", Some(cookie)), ("ann".to_string(), None));
+
+        // renewing keeps the data under a new id, and the old id no longer works
+        let (body, renewed) = get(&handler, "/?login", Some(cookie));
+        assert_eq!(body, "ann");
+        let renewed = renewed.unwrap();
+        let renewed = cookie_pair(&renewed);
+        assert_ne!(renewed, cookie);
+        assert_eq!(get(&handler, "/", Some(renewed)).0, "ann");
+        assert_eq!(get(&handler, "/", Some(cookie)).0, "");
+
+        let (body, removal) = get(&handler, "/?logout", Some(renewed));
+        assert_eq!(body, "");
+        assert!(removal.unwrap().starts_with("session=; Path=/; Expires="));
+        assert_eq!(get(&handler, "/", Some(renewed)).0, "");
+    }
+
+    #[test]
+    fn test_forged_ids() {
+        let handler = stack(Sessions::new(KEY).with_cookie_name("id"));
+        let (_, set_cookie) = get(&handler, "/?user=ann", None);
+        let set_cookie = set_cookie.unwrap();
+        let cookie = cookie_pair(&set_cookie);
+        let (id, signature) = cookie.strip_prefix("id=").unwrap().split_once('.').unwrap();
+
+        let other_key = stack(Sessions::new(&[b'k'; 32]).with_cookie_name("id"));
+        assert_eq!(get(&other_key, "/", Some(cookie)).0, "");
+        for forged in [
+            id.to_string(),
+            format!("{id}.{}", &signature[1..]),
+            // another id, with the signature of the real one
+            format!(
+                "{}{}.{signature}",
+                if id.starts_with('a') { 'b' } else { 'a' },
+                &id[1..]
+            ),
+        ] {
+            let (body, removal) = get(&handler, "/", Some(&format!("id={forged}")));
+            assert_eq!(body, "");
+            // the client is told to forget the cookie
+            assert!(removal.unwrap().starts_with("id=;"));
+        }
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let sessions = Sessions::new(KEY)
+            .with_idle_timeout(Duration::from_millis(50))
+            .with_secure(true);
+        let handler = stack(sessions);
+        let (_, set_cookie) = get(&handler, "/?user=ann", None);
+        let set_cookie = set_cookie.unwrap();
+        assert!(set_cookie.contains("; Secure"));
+        let cookie = cookie_pair(&set_cookie);
+        // each use of the session keeps it alive
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(30));
+            assert_eq!(get(&handler, "/", Some(cookie)).0, "ann");
+        }
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(get(&handler, "/", Some(cookie)).0, "");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_short_key() {
+        let _ = Sessions::new(b"too short");
+    }
+}
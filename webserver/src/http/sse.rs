@@ -0,0 +1,226 @@
+use crate::http::HttpResponse;
+use std::fmt::{self, Display};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// A comment, which clients ignore, sent to keep an otherwise quiet stream alive
+const HEARTBEAT: &[u8] = b": heartbeat\n\n";
+
+/// An event sent to the client in a `text/event-stream` body, as used by the browser's `EventSource`
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::Event;
+/// let event = Event::new("first line\nsecond line").id("42").event("update");
+/// assert_eq!(
+///     event.to_string(),
+///     "id: 42\nevent: update\ndata: first line\ndata: second line\n\n"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    data: String,
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<Duration>,
+}
+
+/// Server-Sent Events received from a channel, and streamed to the client as they arrive
+///
+/// While no events arrive, heartbeat comments are sent so that proxies don't close the connection as idle, and so
+/// that the server notices when the client has gone. The stream ends once every sender has been dropped.
+/// Clients reconnecting after losing the connection send the last id they saw in a `Last-Event-ID` header.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::mpsc;
+/// use std::thread;
+/// use webserver::http::{Event, EventStream, HttpRequest, Router};
+/// let router = Router::new().get("/events", |_request: HttpRequest| {
+///     let (sender, receiver) = mpsc::channel();
+///     thread::spawn(move || {
+///         for i in 0..3 {
+///             let _ = sender.send(Event::new(format!("Tick {i}")).id(&i.to_string()));
+///         }
+///     });
+///     EventStream::new(receiver).into_response()
+/// });
+/// ```
+#[derive(Debug)]
+pub struct EventStream {
+    events: Receiver<Event>,
+    heartbeat: Option<Duration>,
+}
+
+impl Event {
+    /// An event with the data, which can span multiple lines
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            id: None,
+            event: None,
+            retry: None,
+        }
+    }
+
+    /// Set the id the client reports when reconnecting, so it can be sent the events it missed
+    ///
+    /// # Panics
+    ///
+    /// Panics if the id contains a line break or NUL.
+    pub fn id(mut self, id: &str) -> Event {
+        assert!(
+            !id.contains(['\r', '\n', '\0']),
+            "Event ids can't contain line breaks or NUL"
+        );
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Set the type of the event, which picks the client's listener instead of the default `message`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the type contains a line break.
+    pub fn event(mut self, event: &str) -> Event {
+        assert!(
+            !event.contains(['\r', '\n']),
+            "Event types can't contain line breaks"
+        );
+        self.event = Some(event.to_string());
+        self
+    }
+
+    /// Set how long the client waits before reconnecting if the connection is lost
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {id}")?;
+        }
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {event}")?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        // each line of the data is a field of its own, which the client joins back together
+        let data = self.data.replace("\r\n", "\n");
+        for line in data.split(['\r', '\n']) {
+            writeln!(f, "data: {line}")?;
+        }
+        writeln!(f)
+    }
+}
+
+impl EventStream {
+    /// Long enough to add little traffic, and short enough for common proxy idle timeouts
+    const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+
+    /// Stream the events sent on the channel
+    pub fn new(events: Receiver<Event>) -> Self {
+        EventStream {
+            events,
+            heartbeat: Some(EventStream::DEFAULT_HEARTBEAT),
+        }
+    }
+
+    /// Send a heartbeat whenever no event has been sent for this long
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is zero.
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "The heartbeat interval must be non-zero"
+        );
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    /// Never send heartbeats, so a client that has gone is only noticed when the next event is sent
+    pub fn without_heartbeat(mut self) -> Self {
+        self.heartbeat = None;
+        self
+    }
+
+    /// A response streaming the events, which caches mustn't store
+    pub fn into_response(self) -> HttpResponse {
+        HttpResponse::ok()
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .body_chunks(self)
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = Vec<u8>;
+
+    /// Wait for the next event, or a heartbeat if none arrives in time
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let event = match self.heartbeat {
+            Some(interval) => match self.events.recv_timeout(interval) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => return Some(HEARTBEAT.to_vec()),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            },
+            None => self.events.recv().ok()?,
+        };
+        Some(event.to_string().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_event_format() {
+        assert_eq!(Event::new("Hello").to_string(), "data: Hello\n\n");
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+        let event = Event::new("a\r\nb\rc\n")
+            .event("update")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            event.to_string(),
+            "event: update\nretry: 3000\ndata: a\ndata: b\ndata: c\ndata: \n\n"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_id() {
+        let _ = Event::new("data").id("line\nbreak");
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let (sender, receiver) = mpsc::channel();
+        let mut stream = EventStream::new(receiver).with_heartbeat(Duration::from_millis(10));
+        sender.send(Event::new("first").id("1")).unwrap();
+        assert_eq!(stream.next(), Some(b"id: 1\ndata: first\n\n".to_vec()));
+        // nothing was sent, so a heartbeat keeps the connection alive
+        assert_eq!(stream.next(), Some(HEARTBEAT.to_vec()));
+        sender.send(Event::new("last")).unwrap();
+        drop(sender);
+        assert_eq!(stream.next(), Some(b"data: last\n\n".to_vec()));
+        assert_eq!(stream.next(), None);
+
+        let (_sender, receiver) = mpsc::channel();
+        let response = EventStream::new(receiver).into_response();
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/event-stream")
+        );
+        assert!(response.is_streaming());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::http::conditional::conditional_file_response;
+use crate::http::date::format_http_date;
+use crate::http::mime::mime_type;
+use crate::http::url::{percent_encode_path, percent_encode_segment};
+use crate::http::{Handler, HttpMethod, HttpRequest, HttpResponse, HttpStatus};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Serves the files in a root directory to requests with paths under a URL prefix
+///
+/// For example `StaticFiles::new("/assets", "public")` serves `/assets/css/site.css` from `public/css/site.css`.
+/// Requests for a directory are served its `index.html`, or optionally a listing of its contents.
+/// Paths that would escape the root directory, including through symlinks, are rejected with a 403.
+/// Files are served with an `ETag` and `Last-Modified` date, so support conditional and range requests,
+/// and are read as they're sent rather than held in memory.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{Router, StaticFiles};
+/// let router = Router::new().get("/assets/*path", StaticFiles::new("/assets", "public"));
+/// ```
+pub struct StaticFiles {
+    prefix: String,
+    root: PathBuf,
+    directory_listing: bool,
+    not_found_page: Option<PathBuf>,
+}
+
+impl StaticFiles {
+    pub fn new(prefix: &str, root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root: root.into(),
+            directory_listing: false,
+            not_found_page: None,
+        }
+    }
+
+    /// List the contents of directories that have no `index.html`, instead of responding with a 403
+    pub fn with_directory_listing(mut self, directory_listing: bool) -> StaticFiles {
+        self.directory_listing = directory_listing;
+        self
+    }
+
+    /// Serve the file (relative to the root directory) as the body of 404 responses
+    pub fn with_not_found_page(mut self, page: impl Into<PathBuf>) -> StaticFiles {
+        self.not_found_page = Some(page.into());
+        self
+    }
+
+    /// Map the request path onto a path under the root, or the status to respond with if it can't be
+    fn resolve(&self, request_path: &str) -> Result<PathBuf, HttpStatus> {
+        let relative_path = request_path
+            .strip_prefix(&self.prefix)
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+            .ok_or(HttpStatus::NotFound404)?;
+
+        let mut path = self.root.clone();
+        for segment in relative_path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+        {
+            // the request path is already decoded and normalised, but a backslash is a separator on Windows
+            if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+                return Err(HttpStatus::Forbidden403);
+            }
+            path.push(segment);
+        }
+
+        // resolving symlinks shows whether the path really is inside the root
+        let root = self
+            .root
+            .canonicalize()
+            .map_err(|_| HttpStatus::NotFound404)?;
+        let path = path.canonicalize().map_err(|err| status_for(&err))?;
+        if path.starts_with(root) {
+            Ok(path)
+        } else {
+            Err(HttpStatus::Forbidden403)
+        }
+    }
+
+    fn serve_directory(&self, request: &HttpRequest, directory: &Path) -> HttpResponse {
+        let request_path = request.path();
+        // relative links in the page only resolve correctly if the directory path ends with a slash
+        if !request_path.ends_with('/') {
+            let mut location = format!("{}/", percent_encode_path(request_path));
+            if let Some((_, query)) = request.raw_target().split_once('?') {
+                location.push('?');
+                location.push_str(query);
+            }
+            return HttpResponse::new(HttpStatus::MovedPermanently301)
+                .header("Location", &location);
+        }
+        let index = directory.join("index.html");
+        if index.is_file() {
+            return self.serve_file(request, &index);
+        }
+        if !self.directory_listing {
+            return self.error_response(HttpStatus::Forbidden403);
+        }
+        match directory_listing(request_path, directory) {
+            Ok(listing) => HttpResponse::ok()
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(listing),
+            Err(err) => self.error_response(status_for(&err)),
+        }
+    }
+
+    fn serve_file(&self, request: &HttpRequest, path: &Path) -> HttpResponse {
+        let file_response = File::open(path).and_then(|file| {
+            let metadata = file.metadata()?;
+            let length = usize::try_from(metadata.len())
+                .map_err(|_| io::Error::from(ErrorKind::FileTooLarge))?;
+            let modified = metadata.modified()?;
+            // the size and modification time change whenever the file is edited, so make a cheap validator
+            let modified_nanos = modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let etag = format!("\"{:x}-{:x}\"", metadata.len(), modified_nanos);
+            let response = HttpResponse::ok()
+                .header("Content-Type", mime_type(path))
+                .header("ETag", &etag)
+                .header("Last-Modified", &format_http_date(modified));
+            Ok(conditional_file_response(request, response, file, length))
+        });
+        file_response.unwrap_or_else(|err| self.error_response(status_for(&err)))
+    }
+
+    fn error_response(&self, status: HttpStatus) -> HttpResponse {
+        let not_found_page = self
+            .not_found_page
+            .as_ref()
+            .filter(|_| status == HttpStatus::NotFound404)
+            .and_then(|page| fs::read(self.root.join(page)).ok());
+        match not_found_page {
+            Some(page) => HttpResponse::not_found()
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(page),
+            None => HttpResponse::new(status),
+        }
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: HttpRequest) -> HttpResponse {
+        if !matches!(request.method(), HttpMethod::Get | HttpMethod::Head) {
+            return HttpResponse::new(HttpStatus::MethodNotAllowed405).header("Allow", "GET, HEAD");
+        }
+        match self.resolve(request.path()) {
+            Ok(path) if path.is_dir() => self.serve_directory(&request, &path),
+            Ok(path) => self.serve_file(&request, &path),
+            Err(status) => self.error_response(status),
+        }
+    }
+}
+
+fn status_for(err: &io::Error) -> HttpStatus {
+    match err.kind() {
+        ErrorKind::NotFound => HttpStatus::NotFound404,
+        ErrorKind::PermissionDenied => HttpStatus::Forbidden403,
+        _ => HttpStatus::InternalServerError500,
+    }
+}
+
+/// Render an HTML page linking to every entry of the directory
+fn directory_listing(request_path: &str, directory: &Path) -> std::io::Result<String> {
+    let mut entries = fs::read_dir(directory)?
+        .map(|entry| {
+            let entry = entry?;
+            let mut name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() {
+                name.push('/');
+            }
+            Ok(name)
+        })
+        .collect::<std::io::Result<Vec<String>>>()?;
+    entries.sort();
+
+    let title = html_escape(request_path);
+    let mut listing = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<ul>\n"
+    );
+    if request_path != "/" {
+        listing.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in entries {
+        // the name is a single segment of the link, even if it contains characters like `?`, `#` or `:`
+        let href = match name.strip_suffix('/') {
+            Some(directory) => format!("{}/", percent_encode_segment(directory)),
+            None => percent_encode_segment(&name),
+        };
+        let (href, name) = (html_escape(&href), html_escape(&name));
+        listing.push_str(&format!("<li><a href=\"{href}\">{name}</a></li>\n"));
+    }
+    listing.push_str("</ul>\n</body>\n</html>\n");
+    Ok(listing)
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A directory of files to serve, which is removed once the test is done with it
+    struct TempRoot(PathBuf);
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Create a fresh directory containing some files to serve
+    fn create_root() -> TempRoot {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "webserver_static_files_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(root.join("docs/empty")).unwrap();
+        fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(root.join("missing.html"), "Not here").unwrap();
+        fs::write(root.join("docs/guide & notes.txt"), "Guide").unwrap();
+        fs::write(root.join("docs/faq #1.txt"), "FAQ").unwrap();
+        TempRoot(root)
+    }
+
+    fn get_with_headers(files: &StaticFiles, path: &str, headers: &[String]) -> HttpResponse {
+        let lines = std::iter::once(format!("GET {path} HTTP/1.1")).chain(headers.iter().cloned());
+        files.handle(HttpRequest::from_lines(lines).unwrap())
+    }
+
+    fn get(files: &StaticFiles, path: &str) -> HttpResponse {
+        get_with_headers(files, path, &[])
+    }
+
+    /// The whole body of the response, reading it from the file if it's streamed
+    fn body(mut response: HttpResponse) -> Vec<u8> {
+        let mut body = std::mem::take(&mut response.body);
+        if let Some(mut stream) = response.stream.take() {
+            while let Some(chunk) = stream.next_chunk() {
+                body.extend(chunk.unwrap());
+            }
+        }
+        body
+    }
+
+    #[test]
+    fn test_serve_files() {
+        let root = create_root();
+        let files = StaticFiles::new("/static", &root.0);
+
+        let response = get(&files, "/static/index.html");
+        assert_eq!(response.status, HttpStatus::Ok200);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.headers.get("Content-Length"), Some("13"));
+        assert!(response.is_streaming());
+        assert_eq!(body(response), b"<h1>Home</h1>");
+
+        let response = get(&files, "/static/docs/guide%20&%20notes.txt?download=1");
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(body(response), b"Guide");
+    }
+
+    #[test]
+    fn test_conditional_requests() {
+        let root = create_root();
+        let files = StaticFiles::new("/", &root.0);
+        let response = get(&files, "/index.html");
+        let etag = response.headers.get("ETag").unwrap();
+        let last_modified = response.headers.get("Last-Modified").unwrap();
+
+        let response = get_with_headers(&files, "/index.html", &[format!("If-None-Match: {etag}")]);
+        assert_eq!(response.status, HttpStatus::NotModified304);
+        let response = get_with_headers(
+            &files,
+            "/index.html",
+            &[format!("If-Modified-Since: {last_modified}")],
+        );
+        assert_eq!(response.status, HttpStatus::NotModified304);
+
+        let response = get_with_headers(&files, "/", &["Range: bytes=4-7".to_string()]);
+        assert_eq!(response.status, HttpStatus::PartialContent206);
+        assert_eq!(response.headers.get("Content-Length"), Some("4"));
+        assert_eq!(body(response), b"Home");
+
+        // each range is read from its place in the file
+        let response = get_with_headers(&files, "/", &["Range: bytes=0-3, -5".to_string()]);
+        assert_eq!(response.status, HttpStatus::PartialContent206);
+        let content_type = response.headers.get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let length: usize = response
+            .headers
+            .get("Content-Length")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let expected = format!(
+            "--{boundary}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Range: bytes 0-3/13\r\n\r\n<h1>\r\n\
+             --{boundary}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Range: bytes 8-12/13\r\n\r\n</h1>\r\n\
+             --{boundary}--\r\n"
+        );
+        assert_eq!(length, expected.len());
+        assert_eq!(String::from_utf8(body(response)).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_serve_directories() {
+        let root = create_root();
+        let files = StaticFiles::new("/static/", &root.0);
+        assert_eq!(body(get(&files, "/static/")), b"<h1>Home</h1>");
+        assert_eq!(
+            get(&files, "/static").status,
+            HttpStatus::MovedPermanently301
+        );
+        assert_eq!(
+            get(&files, "/static/docs").headers.get("Location"),
+            Some("/static/docs/")
+        );
+        assert_eq!(
+            get(&files, "/static/docs?sort=name")
+                .headers
+                .get("Location"),
+            Some("/static/docs/?sort=name")
+        );
+        assert_eq!(
+            get(&files, "/static/docs/").status,
+            HttpStatus::Forbidden403
+        );
+
+        let files = files.with_directory_listing(true);
+        let listing = String::from_utf8(get(&files, "/static/docs/").body).unwrap();
+        assert!(listing.contains("<a href=\"empty/\">empty/</a>"));
+        assert!(listing.contains("<a href=\"guide%20&amp;%20notes.txt\">guide &amp; notes.txt</a>"));
+        assert!(listing.contains("<a href=\"faq%20%231.txt\">faq #1.txt</a>"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let root = create_root();
+        let files = StaticFiles::new("/static", &root.0);
+        assert_eq!(
+            get(&files, "/static/nope.css").status,
+            HttpStatus::NotFound404
+        );
+        assert_eq!(get(&files, "/staticfile").status, HttpStatus::NotFound404);
+        // parent references are resolved when the request is parsed, so they can't leave the prefix
+        assert_eq!(
+            get(&files, "/static/../Cargo.toml").status,
+            HttpStatus::NotFound404
+        );
+        assert_eq!(
+            get(&files, "/static/docs/%2e%2e/%2E%2E/secret").status,
+            HttpStatus::NotFound404
+        );
+        assert_eq!(
+            get(&files, "/static/docs/../../static/index.html").status,
+            HttpStatus::Ok200
+        );
+        assert_eq!(
+            get(&files, "/static/docs\\..\\..\\Cargo.toml").status,
+            HttpStatus::Forbidden403
+        );
+
+        let files = files.with_not_found_page("missing.html");
+        let response = get(&files, "/static/nope.css");
+        assert_eq!(response.status, HttpStatus::NotFound404);
+        assert_eq!(response.body, b"Not here");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escape() {
+        let root = create_root();
+        let outside = create_root();
+        std::os::unix::fs::symlink(outside.0.join("index.html"), root.0.join("escape.html"))
+            .unwrap();
+        let files = StaticFiles::new("/", &root.0);
+        assert_eq!(get(&files, "/escape.html").status, HttpStatus::Forbidden403);
+    }
+}
@@ -2,6 +2,8 @@ use std::fmt::{Display, Formatter};
 
 /// Serializable representation of an HTTP Status
 ///
+/// Covers every status code in the IANA HTTP Status Code Registry, with `Custom` for any other code.
+///
 /// # Examples
 ///
 /// ```
@@ -10,27 +12,336 @@ use std::fmt::{Display, Formatter};
 /// assert_eq!(HttpStatus::BadRequest400.to_string(), "400 Bad Request".to_string());
 /// assert_eq!(HttpStatus::NotFound404.to_string(), "404 Not Found".to_string());
 /// ```
-#[derive(Debug, PartialEq, Eq)]
+///
+/// Can be converted from a numeric code
+/// ```
+/// use webserver::http::HttpStatus;
+/// assert_eq!(HttpStatus::try_from(503), Ok(HttpStatus::ServiceUnavailable503));
+/// assert_eq!(HttpStatus::try_from(299), Err(()));
+/// assert_eq!(HttpStatus::custom(299, "Custom").to_string(), "299 Custom".to_string());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HttpStatus {
+    Continue100,
+    SwitchingProtocols101,
+    Processing102,
+    EarlyHints103,
     Ok200,
+    Created201,
+    Accepted202,
+    NonAuthoritativeInformation203,
+    NoContent204,
+    ResetContent205,
+    PartialContent206,
+    MultiStatus207,
+    AlreadyReported208,
+    ImUsed226,
+    MultipleChoices300,
+    MovedPermanently301,
+    Found302,
+    SeeOther303,
+    NotModified304,
+    UseProxy305,
+    TemporaryRedirect307,
+    PermanentRedirect308,
     BadRequest400,
+    Unauthorized401,
+    PaymentRequired402,
+    Forbidden403,
     NotFound404,
+    MethodNotAllowed405,
+    NotAcceptable406,
+    ProxyAuthenticationRequired407,
+    RequestTimeout408,
+    Conflict409,
+    Gone410,
+    LengthRequired411,
+    PreconditionFailed412,
+    ContentTooLarge413,
+    UriTooLong414,
+    UnsupportedMediaType415,
+    RangeNotSatisfiable416,
+    ExpectationFailed417,
+    MisdirectedRequest421,
+    UnprocessableContent422,
+    Locked423,
+    FailedDependency424,
+    TooEarly425,
+    UpgradeRequired426,
+    PreconditionRequired428,
+    TooManyRequests429,
+    RequestHeaderFieldsTooLarge431,
+    UnavailableForLegalReasons451,
+    InternalServerError500,
+    NotImplemented501,
+    BadGateway502,
+    ServiceUnavailable503,
+    GatewayTimeout504,
+    HttpVersionNotSupported505,
+    VariantAlsoNegotiates506,
+    InsufficientStorage507,
+    LoopDetected508,
+    NotExtended510,
+    NetworkAuthenticationRequired511,
+    Custom { code: u16, phrase: String },
 }
 
 impl HttpStatus {
+    /// Create a status with a code and reason phrase that aren't in the registry
+    ///
+    /// Codes in the registry give their own variant, with its registered phrase.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the code is not three digits, or the phrase contains control characters other than tab,
+    /// which could otherwise end the status line and inject headers.
+    pub fn custom(code: u16, phrase: &str) -> HttpStatus {
+        assert!(
+            (100..=999).contains(&code),
+            "Status codes must be three digits, found: {code}"
+        );
+        assert!(
+            !phrase.chars().any(|c| c.is_control() && c != '\t'),
+            "Reason phrases can't contain control characters, found: {phrase:?}"
+        );
+        if let Ok(status) = HttpStatus::try_from(code) {
+            return status;
+        }
+        HttpStatus::Custom {
+            code,
+            phrase: phrase.to_string(),
+        }
+    }
+
     pub fn status_code(&self) -> u16 {
         match self {
+            HttpStatus::Continue100 => 100,
+            HttpStatus::SwitchingProtocols101 => 101,
+            HttpStatus::Processing102 => 102,
+            HttpStatus::EarlyHints103 => 103,
             HttpStatus::Ok200 => 200,
+            HttpStatus::Created201 => 201,
+            HttpStatus::Accepted202 => 202,
+            HttpStatus::NonAuthoritativeInformation203 => 203,
+            HttpStatus::NoContent204 => 204,
+            HttpStatus::ResetContent205 => 205,
+            HttpStatus::PartialContent206 => 206,
+            HttpStatus::MultiStatus207 => 207,
+            HttpStatus::AlreadyReported208 => 208,
+            HttpStatus::ImUsed226 => 226,
+            HttpStatus::MultipleChoices300 => 300,
+            HttpStatus::MovedPermanently301 => 301,
+            HttpStatus::Found302 => 302,
+            HttpStatus::SeeOther303 => 303,
+            HttpStatus::NotModified304 => 304,
+            HttpStatus::UseProxy305 => 305,
+            HttpStatus::TemporaryRedirect307 => 307,
+            HttpStatus::PermanentRedirect308 => 308,
             HttpStatus::BadRequest400 => 400,
+            HttpStatus::Unauthorized401 => 401,
+            HttpStatus::PaymentRequired402 => 402,
+            HttpStatus::Forbidden403 => 403,
             HttpStatus::NotFound404 => 404,
+            HttpStatus::MethodNotAllowed405 => 405,
+            HttpStatus::NotAcceptable406 => 406,
+            HttpStatus::ProxyAuthenticationRequired407 => 407,
+            HttpStatus::RequestTimeout408 => 408,
+            HttpStatus::Conflict409 => 409,
+            HttpStatus::Gone410 => 410,
+            HttpStatus::LengthRequired411 => 411,
+            HttpStatus::PreconditionFailed412 => 412,
+            HttpStatus::ContentTooLarge413 => 413,
+            HttpStatus::UriTooLong414 => 414,
+            HttpStatus::UnsupportedMediaType415 => 415,
+            HttpStatus::RangeNotSatisfiable416 => 416,
+            HttpStatus::ExpectationFailed417 => 417,
+            HttpStatus::MisdirectedRequest421 => 421,
+            HttpStatus::UnprocessableContent422 => 422,
+            HttpStatus::Locked423 => 423,
+            HttpStatus::FailedDependency424 => 424,
+            HttpStatus::TooEarly425 => 425,
+            HttpStatus::UpgradeRequired426 => 426,
+            HttpStatus::PreconditionRequired428 => 428,
+            HttpStatus::TooManyRequests429 => 429,
+            HttpStatus::RequestHeaderFieldsTooLarge431 => 431,
+            HttpStatus::UnavailableForLegalReasons451 => 451,
+            HttpStatus::InternalServerError500 => 500,
+            HttpStatus::NotImplemented501 => 501,
+            HttpStatus::BadGateway502 => 502,
+            HttpStatus::ServiceUnavailable503 => 503,
+            HttpStatus::GatewayTimeout504 => 504,
+            HttpStatus::HttpVersionNotSupported505 => 505,
+            HttpStatus::VariantAlsoNegotiates506 => 506,
+            HttpStatus::InsufficientStorage507 => 507,
+            HttpStatus::LoopDetected508 => 508,
+            HttpStatus::NotExtended510 => 510,
+            HttpStatus::NetworkAuthenticationRequired511 => 511,
+            HttpStatus::Custom { code, .. } => *code,
         }
     }
 
     pub fn status_phrase(&self) -> String {
         match self {
-            HttpStatus::Ok200 => "OK".to_string(),
-            HttpStatus::BadRequest400 => "Bad Request".to_string(),
-            HttpStatus::NotFound404 => "Not Found".to_string(),
+            HttpStatus::Continue100 => "Continue",
+            HttpStatus::SwitchingProtocols101 => "Switching Protocols",
+            HttpStatus::Processing102 => "Processing",
+            HttpStatus::EarlyHints103 => "Early Hints",
+            HttpStatus::Ok200 => "OK",
+            HttpStatus::Created201 => "Created",
+            HttpStatus::Accepted202 => "Accepted",
+            HttpStatus::NonAuthoritativeInformation203 => "Non-Authoritative Information",
+            HttpStatus::NoContent204 => "No Content",
+            HttpStatus::ResetContent205 => "Reset Content",
+            HttpStatus::PartialContent206 => "Partial Content",
+            HttpStatus::MultiStatus207 => "Multi-Status",
+            HttpStatus::AlreadyReported208 => "Already Reported",
+            HttpStatus::ImUsed226 => "IM Used",
+            HttpStatus::MultipleChoices300 => "Multiple Choices",
+            HttpStatus::MovedPermanently301 => "Moved Permanently",
+            HttpStatus::Found302 => "Found",
+            HttpStatus::SeeOther303 => "See Other",
+            HttpStatus::NotModified304 => "Not Modified",
+            HttpStatus::UseProxy305 => "Use Proxy",
+            HttpStatus::TemporaryRedirect307 => "Temporary Redirect",
+            HttpStatus::PermanentRedirect308 => "Permanent Redirect",
+            HttpStatus::BadRequest400 => "Bad Request",
+            HttpStatus::Unauthorized401 => "Unauthorized",
+            HttpStatus::PaymentRequired402 => "Payment Required",
+            HttpStatus::Forbidden403 => "Forbidden",
+            HttpStatus::NotFound404 => "Not Found",
+            HttpStatus::MethodNotAllowed405 => "Method Not Allowed",
+            HttpStatus::NotAcceptable406 => "Not Acceptable",
+            HttpStatus::ProxyAuthenticationRequired407 => "Proxy Authentication Required",
+            HttpStatus::RequestTimeout408 => "Request Timeout",
+            HttpStatus::Conflict409 => "Conflict",
+            HttpStatus::Gone410 => "Gone",
+            HttpStatus::LengthRequired411 => "Length Required",
+            HttpStatus::PreconditionFailed412 => "Precondition Failed",
+            HttpStatus::ContentTooLarge413 => "Content Too Large",
+            HttpStatus::UriTooLong414 => "URI Too Long",
+            HttpStatus::UnsupportedMediaType415 => "Unsupported Media Type",
+            HttpStatus::RangeNotSatisfiable416 => "Range Not Satisfiable",
+            HttpStatus::ExpectationFailed417 => "Expectation Failed",
+            HttpStatus::MisdirectedRequest421 => "Misdirected Request",
+            HttpStatus::UnprocessableContent422 => "Unprocessable Content",
+            HttpStatus::Locked423 => "Locked",
+            HttpStatus::FailedDependency424 => "Failed Dependency",
+            HttpStatus::TooEarly425 => "Too Early",
+            HttpStatus::UpgradeRequired426 => "Upgrade Required",
+            HttpStatus::PreconditionRequired428 => "Precondition Required",
+            HttpStatus::TooManyRequests429 => "Too Many Requests",
+            HttpStatus::RequestHeaderFieldsTooLarge431 => "Request Header Fields Too Large",
+            HttpStatus::UnavailableForLegalReasons451 => "Unavailable For Legal Reasons",
+            HttpStatus::InternalServerError500 => "Internal Server Error",
+            HttpStatus::NotImplemented501 => "Not Implemented",
+            HttpStatus::BadGateway502 => "Bad Gateway",
+            HttpStatus::ServiceUnavailable503 => "Service Unavailable",
+            HttpStatus::GatewayTimeout504 => "Gateway Timeout",
+            HttpStatus::HttpVersionNotSupported505 => "HTTP Version Not Supported",
+            HttpStatus::VariantAlsoNegotiates506 => "Variant Also Negotiates",
+            HttpStatus::InsufficientStorage507 => "Insufficient Storage",
+            HttpStatus::LoopDetected508 => "Loop Detected",
+            HttpStatus::NotExtended510 => "Not Extended",
+            HttpStatus::NetworkAuthenticationRequired511 => "Network Authentication Required",
+            HttpStatus::Custom { phrase, .. } => phrase,
+        }
+        .to_string()
+    }
+
+    /// A 1xx status, the request was received and is still being processed
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.status_code())
+    }
+
+    /// A 2xx status, the request was successfully handled
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status_code())
+    }
+
+    /// A 3xx status, the client needs to take further action to complete the request
+    pub fn is_redirect(&self) -> bool {
+        (300..400).contains(&self.status_code())
+    }
+
+    /// A 4xx status, the request was invalid
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.status_code())
+    }
+
+    /// A 5xx status, the server failed to handle a valid request
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.status_code())
+    }
+}
+
+/// Convert a code from the registry, use [`HttpStatus::custom`] for any other code
+impl TryFrom<u16> for HttpStatus {
+    type Error = ();
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        match code {
+            100 => Ok(HttpStatus::Continue100),
+            101 => Ok(HttpStatus::SwitchingProtocols101),
+            102 => Ok(HttpStatus::Processing102),
+            103 => Ok(HttpStatus::EarlyHints103),
+            200 => Ok(HttpStatus::Ok200),
+            201 => Ok(HttpStatus::Created201),
+            202 => Ok(HttpStatus::Accepted202),
+            203 => Ok(HttpStatus::NonAuthoritativeInformation203),
+            204 => Ok(HttpStatus::NoContent204),
+            205 => Ok(HttpStatus::ResetContent205),
+            206 => Ok(HttpStatus::PartialContent206),
+            207 => Ok(HttpStatus::MultiStatus207),
+            208 => Ok(HttpStatus::AlreadyReported208),
+            226 => Ok(HttpStatus::ImUsed226),
+            300 => Ok(HttpStatus::MultipleChoices300),
+            301 => Ok(HttpStatus::MovedPermanently301),
+            302 => Ok(HttpStatus::Found302),
+            303 => Ok(HttpStatus::SeeOther303),
+            304 => Ok(HttpStatus::NotModified304),
+            305 => Ok(HttpStatus::UseProxy305),
+            307 => Ok(HttpStatus::TemporaryRedirect307),
+            308 => Ok(HttpStatus::PermanentRedirect308),
+            400 => Ok(HttpStatus::BadRequest400),
+            401 => Ok(HttpStatus::Unauthorized401),
+            402 => Ok(HttpStatus::PaymentRequired402),
+            403 => Ok(HttpStatus::Forbidden403),
+            404 => Ok(HttpStatus::NotFound404),
+            405 => Ok(HttpStatus::MethodNotAllowed405),
+            406 => Ok(HttpStatus::NotAcceptable406),
+            407 => Ok(HttpStatus::ProxyAuthenticationRequired407),
+            408 => Ok(HttpStatus::RequestTimeout408),
+            409 => Ok(HttpStatus::Conflict409),
+            410 => Ok(HttpStatus::Gone410),
+            411 => Ok(HttpStatus::LengthRequired411),
+            412 => Ok(HttpStatus::PreconditionFailed412),
+            413 => Ok(HttpStatus::ContentTooLarge413),
+            414 => Ok(HttpStatus::UriTooLong414),
+            415 => Ok(HttpStatus::UnsupportedMediaType415),
+            416 => Ok(HttpStatus::RangeNotSatisfiable416),
+            417 => Ok(HttpStatus::ExpectationFailed417),
+            421 => Ok(HttpStatus::MisdirectedRequest421),
+            422 => Ok(HttpStatus::UnprocessableContent422),
+            423 => Ok(HttpStatus::Locked423),
+            424 => Ok(HttpStatus::FailedDependency424),
+            425 => Ok(HttpStatus::TooEarly425),
+            426 => Ok(HttpStatus::UpgradeRequired426),
+            428 => Ok(HttpStatus::PreconditionRequired428),
+            429 => Ok(HttpStatus::TooManyRequests429),
+            431 => Ok(HttpStatus::RequestHeaderFieldsTooLarge431),
+            451 => Ok(HttpStatus::UnavailableForLegalReasons451),
+            500 => Ok(HttpStatus::InternalServerError500),
+            501 => Ok(HttpStatus::NotImplemented501),
+            502 => Ok(HttpStatus::BadGateway502),
+            503 => Ok(HttpStatus::ServiceUnavailable503),
+            504 => Ok(HttpStatus::GatewayTimeout504),
+            505 => Ok(HttpStatus::HttpVersionNotSupported505),
+            506 => Ok(HttpStatus::VariantAlsoNegotiates506),
+            507 => Ok(HttpStatus::InsufficientStorage507),
+            508 => Ok(HttpStatus::LoopDetected508),
+            510 => Ok(HttpStatus::NotExtended510),
+            511 => Ok(HttpStatus::NetworkAuthenticationRequired511),
+            _ => Err(()),
         }
     }
 }
@@ -40,3 +351,53 @@ impl Display for HttpStatus {
         write!(f, "{} {}", self.status_code(), self.status_phrase())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_round_trip() {
+        for code in 0..1000 {
+            if let Ok(status) = HttpStatus::try_from(code) {
+                assert_eq!(status.status_code(), code);
+            }
+        }
+        assert_eq!(HttpStatus::try_from(600), Err(()));
+    }
+
+    #[test]
+    fn test_classification() {
+        assert!(HttpStatus::SwitchingProtocols101.is_informational());
+        assert!(HttpStatus::NoContent204.is_success());
+        assert!(HttpStatus::PermanentRedirect308.is_redirect());
+        assert!(HttpStatus::TooManyRequests429.is_client_error());
+        assert!(HttpStatus::GatewayTimeout504.is_server_error());
+        assert!(!HttpStatus::Ok200.is_client_error());
+
+        let custom = HttpStatus::custom(599, "Network Connect Timeout");
+        assert!(custom.is_server_error());
+        assert_eq!(custom.to_string(), "599 Network Connect Timeout");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_custom_invalid_code() {
+        HttpStatus::custom(1000, "Too Long");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_custom_invalid_phrase() {
+        HttpStatus::custom(299, "OK\r\nSet-Cookie: injected=1");
+    }
+
+    #[test]
+    fn test_custom_registered_code() {
+        assert_eq!(HttpStatus::custom(200, "Fine"), HttpStatus::Ok200);
+        assert_eq!(
+            HttpStatus::custom(299, "Tab\tallowed").to_string(),
+            "299 Tab\tallowed"
+        );
+    }
+}
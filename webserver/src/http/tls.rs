@@ -0,0 +1,270 @@
+use crate::http::{Acceptor, Connection};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Serves HTTPS, starting a TLS session on each connection
+///
+/// The handshake happens as the first request is read, on the thread handling the connection,
+/// so it's bounded by the server's keep alive timeout and a slow client can't hold up accepting others.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::net::TcpListener;
+/// use webserver::http::{HttpRequest, HttpResponse, Server, TlsAcceptor};
+/// let acceptor = TlsAcceptor::from_pem_files("cert.pem", "key.pem").unwrap();
+/// let listener = TcpListener::bind("0.0.0.0:443").unwrap();
+/// Server::new(listener, |_request: HttpRequest| HttpResponse::ok().body("Secure"))
+///     .with_acceptor(acceptor)
+///     .serve();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+/// A TLS session with a client, over TCP
+#[derive(Debug)]
+pub struct TlsConnection {
+    stream: StreamOwned<ServerConnection, TcpStream>,
+}
+
+/// Why a certificate and private key couldn't be used
+#[derive(Debug, PartialEq)]
+pub enum TlsError {
+    /// A PEM file couldn't be read
+    Io(ErrorKind),
+    InvalidPem(String),
+    NoCertificates,
+    NoPrivateKey,
+    /// The certificate or key was rejected, e.g. because the key doesn't match the certificate
+    InvalidConfig(rustls::Error),
+}
+
+impl TlsAcceptor {
+    /// Use a rustls config, for control over protocol versions, client authentication and so on
+    pub fn new(config: Arc<ServerConfig>) -> Self {
+        TlsAcceptor { config }
+    }
+
+    /// Use a certificate chain and private key in PEM format
+    ///
+    /// The chain starts with the server's own certificate, followed by any intermediates.
+    /// The key can be in PKCS#1, PKCS#8 or SEC1 format.
+    pub fn from_pem(cert_chain: &[u8], private_key: &[u8]) -> Result<Self, TlsError> {
+        let certs = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(TlsError::from)?;
+        let key = PrivateKeyDer::from_pem_slice(private_key).map_err(|err| match err {
+            pem::Error::NoItemsFound => TlsError::NoPrivateKey,
+            err => TlsError::from(err),
+        })?;
+        TlsAcceptor::from_der(certs, key)
+    }
+
+    /// Use a certificate chain and private key read from PEM files, see [`TlsAcceptor::from_pem`]
+    pub fn from_pem_files(
+        cert_chain_path: impl AsRef<Path>,
+        private_key_path: impl AsRef<Path>,
+    ) -> Result<Self, TlsError> {
+        let cert_chain = std::fs::read(cert_chain_path).map_err(|err| TlsError::Io(err.kind()))?;
+        let private_key =
+            std::fs::read(private_key_path).map_err(|err| TlsError::Io(err.kind()))?;
+        TlsAcceptor::from_pem(&cert_chain, &private_key)
+    }
+
+    fn from_der(
+        certs: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, TlsError> {
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificates);
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(TlsError::InvalidConfig)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::new(Arc::new(config)))
+    }
+}
+
+impl From<pem::Error> for TlsError {
+    fn from(err: pem::Error) -> Self {
+        match err {
+            pem::Error::Io(err) => TlsError::Io(err.kind()),
+            pem::Error::NoItemsFound => TlsError::NoCertificates,
+            err => TlsError::InvalidPem(err.to_string()),
+        }
+    }
+}
+
+impl Acceptor for TlsAcceptor {
+    type Connection = TlsConnection;
+
+    fn accept(&self, stream: TcpStream) -> io::Result<TlsConnection> {
+        let connection =
+            ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)?;
+        Ok(TlsConnection {
+            stream: StreamOwned::new(connection, stream),
+        })
+    }
+
+    /// Close the connection, since responding would need a TLS handshake which could hold up accepting
+    fn reject(&self, stream: TcpStream) {
+        eprintln!("Rejecting connection, all threads are busy and the queue is full");
+        drop(stream);
+    }
+}
+
+impl TlsConnection {
+    /// The protocol agreed with ALPN, once the handshake has finished
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.stream.conn.alpn_protocol()
+    }
+}
+
+impl Read for TlsConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TlsConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Connection for TlsConnection {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.sock.set_write_timeout(timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.sock.peer_addr()
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for TlsConnection {
+    /// Tell the client the session is over, so it can tell the response wasn't truncated
+    fn drop(&mut self) {
+        let StreamOwned { conn, sock } = &mut self.stream;
+        conn.send_close_notify();
+        while conn.wants_write() {
+            if conn.write_tls(sock).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{HttpRequest, HttpResponse, Server};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A self-signed certificate for localhost, and its private key, in PEM format
+    fn self_signed() -> (String, String) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        (certified.cert.pem(), certified.key_pair.serialize_pem())
+    }
+
+    fn client_config(cert: &str) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(cert.as_bytes()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    }
+
+    #[test]
+    fn test_https() {
+        let (cert, key) = self_signed();
+        let acceptor = TlsAcceptor::from_pem(cert.as_bytes(), key.as_bytes()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(listener, |request: HttpRequest| {
+            HttpResponse::ok().body(request.path().as_str())
+        })
+        .without_access_log()
+        .with_acceptor(acceptor);
+        let shutdown = server.shutdown_handle();
+        let serving = thread::spawn(move || server.serve());
+
+        let connection =
+            ClientConnection::new(client_config(&cert), "localhost".try_into().unwrap()).unwrap();
+        let mut client = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+        client
+            .write_all(b"GET /secure HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        // the server ends the session cleanly, otherwise this fails with an unexpected EOF
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 7\r\n\r\n/secure"
+        );
+        assert_eq!(client.conn.alpn_protocol(), None);
+
+        // plain HTTP doesn't get a response
+        let mut plain = TcpStream::connect(address).unwrap();
+        plain.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response);
+        assert!(!response.starts_with(b"HTTP/1.1"));
+
+        shutdown.shutdown();
+        assert_eq!(serving.join().unwrap().connections_accepted, 2);
+    }
+
+    #[test]
+    fn test_invalid_pem() {
+        let (cert, key) = self_signed();
+        assert_eq!(
+            TlsAcceptor::from_pem(b"", key.as_bytes()).unwrap_err(),
+            TlsError::NoCertificates
+        );
+        assert_eq!(
+            TlsAcceptor::from_pem(cert.as_bytes(), cert.as_bytes()).unwrap_err(),
+            TlsError::NoPrivateKey
+        );
+        let (_, other_key) = self_signed();
+        assert!(matches!(
+            TlsAcceptor::from_pem(cert.as_bytes(), other_key.as_bytes()),
+            Err(TlsError::InvalidConfig(_))
+        ));
+        assert_eq!(
+            TlsAcceptor::from_pem_files("missing-cert.pem", "missing-key.pem").unwrap_err(),
+            TlsError::Io(ErrorKind::NotFound)
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::str::FromStr;
+
+/// The form of a request's target (RFC 9112 section 3.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// A path and optional query, e.g. `/search?q=rust`, which is what clients send to origin servers
+    Origin,
+    /// A full URL, e.g. `http://example.com/search?q=rust`, which clients send to proxies
+    Absolute { scheme: String, authority: String },
+    /// `*`, for an `OPTIONS` request about the server as a whole
+    Asterisk,
+}
+
+/// The parameters of a query string, in the order they appear, allowing repeated names
+///
+/// Names and values are decoded as in HTML forms, with `+` for spaces and `%XX` escapes.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::Query;
+/// let query: Query = "tag=rust&tag=http&q=hello+world".parse().unwrap();
+/// assert_eq!(query.get("q"), Some("hello world"));
+/// assert_eq!(query.get_all("tag").collect::<Vec<_>>(), vec!["rust", "http"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+/// A request target split into its parts, with the path decoded and normalised
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ParsedTarget {
+    pub form: RequestTarget,
+    pub path: String,
+    pub query: Query,
+}
+
+impl Query {
+    pub fn new() -> Query {
+        Query::default()
+    }
+
+    /// The first value of the parameter
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the parameter, in the order they appear
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs
+            .iter()
+            .filter(move |(existing, _)| existing == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Add a parameter after the others, e.g. a field decoded from a form
+    pub(crate) fn push(&mut self, name: String, value: String) {
+        self.pairs.push((name, value));
+    }
+}
+
+impl FromStr for Query {
+    type Err = ();
+
+    /// Parse a query string without its leading `?`, failing if an escape or the decoded UTF-8 is invalid
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = s
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((form_decode(name)?, form_decode(value)?))
+            })
+            .collect::<Result<_, ()>>()?;
+        Ok(Query { pairs })
+    }
+}
+
+impl ParsedTarget {
+    /// Parse the target of a request line, failing if it isn't in a form the method allows
+    pub fn parse(target: &str, is_options: bool) -> Result<ParsedTarget, ()> {
+        // only visible ASCII is allowed, anything else must be percent-encoded
+        if target.is_empty() || !target.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return Err(());
+        }
+        if target == "*" {
+            if !is_options {
+                return Err(());
+            }
+            return Ok(ParsedTarget {
+                form: RequestTarget::Asterisk,
+                path: target.to_string(),
+                query: Query::new(),
+            });
+        }
+        // an origin-form target can contain `://` in its query, so only the scheme of an absolute URL is split off
+        let absolute = if target.starts_with('/') {
+            None
+        } else {
+            target
+                .split_once("://")
+                .filter(|(scheme, _)| !scheme.contains(['/', '?', '#']))
+        };
+        let (form, path_and_query) = match absolute {
+            Some((scheme, rest)) => {
+                if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                    return Err(());
+                }
+                let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+                let (authority, path_and_query) = rest.split_at(authority_end);
+                // credentials in the URL are deprecated for HTTP, and a sign of an attempt to mislead
+                if authority.is_empty() || authority.contains('@') {
+                    return Err(());
+                }
+                let form = RequestTarget::Absolute {
+                    scheme: scheme.to_ascii_lowercase(),
+                    authority: authority.to_ascii_lowercase(),
+                };
+                (form, path_and_query)
+            }
+            None if target.starts_with('/') => (RequestTarget::Origin, target),
+            None => return Err(()),
+        };
+        // fragments are for the client, and never sent
+        if path_and_query.contains('#') {
+            return Err(());
+        }
+        let (path, query) = path_and_query
+            .split_once('?')
+            .unwrap_or((path_and_query, ""));
+        // an absolute URL can leave out the path, e.g. `http://example.com`
+        let path = if path.is_empty() { "/" } else { path };
+        Ok(ParsedTarget {
+            form,
+            path: normalise_path(path)?,
+            query: query.parse()?,
+        })
+    }
+}
+
+/// Decode the path and resolve any `.` and `..` segments, also merging repeated slashes
+///
+/// Escapes are decoded before resolving segments, so `%2e%2e` is treated as `..` and can't be used to reach
+/// above the root. Encoded slashes and NUL bytes are rejected since the decoded path couldn't represent them.
+fn normalise_path(path: &str) -> Result<String, ()> {
+    let raw_segments: Vec<&str> = path.strip_prefix('/').ok_or(())?.split('/').collect();
+    let mut segments = Vec::with_capacity(raw_segments.len());
+    for (index, segment) in raw_segments.iter().enumerate() {
+        let is_last = index == raw_segments.len() - 1;
+        let segment = percent_decode(segment).ok_or(())?;
+        if segment.contains(['/', '\0']) {
+            return Err(());
+        }
+        match segment.as_str() {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            "" if !is_last => continue,
+            _ => {
+                segments.push(segment);
+                continue;
+            }
+        }
+        // the path still refers to a directory when it ends with `.` or `..`
+        if is_last {
+            segments.push(String::new());
+        }
+    }
+    Ok(format!("/{}", segments.join("/")))
+}
+
+/// Decode `%XX` escapes, returning None if the escapes or resulting UTF-8 are invalid
+fn percent_decode(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut input = encoded.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            // from_str_radix alone would accept a sign, e.g. `%+F`
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Encode a decoded path to be sent in a header like `Location`, escaping any characters a path can't contain
+pub(crate) fn percent_encode_path(path: &str) -> String {
+    percent_encode(path, b"/-._~!$&'()*+,;=:@")
+}
+
+/// Encode a name to be used as a single segment of a relative link
+///
+/// Unlike [`percent_encode_path`], `/` is escaped so the name can't be read as several segments,
+/// and `:` so the name can't be read as a scheme.
+pub(crate) fn percent_encode_segment(segment: &str) -> String {
+    percent_encode(segment, b"-._~!$&'()*+,;=@")
+}
+
+/// Escape every byte of the text other than ASCII letters, digits and the allowed characters
+fn percent_encode(text: &str, allowed: &[u8]) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || allowed.contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Decode a name or value of a query string or form, where `+` stands for a space
+pub(crate) fn form_decode(encoded: &str) -> Result<String, ()> {
+    percent_decode(&encoded.replace('+', " ")).ok_or(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc"), Some("a b/c".to_string()));
+        assert_eq!(percent_decode("%e2%9c%93"), Some("✓".to_string()));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%ff"), None);
+        assert_eq!(percent_decode("%+F"), None);
+        assert_eq!(percent_decode("%-1"), None);
+    }
+
+    #[test]
+    fn test_percent_encode_path() {
+        assert_eq!(percent_encode_path("/docs/a b?"), "/docs/a%20b%3F");
+        assert_eq!(percent_encode_path("/✓"), "/%E2%9C%93");
+        let path = "/guide & notes/%";
+        assert_eq!(
+            normalise_path(&percent_encode_path(path)),
+            Ok(path.to_string())
+        );
+    }
+
+    #[test]
+    fn test_percent_encode_segment() {
+        assert_eq!(percent_encode_segment("a b?#%"), "a%20b%3F%23%25");
+        assert_eq!(percent_encode_segment("a/b"), "a%2Fb");
+        assert_eq!(percent_encode_segment("javascript:x"), "javascript%3Ax");
+    }
+
+    #[test]
+    fn test_normalise_path() {
+        assert_eq!(normalise_path("/"), Ok("/".to_string()));
+        assert_eq!(normalise_path("/a//b/"), Ok("/a/b/".to_string()));
+        assert_eq!(normalise_path("/a/./b/../c"), Ok("/a/c".to_string()));
+        assert_eq!(normalise_path("/a/b/.."), Ok("/a/".to_string()));
+        assert_eq!(
+            normalise_path("/../../etc/passwd"),
+            Ok("/etc/passwd".to_string())
+        );
+        assert_eq!(normalise_path("/a/%2e%2E/b"), Ok("/b".to_string()));
+        assert_eq!(
+            normalise_path("/guide%20&%20notes.txt"),
+            Ok("/guide & notes.txt".to_string())
+        );
+        assert_eq!(normalise_path("/a%2Fb"), Err(()));
+        assert_eq!(normalise_path("/a%00"), Err(()));
+        assert_eq!(normalise_path("/%zz"), Err(()));
+    }
+
+    #[test]
+    fn test_query() {
+        let query: Query = "a=1&b=&c&a=2&&name=J%C3%BCrgen+M".parse().unwrap();
+        assert_eq!(query.len(), 5);
+        assert_eq!(query.get("a"), Some("1"));
+        assert_eq!(query.get_all("a").collect::<Vec<_>>(), vec!["1", "2"]);
+        assert_eq!(query.get("b"), Some(""));
+        assert!(query.contains("c"));
+        assert_eq!(query.get("name"), Some("Jürgen M"));
+        assert_eq!(query.get("missing"), None);
+        assert_eq!("".parse::<Query>(), Ok(Query::new()));
+        assert_eq!("a=%zz".parse::<Query>(), Err(()));
+    }
+
+    #[test]
+    fn test_parse_target() {
+        let target = ParsedTarget::parse("/a/../search?q=x", false).unwrap();
+        assert_eq!(target.form, RequestTarget::Origin);
+        assert_eq!(target.path, "/search");
+        assert_eq!(target.query.get("q"), Some("x"));
+        // a URL in the query doesn't make the target absolute
+        let target = ParsedTarget::parse("/login?next=http://example.com/", false).unwrap();
+        assert_eq!(target.form, RequestTarget::Origin);
+        assert_eq!(target.path, "/login");
+        assert_eq!(target.query.get("next"), Some("http://example.com/"));
+
+        let target = ParsedTarget::parse("HTTP://Example.com:8080?q=x", false).unwrap();
+        assert_eq!(
+            target.form,
+            RequestTarget::Absolute {
+                scheme: "http".to_string(),
+                authority: "example.com:8080".to_string()
+            }
+        );
+        assert_eq!(target.path, "/");
+        assert_eq!(target.query.get("q"), Some("x"));
+
+        let target = ParsedTarget::parse("*", true).unwrap();
+        assert_eq!(target.form, RequestTarget::Asterisk);
+        assert_eq!(ParsedTarget::parse("*", false), Err(()));
+
+        for invalid in [
+            "",
+            "path",
+            "/a b",
+            "/caf\u{e9}",
+            "/page#section",
+            "ftp://example.com/",
+            "http:///path",
+            "http://user@example.com/",
+            "example.com:443",
+            "path?next=http://example.com/",
+        ] {
+            assert_eq!(ParsedTarget::parse(invalid, false), Err(()), "{invalid}");
+        }
+    }
+}
@@ -29,9 +29,9 @@ impl FromStr for HttpVersion {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "HTTP/1" => Ok(HttpVersion::Http1),
+            "HTTP/1" | "HTTP/1.0" => Ok(HttpVersion::Http1),
             "HTTP/1.1" => Ok(HttpVersion::Http1_1),
-            "HTTP/2" => Ok(HttpVersion::Http2),
+            "HTTP/2" | "HTTP/2.0" => Ok(HttpVersion::Http2),
             "HTTP/3" => Ok(HttpVersion::Http3),
             _ => Err(()),
         }
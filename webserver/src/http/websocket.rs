@@ -0,0 +1,801 @@
+mod frame;
+mod sha1;
+
+use self::frame::{Frame, FrameError, Opcode, MAX_CONTROL_PAYLOAD};
+use self::sha1::sha1;
+use crate::http::base64;
+use crate::http::connection::TimedConnection;
+use crate::http::server::{is_timeout, SHUTDOWN_POLL_INTERVAL};
+use crate::http::{
+    Connection, HttpMethod, HttpRequest, HttpResponse, HttpStatus, HttpVersion, ShutdownHandle,
+};
+use crate::thread_pool::panic_message;
+use std::fmt;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Appended to the client's key before hashing it, to prove the server understood the handshake
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The only version of the protocol, from RFC 6455
+const VERSION: &str = "13";
+
+// close codes (RFC 6455 section 7.4.1)
+const NORMAL_CLOSURE: u16 = 1000;
+const GOING_AWAY: u16 = 1001;
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_DATA: u16 = 1007;
+const MESSAGE_TOO_BIG: u16 = 1009;
+const INTERNAL_ERROR: u16 = 1011;
+
+/// How long closing waits for the client to acknowledge
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_BUFFER_SIZE: usize = 8192;
+
+/// A WebSocket connection to a client, handed to the handler passed to [`WebSocket::accept`]
+///
+/// Pings are answered automatically, and closing frames acknowledged, though both are still received as
+/// messages. Fragmented messages are received whole, and can be up to the server's maximum body size unless
+/// changed with [`WebSocket::set_max_message_size`]. If the client breaks the protocol, or the server shuts
+/// down, the connection is closed with the matching close code and receiving returns an error.
+///
+/// # Examples
+///
+/// ```
+/// use webserver::http::{HttpRequest, Message, Router, WebSocket};
+/// let router = Router::new().get("/echo", |request: HttpRequest| {
+///     WebSocket::accept(&request, |mut socket| {
+///         while let Ok(message) = socket.receive() {
+///             if let Message::Text(_) | Message::Binary(_) = message {
+///                 if socket.send(message).is_err() {
+///                     break;
+///                 }
+///             }
+///         }
+///     })
+/// });
+/// ```
+pub struct WebSocket {
+    connection: Box<dyn Connection>,
+    /// Data received but not yet parsed into frames
+    input: Vec<u8>,
+    /// The opcode and payload so far of a fragmented message
+    fragments: Option<(Opcode, Vec<u8>)>,
+    max_message_size: usize,
+    shutdown: ShutdownHandle,
+    close_sent: bool,
+    /// Whether no more messages will be received, because the client closed the connection or broke the protocol
+    closed: bool,
+}
+
+/// A message sent over a WebSocket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The start of the closing handshake, with the reason if one was given
+    Close(Option<CloseFrame>),
+}
+
+/// Why a WebSocket was closed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// The status code, e.g. 1000 for a normal closure (RFC 6455 section 7.4)
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WebSocketError {
+    /// The connection failed, or the client closed it without a closing handshake
+    ConnectionError(ErrorKind),
+    /// The client broke the protocol, so the connection was closed with 1002 Protocol Error
+    ProtocolError,
+    /// The client sent text that isn't valid UTF-8, so the connection was closed with 1007 Invalid Data
+    InvalidUtf8,
+    /// The client sent a message over the limit, so the connection was closed with 1009 Message Too Big
+    MessageTooLarge { max_size: usize },
+    /// The connection was closed, so no more messages can be sent or received
+    Closed,
+    /// The server is shutting down, so the connection was closed with 1001 Going Away
+    ShuttingDown,
+}
+
+/// Hands the connection to a WebSocket handler once the response accepting the handshake is sent
+pub(crate) struct Upgrade(Box<dyn FnOnce(WebSocket) + Send>);
+
+impl WebSocket {
+    /// Answer a request to open a WebSocket, with the handler taking over the connection if it's accepted
+    ///
+    /// The handler runs on the thread that was handling the connection, until it returns.
+    /// Requests that aren't valid WebSocket handshakes are answered with a 400, or a 426 if they ask for
+    /// another version of the protocol.
+    pub fn accept(
+        request: &HttpRequest,
+        handler: impl FnOnce(WebSocket) + Send + 'static,
+    ) -> HttpResponse {
+        let Some(key) = handshake_key(request) else {
+            return HttpResponse::bad_request().body("Expected a WebSocket handshake");
+        };
+        if request.header("Sec-WebSocket-Version") != Some(VERSION) {
+            return HttpResponse::new(HttpStatus::UpgradeRequired426)
+                .header("Upgrade", "websocket")
+                .header("Sec-WebSocket-Version", VERSION);
+        }
+        let mut response = HttpResponse::new(HttpStatus::SwitchingProtocols101)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Accept", &accept_key(key));
+        response.upgrade = Some(Upgrade(Box::new(handler)));
+        response
+    }
+
+    /// Wait for the next message from the client
+    pub fn receive(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            if let Some(message) = self.receive_until(None)? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Wait up to the timeout for the next message, returning None if none arrived in time
+    ///
+    /// This allows a single thread to both push messages to the client and listen for its messages.
+    pub fn receive_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Message>, WebSocketError> {
+        self.receive_until(Some(Instant::now() + timeout))
+    }
+
+    /// Send a message in a single frame
+    ///
+    /// Sending a [`Message::Close`] starts the closing handshake, after which no more messages can be sent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a ping, pong or close message's payload is over 125 bytes, or a close code isn't one that
+    /// can be sent.
+    pub fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => (Opcode::Binary, data),
+            Message::Ping(data) => (Opcode::Ping, data),
+            Message::Pong(data) => (Opcode::Pong, data),
+            Message::Close(close) => (Opcode::Close, close_payload(close.as_ref())),
+        };
+        assert!(
+            !opcode.is_control() || payload.len() <= MAX_CONTROL_PAYLOAD,
+            "Control frames can't have payloads over {MAX_CONTROL_PAYLOAD} bytes"
+        );
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        self.close_sent = opcode == Opcode::Close;
+        self.write_frame(true, opcode, payload)
+    }
+
+    /// Send a text or binary message split into frames of at most `fragment_size` bytes
+    ///
+    /// # Panics
+    ///
+    /// Panics if the message isn't text or binary, or the fragment size is zero.
+    pub fn send_fragmented(
+        &mut self,
+        message: Message,
+        fragment_size: usize,
+    ) -> Result<(), WebSocketError> {
+        assert!(fragment_size > 0, "The fragment size must be at least 1");
+        let (opcode, payload) = match message {
+            Message::Text(text) => (Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => (Opcode::Binary, data),
+            _ => panic!("Only text and binary messages can be fragmented"),
+        };
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        // an empty message is still sent as a frame
+        let fragments = payload.chunks(fragment_size).count().max(1);
+        let mut chunks = payload.chunks(fragment_size);
+        for i in 0..fragments {
+            let opcode = if i == 0 { opcode } else { Opcode::Continuation };
+            let chunk = chunks.next().unwrap_or_default();
+            self.write_frame(i == fragments - 1, opcode, chunk.to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Close the connection, waiting a few seconds for the client to acknowledge
+    ///
+    /// Any messages the client sends before acknowledging are discarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reason is over 123 bytes, or the code isn't one that can be sent.
+    pub fn close(mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        let close = CloseFrame {
+            code,
+            reason: reason.to_string(),
+        };
+        self.send(Message::Close(Some(close)))?;
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        while matches!(self.receive_until(Some(deadline)), Ok(Some(_))) {}
+        Ok(())
+    }
+
+    /// Limit the size of messages the client can send, which defaults to the server's maximum body size
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// The address of the client
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.connection.peer_addr()
+    }
+
+    /// Receive the next message, unless the deadline passes first
+    fn receive_until(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Message>, WebSocketError> {
+        loop {
+            if self.closed {
+                return Err(WebSocketError::Closed);
+            }
+            if let Some(message) = self.next_message()? {
+                return Ok(Some(message));
+            }
+            if self.shutdown.is_shutting_down() {
+                return Err(self.fail(GOING_AWAY, WebSocketError::ShuttingDown));
+            }
+            // wake up periodically to check whether the server is shutting down
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Ok(None);
+                    }
+                    remaining.min(SHUTDOWN_POLL_INTERVAL)
+                }
+                None => SHUTDOWN_POLL_INTERVAL,
+            };
+            self.read(timeout)?;
+        }
+    }
+
+    /// Read whatever data arrives within the timeout
+    fn read(&mut self, timeout: Duration) -> Result<(), WebSocketError> {
+        let connection_error = |err: io::Error| WebSocketError::ConnectionError(err.kind());
+        self.connection
+            .set_read_timeout(Some(timeout))
+            .map_err(connection_error)?;
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        match self.connection.read(&mut buffer) {
+            Ok(0) => {
+                self.closed = true;
+                Err(WebSocketError::ConnectionError(ErrorKind::UnexpectedEof))
+            }
+            Ok(length) => {
+                self.input.extend_from_slice(&buffer[..length]);
+                Ok(())
+            }
+            Err(err) if is_timeout(err.kind()) || err.kind() == ErrorKind::Interrupted => Ok(()),
+            Err(err) => {
+                self.closed = true;
+                Err(connection_error(err))
+            }
+        }
+    }
+
+    /// Handle the frames received so far, until they complete a message
+    fn next_message(&mut self) -> Result<Option<Message>, WebSocketError> {
+        loop {
+            // control frames are always allowed their full size, since they can't be fragmented
+            let received = self
+                .fragments
+                .as_ref()
+                .map_or(0, |(_, payload)| payload.len());
+            let max_payload = self
+                .max_message_size
+                .saturating_sub(received)
+                .max(MAX_CONTROL_PAYLOAD);
+            let frame = match Frame::parse(&self.input, max_payload) {
+                Ok(Some((frame, length))) => {
+                    self.input.drain(..length);
+                    frame
+                }
+                Ok(None) => return Ok(None),
+                Err(FrameError::Invalid) => {
+                    return Err(self.fail(PROTOCOL_ERROR, WebSocketError::ProtocolError))
+                }
+                Err(FrameError::TooLarge) => return Err(self.message_too_large()),
+            };
+            if let Some(message) = self.handle_frame(frame)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Handle a frame, returning the message it completes if any
+    fn handle_frame(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        // clients mask every frame, so that proxies can't mistake them for other traffic (RFC 6455 section 10.3)
+        if !frame.masked {
+            return Err(self.fail(PROTOCOL_ERROR, WebSocketError::ProtocolError));
+        }
+        match frame.opcode {
+            Opcode::Ping => {
+                if !self.close_sent {
+                    self.write_frame(true, Opcode::Pong, frame.payload.clone())?;
+                }
+                return Ok(Some(Message::Ping(frame.payload)));
+            }
+            Opcode::Pong => return Ok(Some(Message::Pong(frame.payload))),
+            Opcode::Close => return self.receive_close(&frame.payload).map(Some),
+            Opcode::Text | Opcode::Binary if self.fragments.is_none() => {
+                self.fragments = Some((frame.opcode, frame.payload));
+            }
+            Opcode::Continuation if self.fragments.is_some() => {
+                if let Some((_, payload)) = &mut self.fragments {
+                    payload.extend_from_slice(&frame.payload);
+                }
+            }
+            // a message can't start before the last one finishes, or continue one that never started
+            _ => return Err(self.fail(PROTOCOL_ERROR, WebSocketError::ProtocolError)),
+        }
+        let received = self
+            .fragments
+            .as_ref()
+            .map_or(0, |(_, payload)| payload.len());
+        if received > self.max_message_size {
+            return Err(self.message_too_large());
+        }
+        match self.fragments.take() {
+            Some((opcode, payload)) if frame.fin => match opcode {
+                Opcode::Text => match String::from_utf8(payload) {
+                    Ok(text) => Ok(Some(Message::Text(text))),
+                    Err(_) => Err(self.fail(INVALID_DATA, WebSocketError::InvalidUtf8)),
+                },
+                _ => Ok(Some(Message::Binary(payload))),
+            },
+            fragments => {
+                self.fragments = fragments;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Acknowledge the client closing the connection, echoing its close code
+    fn receive_close(&mut self, payload: &[u8]) -> Result<Message, WebSocketError> {
+        let close = match *payload {
+            [] => None,
+            [high, low, ref reason @ ..]
+                if is_valid_close_code(u16::from_be_bytes([high, low])) =>
+            {
+                let Ok(reason) = String::from_utf8(reason.to_vec()) else {
+                    return Err(self.fail(INVALID_DATA, WebSocketError::InvalidUtf8));
+                };
+                Some(CloseFrame {
+                    code: u16::from_be_bytes([high, low]),
+                    reason,
+                })
+            }
+            _ => return Err(self.fail(PROTOCOL_ERROR, WebSocketError::ProtocolError)),
+        };
+        self.closed = true;
+        if !self.close_sent {
+            self.close_sent = true;
+            let code = close.as_ref().map(|close| CloseFrame {
+                code: close.code,
+                reason: String::new(),
+            });
+            self.write_frame(true, Opcode::Close, close_payload(code.as_ref()))?;
+        }
+        Ok(Message::Close(close))
+    }
+
+    fn message_too_large(&mut self) -> WebSocketError {
+        let max_size = self.max_message_size;
+        self.fail(
+            MESSAGE_TOO_BIG,
+            WebSocketError::MessageTooLarge { max_size },
+        )
+    }
+
+    /// Stop receiving messages, closing the connection with the code unless it's already closing
+    fn fail(&mut self, code: u16, err: WebSocketError) -> WebSocketError {
+        self.closed = true;
+        self.send_close_code(code);
+        err
+    }
+
+    /// Start the closing handshake if it hasn't started, ignoring failures since the connection is ending anyway
+    fn send_close_code(&mut self, code: u16) {
+        if !self.close_sent {
+            self.close_sent = true;
+            let close = CloseFrame {
+                code,
+                reason: String::new(),
+            };
+            let _ = self.write_frame(true, Opcode::Close, close_payload(Some(&close)));
+        }
+    }
+
+    fn write_frame(
+        &mut self,
+        fin: bool,
+        opcode: Opcode,
+        payload: Vec<u8>,
+    ) -> Result<(), WebSocketError> {
+        let frame = Frame {
+            fin,
+            opcode,
+            masked: false,
+            payload,
+        };
+        let mut output = Vec::new();
+        frame.encode(None, &mut output);
+        self.connection
+            .write_all(&output)
+            .and_then(|_| self.connection.flush())
+            .map_err(|err| WebSocketError::ConnectionError(err.kind()))
+    }
+}
+
+impl Drop for WebSocket {
+    /// Close the connection if the handler didn't, telling the client if it panicked
+    fn drop(&mut self) {
+        let code = if thread::panicking() {
+            INTERNAL_ERROR
+        } else {
+            NORMAL_CLOSURE
+        };
+        self.send_close_code(code);
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade").finish_non_exhaustive()
+    }
+}
+
+/// Run the handler on the connection, once the response accepting the handshake has been sent
+pub(super) fn serve<C: Connection>(
+    reader: BufReader<TimedConnection<C>>,
+    upgrade: Upgrade,
+    max_message_size: usize,
+    shutdown: &ShutdownHandle,
+) {
+    // the client can send frames straight after the handshake, without waiting for the response
+    let input = reader.buffer().to_vec();
+    let socket = WebSocket {
+        connection: Box::new(reader.into_inner().into_inner()),
+        input,
+        fragments: None,
+        max_message_size,
+        shutdown: shutdown.clone(),
+        close_sent: false,
+        closed: false,
+    };
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| (upgrade.0)(socket))) {
+        eprintln!(
+            "The WebSocket handler panicked with: {}",
+            panic_message(payload.as_ref())
+        );
+    }
+}
+
+/// The client's key, if the request asks to open a WebSocket (RFC 6455 section 4.2.1)
+fn handshake_key(request: &HttpRequest) -> Option<&str> {
+    let has_option = |name, option: &str| {
+        request
+            .headers()
+            .get_list(name)
+            .any(|value| value.eq_ignore_ascii_case(option))
+    };
+    let key = request.header("Sec-WebSocket-Key")?;
+    let is_handshake = request.method() == HttpMethod::Get
+        && request.version() == HttpVersion::Http1_1
+        && has_option("Upgrade", "websocket")
+        && has_option("Connection", "Upgrade")
+        && base64::decode(key).is_some_and(|nonce| nonce.len() == 16);
+    is_handshake.then_some(key)
+}
+
+/// The `Sec-WebSocket-Accept` value answering the key
+fn accept_key(key: &str) -> String {
+    base64::encode(&sha1(format!("{key}{ACCEPT_GUID}").as_bytes()))
+}
+
+/// The payload of a close frame, checking it's valid to send
+fn close_payload(close: Option<&CloseFrame>) -> Vec<u8> {
+    let Some(close) = close else {
+        return Vec::new();
+    };
+    assert!(
+        is_valid_close_code(close.code),
+        "{} can't be sent as a close code",
+        close.code
+    );
+    let mut payload = close.code.to_be_bytes().to_vec();
+    payload.extend_from_slice(close.reason.as_bytes());
+    payload
+}
+
+/// Whether the code can be sent in a close frame, since some are reserved or only used locally
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Router, Server};
+    use std::net::{TcpListener, TcpStream};
+
+    /// The key from the example in RFC 6455 section 1.3
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+    const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+    /// A client speaking WebSockets frame by frame
+    struct Client {
+        stream: TcpStream,
+        input: Vec<u8>,
+    }
+
+    fn request(lines: &[&str]) -> HttpRequest {
+        HttpRequest::from_lines(lines.iter().map(|line| line.to_string())).unwrap()
+    }
+
+    fn handshake(version: &str) -> HttpRequest {
+        request(&[
+            "GET /chat HTTP/1.1",
+            "Host: localhost",
+            "Upgrade: websocket",
+            "Connection: keep-alive, Upgrade",
+            &format!("Sec-WebSocket-Key: {KEY}"),
+            &format!("Sec-WebSocket-Version: {version}"),
+        ])
+    }
+
+    /// Echo text and binary messages until the client closes the connection
+    fn echo(mut socket: WebSocket) {
+        socket.set_max_message_size(16);
+        while let Ok(message) = socket.receive() {
+            if let Message::Text(_) | Message::Binary(_) = message {
+                socket.send(message).unwrap();
+            }
+        }
+    }
+
+    fn start_server() -> (SocketAddr, ShutdownHandle) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = Router::new()
+            .get("/echo", |request: HttpRequest| {
+                WebSocket::accept(&request, echo)
+            })
+            .get("/push", |request: HttpRequest| {
+                WebSocket::accept(&request, |mut socket| {
+                    let message = Message::Text("pushed".to_string());
+                    socket.send_fragmented(message, 4).unwrap();
+                    socket.close(NORMAL_CLOSURE, "done").unwrap();
+                })
+            });
+        let server = Server::new(listener, router).without_access_log();
+        let shutdown = server.shutdown_handle();
+        thread::spawn(move || server.serve());
+        (address, shutdown)
+    }
+
+    impl Client {
+        /// Open a WebSocket, checking the handshake response
+        fn connect(address: SocketAddr, path: &str) -> Client {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let handshake = format!(
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Key: {KEY}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+            );
+            stream.write_all(handshake.as_bytes()).unwrap();
+            let mut client = Client {
+                stream,
+                input: Vec::new(),
+            };
+            let head = loop {
+                if let Some(end) = client.input.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let head = String::from_utf8(client.input[..end].to_vec()).unwrap();
+                    client.input.drain(..end + 4);
+                    break head;
+                }
+                client.fill();
+            };
+            assert_eq!(
+                head,
+                format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                     Sec-WebSocket-Accept: {ACCEPT}"
+                )
+            );
+            client
+        }
+
+        fn fill(&mut self) -> usize {
+            let mut buffer = [0; 4096];
+            let length = self.stream.read(&mut buffer).unwrap();
+            self.input.extend_from_slice(&buffer[..length]);
+            length
+        }
+
+        fn send(&mut self, fin: bool, opcode: Opcode, payload: &[u8]) {
+            let frame = Frame {
+                fin,
+                opcode,
+                masked: true,
+                payload: payload.to_vec(),
+            };
+            let mut output = Vec::new();
+            frame.encode(Some([0x12, 0x34, 0x56, 0x78]), &mut output);
+            self.stream.write_all(&output).unwrap();
+        }
+
+        fn receive(&mut self) -> Frame {
+            loop {
+                if let Some((frame, length)) = Frame::parse(&self.input, usize::MAX).unwrap() {
+                    self.input.drain(..length);
+                    assert!(!frame.masked);
+                    return frame;
+                }
+                assert!(self.fill() > 0, "The server closed the connection");
+            }
+        }
+
+        /// Expect a close frame with the code, followed by the server closing the connection
+        fn expect_close(&mut self, code: u16) {
+            let frame = self.receive();
+            assert_eq!(frame.opcode, Opcode::Close);
+            assert_eq!(frame.payload[..2], code.to_be_bytes());
+            assert_eq!(self.fill(), 0);
+        }
+    }
+
+    #[test]
+    fn test_accept() {
+        assert_eq!(accept_key(KEY), ACCEPT);
+        let response = WebSocket::accept(&handshake("13"), |_| {});
+        assert_eq!(response.status, HttpStatus::SwitchingProtocols101);
+        assert_eq!(response.headers.get("Sec-WebSocket-Accept"), Some(ACCEPT));
+        assert!(response.upgrade.is_some());
+
+        let response = WebSocket::accept(&handshake("8"), |_| {});
+        assert_eq!(response.status, HttpStatus::UpgradeRequired426);
+        assert_eq!(response.headers.get("Sec-WebSocket-Version"), Some("13"));
+        assert!(response.upgrade.is_none());
+
+        // the key must be 16 bytes of base64
+        for key in ["c2hvcnQ=", "not base64"] {
+            let request = request(&[
+                "GET /chat HTTP/1.1",
+                "Upgrade: websocket",
+                "Connection: Upgrade",
+                &format!("Sec-WebSocket-Key: {key}"),
+                "Sec-WebSocket-Version: 13",
+            ]);
+            let response = WebSocket::accept(&request, |_| {});
+            assert_eq!(response.status, HttpStatus::BadRequest400);
+        }
+        let request = request(&["GET /chat HTTP/1.1", "Host: localhost"]);
+        let response = WebSocket::accept(&request, |_| {});
+        assert_eq!(response.status, HttpStatus::BadRequest400);
+    }
+
+    #[test]
+    fn test_messages() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, "/echo");
+        client.send(true, Opcode::Text, b"Hello");
+        assert_eq!(client.receive().payload, b"Hello");
+
+        // a ping between fragments is answered before the message completes
+        client.send(false, Opcode::Binary, &[1, 2]);
+        client.send(true, Opcode::Ping, b"ping");
+        client.send(false, Opcode::Continuation, &[3]);
+        client.send(true, Opcode::Continuation, &[4, 5]);
+        let pong = client.receive();
+        assert_eq!(
+            (pong.opcode, pong.payload),
+            (Opcode::Pong, b"ping".to_vec())
+        );
+        let message = client.receive();
+        assert_eq!(message.opcode, Opcode::Binary);
+        assert_eq!(message.payload, [1, 2, 3, 4, 5]);
+
+        // the server acknowledges the client closing, then closes the connection
+        client.send(true, Opcode::Close, b"\x03\xe8bye");
+        client.expect_close(NORMAL_CLOSURE);
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_push() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, "/push");
+        let fragments: Vec<_> = (0..2).map(|_| client.receive()).collect();
+        assert_eq!(
+            fragments,
+            [
+                Frame {
+                    fin: false,
+                    opcode: Opcode::Text,
+                    masked: false,
+                    payload: b"push".to_vec()
+                },
+                Frame {
+                    fin: true,
+                    opcode: Opcode::Continuation,
+                    masked: false,
+                    payload: b"ed".to_vec()
+                }
+            ]
+        );
+        let close = client.receive();
+        assert_eq!(close.opcode, Opcode::Close);
+        assert_eq!(close.payload, b"\x03\xe8done");
+        client.send(true, Opcode::Close, b"\x03\xe8");
+        assert_eq!(client.fill(), 0);
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let (address, shutdown) = start_server();
+        // frames from clients must be masked
+        let mut client = Client::connect(address, "/echo");
+        let mut output = Vec::new();
+        let frame = Frame {
+            fin: true,
+            opcode: Opcode::Text,
+            masked: false,
+            payload: b"Hello".to_vec(),
+        };
+        frame.encode(None, &mut output);
+        client.stream.write_all(&output).unwrap();
+        client.expect_close(PROTOCOL_ERROR);
+
+        let mut client = Client::connect(address, "/echo");
+        client.send(true, Opcode::Continuation, b"no start");
+        client.expect_close(PROTOCOL_ERROR);
+
+        let mut client = Client::connect(address, "/echo");
+        client.send(true, Opcode::Text, &[0xff, 0xfe]);
+        client.expect_close(INVALID_DATA);
+
+        // fragments add up to more than the handler's limit of 16 bytes
+        let mut client = Client::connect(address, "/echo");
+        client.send(false, Opcode::Binary, &[0; 10]);
+        client.send(true, Opcode::Continuation, &[0; 10]);
+        client.expect_close(MESSAGE_TOO_BIG);
+
+        let mut client = Client::connect(address, "/echo");
+        client.send(true, Opcode::Close, &1005_u16.to_be_bytes());
+        client.expect_close(PROTOCOL_ERROR);
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let (address, shutdown) = start_server();
+        let mut client = Client::connect(address, "/echo");
+        client.send(true, Opcode::Text, b"Hello");
+        assert_eq!(client.receive().payload, b"Hello");
+        shutdown.shutdown();
+        client.expect_close(GOING_AWAY);
+    }
+}
@@ -0,0 +1,231 @@
+/// The largest payload of a control frame, which can't be fragmented
+pub(super) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+const FIN: u8 = 0x80;
+/// Bits reserved for extensions, which the server never negotiates
+const RSV: u8 = 0x70;
+const MASKED: u8 = 0x80;
+
+/// The kind of a frame (RFC 6455 section 5.2)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xa,
+}
+
+/// A frame, whose payload has been unmasked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Frame {
+    /// Whether this is the last frame of its message
+    pub(super) fin: bool,
+    pub(super) opcode: Opcode,
+    /// Whether the payload was masked, which it must be from clients and never from servers
+    pub(super) masked: bool,
+    pub(super) payload: Vec<u8>,
+}
+
+/// Why a frame couldn't be parsed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum FrameError {
+    /// The frame breaks the rules of the protocol, e.g. by using reserved bits or opcodes
+    Invalid,
+    /// The payload is larger than the limit
+    TooLarge,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Opcode> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    /// Control frames can appear between the frames of a fragmented message
+    pub(super) fn is_control(self) -> bool {
+        self as u8 & 0x8 != 0
+    }
+}
+
+impl Frame {
+    /// Parse the frame at the start of the input, returning it with its length, or None if it's incomplete
+    ///
+    /// A frame whose payload is over `max_payload` bytes is rejected as soon as its header arrives.
+    pub(super) fn parse(
+        input: &[u8],
+        max_payload: usize,
+    ) -> Result<Option<(Frame, usize)>, FrameError> {
+        let [first, second, ..] = *input else {
+            return Ok(None);
+        };
+        if first & RSV != 0 {
+            return Err(FrameError::Invalid);
+        }
+        let opcode = Opcode::from_u8(first & 0x0f).ok_or(FrameError::Invalid)?;
+        let fin = first & FIN != 0;
+        let masked = second & MASKED != 0;
+        // lengths over 125 follow in 2 bytes, or in 8 bytes if over 65535
+        let (length, mut offset) = match second & 0x7f {
+            126 => match input.get(2..4) {
+                Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+                None => return Ok(None),
+            },
+            127 => match input.get(2..10) {
+                Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap()), 10),
+                None => return Ok(None),
+            },
+            length => (u64::from(length), 2),
+        };
+        if opcode.is_control() && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(FrameError::Invalid);
+        }
+        let length = match usize::try_from(length) {
+            Ok(length) if length <= max_payload => length,
+            _ => return Err(FrameError::TooLarge),
+        };
+        let mask = if masked {
+            let Some(mask) = input.get(offset..offset + 4) else {
+                return Ok(None);
+            };
+            offset += 4;
+            Some([mask[0], mask[1], mask[2], mask[3]])
+        } else {
+            None
+        };
+        let Some(payload) = input.get(offset..offset + length) else {
+            return Ok(None);
+        };
+        let mut payload = payload.to_vec();
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+        let frame = Frame {
+            fin,
+            opcode,
+            masked,
+            payload,
+        };
+        Ok(Some((frame, offset + length)))
+    }
+
+    /// Append the frame to the output, masking the payload with `mask` if given
+    pub(super) fn encode(&self, mask: Option<[u8; 4]>, output: &mut Vec<u8>) {
+        let fin = if self.fin { FIN } else { 0 };
+        output.push(fin | self.opcode as u8);
+        let masked = if mask.is_some() { MASKED } else { 0 };
+        let length = self.payload.len();
+        if length <= MAX_CONTROL_PAYLOAD {
+            output.push(masked | length as u8);
+        } else if let Ok(length) = u16::try_from(length) {
+            output.push(masked | 126);
+            output.extend_from_slice(&length.to_be_bytes());
+        } else {
+            output.push(masked | 127);
+            output.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+        if let Some(mask) = mask {
+            output.extend_from_slice(&mask);
+        }
+        let payload_start = output.len();
+        output.extend_from_slice(&self.payload);
+        if let Some(mask) = mask {
+            apply_mask(&mut output[payload_start..], mask);
+        }
+    }
+}
+
+/// XOR the data with the mask, which both masks and unmasks it
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (byte, mask) in data.iter_mut().zip(mask.iter().cycle()) {
+        *byte ^= mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(fin: bool, opcode: Opcode, payload: &[u8]) -> Frame {
+        Frame {
+            fin,
+            opcode,
+            masked: false,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_examples() {
+        // examples from RFC 6455 section 5.7
+        let hello = frame(true, Opcode::Text, b"Hello");
+        let mut output = Vec::new();
+        hello.encode(None, &mut output);
+        assert_eq!(output, b"\x81\x05Hello");
+        assert_eq!(Frame::parse(&output, 100), Ok(Some((hello.clone(), 7))));
+
+        let masked = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        let mut output = Vec::new();
+        hello.encode(Some([0x37, 0xfa, 0x21, 0x3d]), &mut output);
+        assert_eq!(output, masked);
+        let (parsed, length) = Frame::parse(masked, 100).unwrap().unwrap();
+        assert!(parsed.masked);
+        assert_eq!(parsed.payload, b"Hello");
+        assert_eq!(length, masked.len());
+
+        let fragments = [b"\x01\x03Hel".as_slice(), b"\x80\x02lo"];
+        assert_eq!(
+            Frame::parse(fragments[0], 100),
+            Ok(Some((frame(false, Opcode::Text, b"Hel"), 5)))
+        );
+        assert_eq!(
+            Frame::parse(fragments[1], 100),
+            Ok(Some((frame(true, Opcode::Continuation, b"lo"), 4)))
+        );
+    }
+
+    #[test]
+    fn test_lengths() {
+        for length in [125, 126, 65535, 65536] {
+            let binary = frame(true, Opcode::Binary, &vec![7; length]);
+            let mut output = Vec::new();
+            binary.encode(Some([1, 2, 3, 4]), &mut output);
+            let header_length = match length {
+                ..126 => 2,
+                126..65536 => 4,
+                _ => 10,
+            };
+            assert_eq!(output.len(), header_length + 4 + length);
+            let (parsed, parsed_length) = Frame::parse(&output, length).unwrap().unwrap();
+            assert_eq!(parsed.payload, binary.payload);
+            assert_eq!(parsed_length, output.len());
+            // incomplete frames wait for more input
+            assert_eq!(Frame::parse(&output[..output.len() - 1], length), Ok(None));
+            assert_eq!(Frame::parse(&output[..header_length - 1], length), Ok(None));
+            assert_eq!(Frame::parse(&output, length - 1), Err(FrameError::TooLarge));
+        }
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        // reserved bits
+        assert_eq!(Frame::parse(b"\xc1\x00", 100), Err(FrameError::Invalid));
+        // reserved opcode
+        assert_eq!(Frame::parse(b"\x83\x00", 100), Err(FrameError::Invalid));
+        // fragmented control frame
+        assert_eq!(Frame::parse(b"\x09\x00", 100), Err(FrameError::Invalid));
+        // control frame over 125 bytes
+        assert_eq!(
+            Frame::parse(b"\x89\x7e\x00\x7e", 1000),
+            Err(FrameError::Invalid)
+        );
+    }
+}
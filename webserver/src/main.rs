@@ -1,44 +1,44 @@
+#[cfg(feature = "tls")]
+use std::env;
 use std::net::TcpListener;
+use std::thread;
 use std::time::Duration;
-use std::{fs, thread};
 use webserver::http::*;
 
-fn hello_world_responder(request: HttpRequest, policy: &str) -> HttpResponse {
-    match request.path().as_str() {
-        "/" => HttpResponse {
-            version: request.version(),
-            status: HttpStatus::Ok200,
-            content: fs::read_to_string("hello.html").unwrap(),
-        },
-        "/sleep" => {
-            thread::sleep(Duration::from_secs(5));
-            HttpResponse {
-                version: request.version(),
-                status: HttpStatus::Ok200,
-                content: fs::read_to_string("hello.html").unwrap(),
-            }
-        }
-        "/policy" => HttpResponse {
-            version: request.version(),
-            status: HttpStatus::Ok200,
-            content: policy.to_string(),
-        },
-        _ => HttpResponse {
-            version: request.version(),
-            status: HttpStatus::NotFound404,
-            content: fs::read_to_string("not_found.html").unwrap(),
-        },
-    }
-}
-
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:7878")
         .expect("The 7878 port should be free, otherwise the program cannot run");
 
     // can use an immutable closure
     let policy = "All your data are belong to us";
-    let response_closure = |request| hello_world_responder(request, policy);
+    let router = Router::new()
+        .get("/sleep", |_request| {
+            thread::sleep(Duration::from_secs(5));
+            HttpResponse::ok().body("Finished sleeping")
+        })
+        .get("/policy", move |_request| HttpResponse::ok().body(policy))
+        .not_found(StaticFiles::new("/", "static").with_not_found_page("not_found.html"));
+
+    let server = Server::new(listener, router)
+        .with_middleware(Compression::new())
+        .with_access_log(AccessLog::new(LogFormat::Combined, StdoutSink));
+    // serve HTTPS when given a certificate and key, e.g. TLS_CERT=cert.pem TLS_KEY=key.pem
+    #[cfg(feature = "tls")]
+    if let (Some(cert_path), Some(key_path)) = (env::var_os("TLS_CERT"), env::var_os("TLS_KEY")) {
+        let acceptor = TlsAcceptor::from_pem_files(cert_path, key_path)
+            .expect("The TLS certificate and key should be valid PEM files");
+        serve(server.with_acceptor(acceptor));
+        return;
+    }
+    serve(server);
+}
+
+fn serve<H: Handler, A: Acceptor>(server: Server<H, A>) {
+    // stop gracefully on SIGINT (Ctrl+C) or SIGTERM
+    let shutdown = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown.shutdown())
+        .expect("Should be able to register the signal handler");
 
-    let server = Server::new(listener, response_closure);
-    server.serve();
+    let summary = server.serve();
+    println!("Server stopped: {summary:?}");
 }
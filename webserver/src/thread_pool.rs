@@ -1,99 +1,694 @@
-use pooled_thread::PooledThread;
-use std::sync::mpsc;
+use job_queue::JobQueue;
+use pooled_thread::{PooledThread, Threads};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
 use std::thread;
+use std::time::{Duration, Instant};
 use thread_id::ThreadId;
 
+mod job;
+mod scope;
+mod work_stealing;
+
+pub use job::{JobError, JobHandle};
+pub use scope::Scope;
+
 // note: functions need to live for the static lifetime
 // since they could be waiting for an indefinite amount of time before the thread executes them
 type Function = Box<dyn FnOnce() + Send + 'static>;
 
+/// Threads taking jobs from a shared, bounded queue
+///
+/// The pool keeps its minimum number of threads running, starting more (up to the maximum) when jobs back up in the queue,
+/// and retiring the extra threads once they have been idle for the keep alive period.
+/// When the queue is full, new jobs are handled according to the pool's [`RejectionPolicy`].
+/// How threads share out the jobs is set by the pool's [`Scheduling`].
+/// A job that panics doesn't take down its thread, and any thread that does die is replaced.
 pub struct ThreadPool {
-    threads: Vec<PooledThread>,
-    thread_complete_receiver: mpsc::Receiver<ThreadId>,
+    threads: Arc<Threads>,
+    queue: Arc<JobQueue>,
+}
+
+/// A handle for queueing jobs on a [`ThreadPool`] from its own jobs, which can't borrow the pool
+///
+/// Jobs queued once the pool has shut down are rejected with [`ExecuteError::ShutDown`].
+#[derive(Clone)]
+pub struct PoolHandle {
+    threads: Arc<Threads>,
+    queue: Arc<JobQueue>,
+}
+
+/// The number of threads and size of the queue for a [`ThreadPool`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    min_threads: u32,
+    max_threads: u32,
+    queue_capacity: usize,
+    policy: RejectionPolicy,
+    keep_alive: Duration,
+    scheduling: Scheduling,
+}
+
+/// How jobs are shared out between the threads of a pool
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scheduling {
+    /// Every job goes through the shared queue, and runs in the order it was queued
+    SharedQueue,
+    /// Each thread also has its own deque, which jobs queued from that thread go to,
+    /// and idle threads steal jobs from the other threads' deques
+    ///
+    /// This balances jobs that split their work into more jobs, and runs them while their data is still in cache.
+    /// Jobs in the threads' deques count towards the queue capacity, but a pool thread is never blocked
+    /// by [`RejectionPolicy::Block`], since blocking a thread on its own pool could deadlock.
+    WorkStealing,
+}
+
+/// What to do with a new job when the queue is already full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RejectionPolicy {
+    /// Wait until a thread takes a job from the queue, making room for the new one
+    Block,
+    /// Discard the new job, counting it in [`ThreadPool::dropped_jobs`]
+    Drop,
+    /// Return [`ExecuteError::QueueFull`], so the caller can decide what to do
+    Error,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExecuteError {
+    QueueFull,
+    ShutDown,
+}
+
+/// What happened to the threads of a pool when it was shut down
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// Threads that finished their work before the deadline and were joined
+    pub joined: usize,
+    /// Threads still running a job at the deadline, which are left to finish in the background
+    pub abandoned: usize,
+}
+
+/// How often to check whether the threads have finished while shutting down
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// The number of jobs each thread can have waiting in the queue, unless configured otherwise
+const DEFAULT_QUEUE_CAPACITY_PER_THREAD: usize = 16;
+/// The default time a thread above the minimum can be idle before it is retired
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+impl PoolConfig {
+    /// A pool of `num_threads` that doesn't grow, and blocks when adding jobs to a full queue
+    pub fn new(num_threads: u32) -> Self {
+        PoolConfig {
+            min_threads: num_threads,
+            max_threads: num_threads,
+            queue_capacity: num_threads as usize * DEFAULT_QUEUE_CAPACITY_PER_THREAD,
+            policy: RejectionPolicy::Block,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            scheduling: Scheduling::SharedQueue,
+        }
+    }
+
+    /// Let the pool start up to `max_threads` when jobs are waiting in the queue
+    pub fn with_max_threads(mut self, max_threads: u32) -> Self {
+        self.max_threads = max_threads;
+        self
+    }
+
+    /// Set how many jobs can wait for a thread, and what to do with new jobs once the queue is full
+    pub fn with_queue(mut self, queue_capacity: usize, policy: RejectionPolicy) -> Self {
+        self.queue_capacity = queue_capacity;
+        self.policy = policy;
+        self
+    }
+
+    /// Set how long a thread above the minimum can be idle before it is retired
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn with_scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
 }
 
 impl ThreadPool {
-    /// Create a new ThreadPool.
+    /// Create a new ThreadPool, that blocks when adding jobs to a full queue
     ///
     /// # Panics
     ///
     /// The `new` function will panic if the size is zero.
     pub fn new(num_threads: u32) -> ThreadPool {
+        ThreadPool::from_config(PoolConfig::new(num_threads))
+    }
+
+    /// Create a new ThreadPool, with a queue holding up to `queue_capacity` jobs that are waiting for a thread
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of threads or queue capacity is zero.
+    pub fn with_queue(
+        num_threads: u32,
+        queue_capacity: usize,
+        policy: RejectionPolicy,
+    ) -> ThreadPool {
+        ThreadPool::from_config(PoolConfig::new(num_threads).with_queue(queue_capacity, policy))
+    }
+
+    /// Create a new ThreadPool, starting the minimum number of threads
+    ///
+    /// # Panics
+    ///
+    /// Panics if the minimum number of threads or queue capacity is zero,
+    /// or the maximum number of threads is below the minimum.
+    pub fn from_config(config: PoolConfig) -> ThreadPool {
         assert!(
-            num_threads > 0,
+            config.min_threads > 0,
             "Must provide more than 0 threads for work to be done"
         );
-        let (sender, receiver) = mpsc::channel::<ThreadId>();
-        let threads = (0..num_threads)
-            .map(|id| PooledThread::start(ThreadId::new(id), sender.clone()))
-            .collect();
-        ThreadPool {
-            threads,
-            thread_complete_receiver: receiver,
+        assert!(
+            config.max_threads >= config.min_threads,
+            "The maximum number of threads can't be below the minimum"
+        );
+        assert!(
+            config.queue_capacity > 0,
+            "The queue must be able to hold jobs"
+        );
+        let pool = ThreadPool {
+            threads: Arc::new(Threads::new()),
+            queue: Arc::new(JobQueue::new(config)),
+        };
+        pool.manage_threads();
+        pool
+    }
+
+    /// Queue the work to be run on one of the threads
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, work: F) -> Result<(), ExecuteError> {
+        self.handle().execute(work)
+    }
+
+    /// Run the work on one of the threads, returning a handle to wait for its result
+    pub fn spawn<F, T>(&self, work: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = job::job(work);
+        self.execute(move || job.run())?;
+        Ok(handle)
+    }
+
+    /// Run jobs on the pool that can borrow data from outside the scope, waiting for them all to finish before returning
+    ///
+    /// Calling this from a job on the same pool can deadlock, if every thread ends up waiting on a scope.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope::new(self);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        // the jobs may borrow from the caller, so they have to finish even if `f` panicked
+        scope.wait();
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// A handle that queues jobs on this pool
+    pub fn handle(&self) -> PoolHandle {
+        PoolHandle {
+            threads: Arc::clone(&self.threads),
+            queue: Arc::clone(&self.queue),
         }
     }
 
-    pub fn execute<F: FnOnce() + Send + 'static>(&self, work: F) {
-        let ready_id = self.thread_complete_receiver.recv().unwrap();
-        self.threads[ready_id.id() as usize].execute(Box::new(work));
+    /// Clean up threads that retired or died, and start any threads the queue needs
+    fn manage_threads(&self) {
+        self.threads.manage(&self.queue);
+    }
+
+    /// The number of jobs waiting for a thread
+    pub fn queue_depth(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The number of threads currently running a job
+    pub fn active_workers(&self) -> usize {
+        self.queue.active_workers()
     }
 
-    // todo: implement drop to terminate all the threads
+    /// The number of threads in the pool, which are either running or waiting for a job
+    pub fn num_threads(&self) -> usize {
+        self.queue.num_threads()
+    }
+
+    /// The number of jobs discarded because the queue was full, when using [`RejectionPolicy::Drop`]
+    pub fn dropped_jobs(&self) -> usize {
+        self.queue.dropped_jobs()
+    }
+
+    /// Stop accepting jobs, and wait up to the timeout for the threads to finish the queued jobs
+    ///
+    /// Threads that finish in time are joined, any others are detached so a stuck job can't block shutdown forever.
+    pub fn shutdown(self, timeout: Duration) -> ShutdownSummary {
+        let deadline = Instant::now() + timeout;
+        let mut threads = {
+            let mut threads = self.threads.lock();
+            // only count the threads that hadn't already retired
+            threads.retain(|thread| !thread.is_finished());
+            // closed while holding the lock, so a thread dying now can't start a replacement that's missed here
+            self.queue.close();
+            std::mem::take(&mut *threads)
+        };
+        while Instant::now() < deadline && !threads.iter().all(PooledThread::is_finished) {
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+
+        let mut summary = ShutdownSummary {
+            joined: 0,
+            abandoned: 0,
+        };
+        for thread in threads.drain(..) {
+            if thread.is_finished() {
+                thread.join();
+                summary.joined += 1;
+            } else {
+                thread.detach();
+                summary.abandoned += 1;
+            }
+        }
+        summary
+    }
+}
+
+impl PoolHandle {
+    /// Queue the work to be run on one of the pool's threads, handling a full queue by the pool's policy
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, work: F) -> Result<(), ExecuteError> {
+        self.push(Box::new(work), self.queue.policy())
+    }
+
+    /// Queue the work to be run on one of the pool's threads, returning [`ExecuteError::QueueFull`]
+    /// rather than waiting or discarding the work when the queue is full
+    pub fn try_execute<F: FnOnce() + Send + 'static>(&self, work: F) -> Result<(), ExecuteError> {
+        self.push(Box::new(work), RejectionPolicy::Error)
+    }
+
+    fn push(&self, job: Function, policy: RejectionPolicy) -> Result<(), ExecuteError> {
+        // replace any threads that died, so there's a thread to take the job
+        self.threads.manage(&self.queue);
+        self.queue.push(job, policy)?;
+        // start another thread if the job has to wait
+        self.threads.manage(&self.queue);
+        Ok(())
+    }
+}
+
+/// Drop waits for every thread to finish the queued jobs
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // close the queue first, so the threads all finish in parallel rather than one after the other
+        self.queue.close();
+        self.threads.take().into_iter().for_each(PooledThread::join);
+    }
+}
+
+mod job_queue {
+    use super::work_stealing::WorkerDeques;
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{fence, AtomicUsize};
+    use std::sync::Condvar;
+
+    /// A bounded queue of jobs shared between the pool and its threads
+    pub struct JobQueue {
+        state: Mutex<QueueState>,
+        /// The threads' own deques, when work stealing
+        deques: Option<WorkerDeques>,
+        /// Threads waiting for a job, only changed while holding the state lock
+        idle_threads: AtomicUsize,
+        /// Notified when a job is added or the queue is closed
+        job_added: Condvar,
+        /// Notified when a job is taken or the queue is closed
+        job_taken: Condvar,
+        config: PoolConfig,
+        active_workers: AtomicUsize,
+        dropped_jobs: AtomicUsize,
+    }
+
+    struct QueueState {
+        jobs: VecDeque<Function>,
+        closed: bool,
+        /// Threads that have started and not yet exited
+        threads: u32,
+    }
+
+    impl JobQueue {
+        pub fn new(config: PoolConfig) -> JobQueue {
+            JobQueue {
+                state: Mutex::new(QueueState {
+                    jobs: VecDeque::with_capacity(config.queue_capacity),
+                    closed: false,
+                    threads: 0,
+                }),
+                deques: match config.scheduling {
+                    Scheduling::SharedQueue => None,
+                    Scheduling::WorkStealing => Some(WorkerDeques::new()),
+                },
+                idle_threads: AtomicUsize::new(0),
+                job_added: Condvar::new(),
+                job_taken: Condvar::new(),
+                config,
+                active_workers: AtomicUsize::new(0),
+                dropped_jobs: AtomicUsize::new(0),
+            }
+        }
+
+        /// Add a job, handling a full queue by the given policy rather than the pool's
+        pub fn push(&self, job: Function, policy: RejectionPolicy) -> Result<(), ExecuteError> {
+            // only the pool's own threads have a deque
+            let is_pool_thread = self
+                .deques
+                .as_ref()
+                .is_some_and(WorkerDeques::is_registered);
+            let mut state = self.state.lock().unwrap();
+            loop {
+                if state.closed {
+                    return Err(ExecuteError::ShutDown);
+                }
+                let local_jobs = self.deques.as_ref().map_or(0, WorkerDeques::len);
+                if state.jobs.len() + local_jobs < self.config.queue_capacity {
+                    break;
+                }
+                match policy {
+                    // a pool thread waiting for room could be the one that has to make it
+                    RejectionPolicy::Block if is_pool_thread => break,
+                    RejectionPolicy::Block => state = self.job_taken.wait(state).unwrap(),
+                    RejectionPolicy::Drop => {
+                        self.dropped_jobs.fetch_add(1, Ordering::SeqCst);
+                        return Ok(());
+                    }
+                    RejectionPolicy::Error => return Err(ExecuteError::QueueFull),
+                }
+            }
+            let job = match &self.deques {
+                Some(deques) => match deques.push_local(job) {
+                    Ok(()) => {
+                        drop(state);
+                        self.wake_idle_thread();
+                        return Ok(());
+                    }
+                    Err(job) => job,
+                },
+                None => job,
+            };
+            state.jobs.push_back(job);
+            self.job_added.notify_one();
+            Ok(())
+        }
+
+        pub fn policy(&self) -> RejectionPolicy {
+            self.config.policy
+        }
+
+        /// Wait for the next job, returning None when the thread should exit
+        ///
+        /// Threads exit once the queue is closed and empty, or they've been idle for the keep alive period
+        /// and there are more than the minimum number of threads.
+        /// The job counts as active until [`JobQueue::finish`] is called
+        pub fn pop(&self) -> Option<Function> {
+            // a thread's own jobs come first, then the shared queue, then jobs stolen from other threads
+            if let Some(job) = self.deques.as_ref().and_then(WorkerDeques::pop_local) {
+                // a job pushed while the queue was full may be waiting for the room
+                let _state = self.state.lock().unwrap();
+                self.job_taken.notify_one();
+                return Some(self.start(job));
+            }
+            let mut state = self.state.lock().unwrap();
+            let mut timed_out = false;
+            loop {
+                if let Some(job) = state.jobs.pop_front() {
+                    self.job_taken.notify_one();
+                    return Some(self.start(job));
+                }
+                // count as idle before stealing, so a job pushed to another thread's deque is either
+                // found here or wakes this thread up
+                self.idle_threads.fetch_add(1, Ordering::SeqCst);
+                fence(Ordering::SeqCst);
+                if let Some(job) = self.deques.as_ref().and_then(WorkerDeques::steal) {
+                    self.job_taken.notify_one();
+                    self.idle_threads.fetch_sub(1, Ordering::SeqCst);
+                    return Some(self.start(job));
+                }
+                if state.closed || (timed_out && state.threads > self.config.min_threads) {
+                    self.idle_threads.fetch_sub(1, Ordering::SeqCst);
+                    state.threads -= 1;
+                    return None;
+                }
+                let (new_state, wait) = self
+                    .job_added
+                    .wait_timeout(state, self.config.keep_alive)
+                    .unwrap();
+                state = new_state;
+                self.idle_threads.fetch_sub(1, Ordering::SeqCst);
+                timed_out = wait.timed_out();
+            }
+        }
+
+        fn start(&self, job: Function) -> Function {
+            self.active_workers.fetch_add(1, Ordering::SeqCst);
+            job
+        }
+
+        /// Wake a thread waiting for a job, after a job was pushed to a thread's deque
+        fn wake_idle_thread(&self) {
+            fence(Ordering::SeqCst);
+            if self.idle_threads.load(Ordering::SeqCst) > 0 {
+                // waiting threads hold the lock until they are waiting, so they can't miss the notification
+                let _state = self.state.lock().unwrap();
+                self.job_added.notify_one();
+            }
+        }
+
+        /// Set up the current thread to take jobs from the queue
+        pub fn register_thread(&self) {
+            if let Some(deques) = &self.deques {
+                deques.register();
+            }
+        }
+
+        /// Stop the current thread taking jobs, giving any jobs left in its deque to the other threads
+        pub fn unregister_thread(&self) {
+            if let Some(deques) = &self.deques {
+                let jobs = deques.unregister();
+                if !jobs.is_empty() {
+                    self.state.lock().unwrap().jobs.extend(jobs);
+                    self.job_added.notify_all();
+                }
+            }
+        }
+
+        /// Whether another thread should be started, counting it as started if so
+        ///
+        /// The pool needs a thread when it is below the minimum, or jobs are waiting with no idle thread to take them
+        pub fn needs_thread(&self) -> bool {
+            let mut state = self.state.lock().unwrap();
+            let below_min = state.threads < self.config.min_threads;
+            let backed_up = state.jobs.len() > self.idle_threads.load(Ordering::SeqCst)
+                && state.threads < self.config.max_threads;
+            if state.closed || !(below_min || backed_up) {
+                return false;
+            }
+            state.threads += 1;
+            true
+        }
+
+        /// Stop counting a thread that exited without being told to, e.g. because it panicked
+        pub fn remove_thread(&self) {
+            self.state.lock().unwrap().threads -= 1;
+        }
+
+        pub fn num_threads(&self) -> usize {
+            self.state.lock().unwrap().threads as usize
+        }
+
+        /// Mark a job taken with [`JobQueue::pop`] as finished
+        pub fn finish(&self) {
+            self.active_workers.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        /// Stop accepting jobs, the jobs already queued will still be run
+        pub fn close(&self) {
+            self.state.lock().unwrap().closed = true;
+            self.job_added.notify_all();
+            self.job_taken.notify_all();
+        }
+
+        pub fn len(&self) -> usize {
+            let local_jobs = self.deques.as_ref().map_or(0, WorkerDeques::len);
+            self.state.lock().unwrap().jobs.len() + local_jobs
+        }
+
+        pub fn active_workers(&self) -> usize {
+            self.active_workers.load(Ordering::SeqCst)
+        }
+
+        pub fn dropped_jobs(&self) -> usize {
+            self.dropped_jobs.load(Ordering::SeqCst)
+        }
+    }
 }
 
 mod pooled_thread {
     use super::*;
 
-    /// A thread owned by the thread pool, with a way to send work to it
+    /// The threads of a pool, shared with the threads themselves so that one which dies can start its replacement
+    pub struct Threads {
+        threads: Mutex<Vec<PooledThread>>,
+        next_thread_id: AtomicU32,
+    }
+
+    /// A thread owned by the thread pool, which runs jobs from the pool's queue
     pub struct PooledThread {
         thread: Option<thread::JoinHandle<()>>,
-        work_sender: Option<mpsc::Sender<Function>>,
+    }
+
+    impl Threads {
+        pub fn new() -> Threads {
+            Threads {
+                threads: Mutex::new(Vec::new()),
+                next_thread_id: AtomicU32::new(0),
+            }
+        }
+
+        /// Clean up threads that retired or died, and start any threads the queue needs
+        pub fn manage(self: &Arc<Self>, queue: &Arc<JobQueue>) {
+            let mut threads = self.lock();
+            let (finished, running) = threads.drain(..).partition(PooledThread::is_finished);
+            *threads = running;
+            finished.into_iter().for_each(PooledThread::join);
+            while queue.needs_thread() {
+                let thread_id = ThreadId::new(self.next_thread_id.fetch_add(1, Ordering::SeqCst));
+                let thread =
+                    PooledThread::start(thread_id, Arc::clone(queue), Arc::downgrade(self));
+                threads.push(thread);
+            }
+        }
+
+        /// Take every thread, to be joined without holding the lock that dying threads need
+        pub fn take(&self) -> Vec<PooledThread> {
+            std::mem::take(&mut *self.lock())
+        }
+
+        /// Lock the threads, which stay consistent even if a thread panicked while holding the lock
+        pub fn lock(&self) -> MutexGuard<'_, Vec<PooledThread>> {
+            self.threads.lock().unwrap_or_else(PoisonError::into_inner)
+        }
     }
 
     impl PooledThread {
-        /// Start a pooled thread that polls for work, returning a handle that sends the thread work
-        pub fn start(thread_id: ThreadId, work_complete_sender: mpsc::Sender<ThreadId>) -> Self {
-            let (sender, receiver) = mpsc::channel::<Function>();
-            let thread = thread::spawn(move || {
-                work_complete_sender.send(thread_id).unwrap();
-                for work in receiver {
-                    work();
-                    work_complete_sender.send(thread_id).unwrap();
-                }
-            });
+        /// Start a pooled thread that polls the queue for work, until the queue says to exit
+        pub fn start(thread_id: ThreadId, queue: Arc<JobQueue>, threads: Weak<Threads>) -> Self {
+            let thread = thread::Builder::new()
+                .name(format!("pool-worker-{}", thread_id.id()))
+                .spawn(move || {
+                    queue.register_thread();
+                    let _exit_guard = ExitGuard {
+                        queue: Arc::clone(&queue),
+                        thread_id,
+                        threads,
+                    };
+                    while let Some(work) = queue.pop() {
+                        // catch panics so one bad job doesn't take down the thread
+                        let result = panic::catch_unwind(AssertUnwindSafe(work));
+                        queue.finish();
+                        if let Err(payload) = result {
+                            eprintln!(
+                                "A job panicked on thread {}: {}",
+                                thread_id.id(),
+                                panic_message(payload.as_ref())
+                            );
+                        }
+                    }
+                })
+                .expect("Should be able to spawn a thread");
             PooledThread {
                 thread: Some(thread),
-                work_sender: Some(sender),
             }
         }
 
-        /// Execute work on the thread
-        pub fn execute(&self, work: Function) {
-            self.work_sender
+        pub fn is_finished(&self) -> bool {
+            self.thread
                 .as_ref()
-                .expect("The sender should sill be open when executing")
-                .send(work)
-                .expect("The thread with the receiver should still be open");
+                .is_none_or(|thread| thread.is_finished())
+        }
+
+        /// Wait for the thread to finish, which it does once the queue says to exit
+        pub fn join(mut self) {
+            self.join_thread();
+        }
+
+        /// Leave the thread to finish its current job in the background
+        pub fn detach(mut self) {
+            self.thread.take();
+        }
+
+        fn join_thread(&mut self) {
+            if let Some(thread) = self.thread.take() {
+                // a job holding the last reference to its pool drops the pool on this thread, which can't join itself
+                if thread.thread().id() == thread::current().id() {
+                    return;
+                }
+                // the thread only fails if it panicked outside a job, which was already reported
+                let _ = thread.join();
+            }
+        }
+    }
+
+    /// Notices when a thread dies, so that it can be replaced
+    struct ExitGuard {
+        queue: Arc<JobQueue>,
+        thread_id: ThreadId,
+        /// Weak, so the threads don't keep their pool's threads alive once the pool is dropped
+        threads: Weak<Threads>,
+    }
+
+    impl Drop for ExitGuard {
+        fn drop(&mut self) {
+            self.queue.unregister_thread();
+            // threads that exit normally have already been removed by the queue
+            if thread::panicking() {
+                eprintln!("Thread {} of the pool died", self.thread_id.id());
+                self.queue.remove_thread();
+                // start the replacement now, so an idle pool doesn't stay short of threads
+                if let Some(threads) = self.threads.upgrade() {
+                    threads.manage(&self.queue);
+                }
+            }
         }
     }
 
     /// Drop waits for the thread to finish
     impl Drop for PooledThread {
         fn drop(&mut self) {
-            // drop the work sender so the thread no longer receives any new jobs
-            // (as the receiver will close and return None when trying to iterate to the next message)
-            self.work_sender.take();
-
-            // stop the job (if any) running on the thread
-            self.thread
-                .take()
-                .expect("Thread shouldn't be dropped yet")
-                .join()
-                .unwrap()
+            // wait for the job (if any) running on the thread
+            self.join_thread();
         }
     }
 }
 
+/// The message a panic was started with, if it was given one
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
 mod thread_id {
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub struct ThreadId {
@@ -110,3 +705,363 @@ mod thread_id {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::mpsc;
+
+    /// Occupy every thread of the pool until the returned sender is dropped
+    fn block_threads(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let receiver = Arc::new(std::sync::Mutex::new(receiver));
+        for _ in 0..pool.num_threads() {
+            let receiver = Arc::clone(&receiver);
+            pool.execute(move || {
+                let _ = receiver.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+        while pool.active_workers() < pool.num_threads() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        sender
+    }
+
+    #[test]
+    fn test_drop_finishes_jobs() {
+        let completed = Arc::new(AtomicU32::new(0));
+        let pool = ThreadPool::new(2);
+        for _ in 0..4 {
+            let completed = Arc::clone(&completed);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(20));
+                completed.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        drop(pool);
+        assert_eq!(completed.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_execute_does_not_wait_for_a_thread() {
+        let pool = ThreadPool::with_queue(1, 2, RejectionPolicy::Error);
+        let blocker = block_threads(&pool);
+
+        // jobs are queued while the only thread is busy
+        assert_eq!(pool.execute(|| {}), Ok(()));
+        assert_eq!(pool.execute(|| {}), Ok(()));
+        assert_eq!(pool.queue_depth(), 2);
+        assert_eq!(pool.active_workers(), 1);
+        assert_eq!(pool.execute(|| {}), Err(ExecuteError::QueueFull));
+
+        drop(blocker);
+        pool.shutdown(Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_handle() {
+        let pool = ThreadPool::with_queue(1, 1, RejectionPolicy::Block);
+        let handle = pool.handle();
+        let blocker = block_threads(&pool);
+
+        // trying doesn't wait for room, whatever the pool's policy
+        assert_eq!(handle.execute(|| {}), Ok(()));
+        assert_eq!(handle.try_execute(|| {}), Err(ExecuteError::QueueFull));
+
+        drop(blocker);
+        pool.shutdown(Duration::from_secs(5));
+        assert_eq!(handle.try_execute(|| {}), Err(ExecuteError::ShutDown));
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        let pool = ThreadPool::with_queue(1, 1, RejectionPolicy::Drop);
+        let blocker = block_threads(&pool);
+        let completed = Arc::new(AtomicU32::new(0));
+        for _ in 0..3 {
+            let completed = Arc::clone(&completed);
+            let job = move || {
+                completed.fetch_add(1, Ordering::SeqCst);
+            };
+            assert_eq!(pool.execute(job), Ok(()));
+        }
+        assert_eq!(pool.dropped_jobs(), 2);
+
+        drop(blocker);
+        drop(pool);
+        assert_eq!(completed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_block_policy() {
+        let pool = Arc::new(ThreadPool::with_queue(1, 1, RejectionPolicy::Block));
+        let blocker = block_threads(&pool);
+        pool.execute(|| {}).unwrap();
+
+        let blocked_pool = Arc::clone(&pool);
+        let blocked = thread::spawn(move || blocked_pool.execute(|| {}));
+        thread::sleep(Duration::from_millis(20));
+        assert!(!blocked.is_finished());
+
+        // once the thread is free the queue drains, letting the blocked job in
+        drop(blocker);
+        assert_eq!(blocked.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn test_elastic_pool() {
+        let config = PoolConfig::new(1)
+            .with_max_threads(3)
+            .with_queue(8, RejectionPolicy::Error)
+            .with_keep_alive(Duration::from_millis(50));
+        let pool = ThreadPool::from_config(config);
+        assert_eq!(pool.num_threads(), 1);
+
+        // jobs waiting in the queue start new threads, up to the maximum
+        let (sender, receiver) = mpsc::channel::<()>();
+        let receiver = Arc::new(std::sync::Mutex::new(receiver));
+        for _ in 0..4 {
+            let receiver = Arc::clone(&receiver);
+            pool.execute(move || {
+                let _ = receiver.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+        assert_eq!(pool.num_threads(), 3);
+
+        // the extra threads retire once they've been idle for the keep alive period
+        drop(sender);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.num_threads() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.num_threads(), 1);
+        assert_eq!(pool.queue_depth(), 0);
+    }
+
+    fn work_stealing_pool(num_threads: u32) -> Arc<ThreadPool> {
+        let config = PoolConfig::new(num_threads).with_scheduling(Scheduling::WorkStealing);
+        Arc::new(ThreadPool::from_config(config))
+    }
+
+    #[test]
+    fn test_work_stealing_runs_own_jobs_last_in_first_out() {
+        let pool = work_stealing_pool(1);
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (sender, receiver) = mpsc::channel();
+        let inner_pool = Arc::clone(&pool);
+        let inner_order = Arc::clone(&order);
+        pool.execute(move || {
+            for job in 1..=3 {
+                let order = Arc::clone(&inner_order);
+                inner_pool
+                    .execute(move || order.lock().unwrap().push(job))
+                    .unwrap();
+            }
+            sender.send(inner_pool.queue_depth()).unwrap();
+        })
+        .unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(3));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while order.lock().unwrap().len() < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(*order.lock().unwrap(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_work_stealing_respects_queue() {
+        let config = PoolConfig::new(1)
+            .with_queue(2, RejectionPolicy::Error)
+            .with_scheduling(Scheduling::WorkStealing);
+        let pool = ThreadPool::from_config(config);
+        let handle = pool.handle();
+        let (started_sender, started) = mpsc::channel();
+        let (results_sender, results) = mpsc::channel();
+        pool.execute(move || {
+            // jobs queued from a pool thread count towards the capacity
+            let mut queued: Vec<_> = (0..3).map(|_| handle.execute(|| {})).collect();
+            started_sender.send(()).unwrap();
+            // by now the pool is shutting down, so no more jobs are taken
+            thread::sleep(Duration::from_millis(200));
+            queued.push(handle.execute(|| {}));
+            results_sender.send(queued).unwrap();
+        })
+        .unwrap();
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        let summary = pool.shutdown(Duration::from_secs(5));
+        assert_eq!(summary.joined, 1);
+        assert_eq!(
+            results.recv().unwrap(),
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(ExecuteError::QueueFull),
+                Err(ExecuteError::ShutDown)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_work_stealing_idle_threads_steal() {
+        let pool = work_stealing_pool(2);
+        let (sender, receiver) = mpsc::channel();
+        let inner_pool = Arc::clone(&pool);
+        pool.execute(move || {
+            let (done_sender, done_receiver) = mpsc::channel();
+            for _ in 0..3 {
+                let done_sender = done_sender.clone();
+                let job = move || done_sender.send(thread::current().id()).unwrap();
+                inner_pool.execute(job).unwrap();
+            }
+            // the jobs are in this thread's deque, so the other thread has to steal them while this one waits
+            let runners: Vec<_> = (0..3).map(|_| done_receiver.recv().unwrap()).collect();
+            sender.send((thread::current().id(), runners)).unwrap();
+        })
+        .unwrap();
+
+        let (parent, runners) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(runners.iter().all(|runner| *runner != parent));
+    }
+
+    #[test]
+    fn test_panicking_job_keeps_thread() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed")).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || {
+            sender
+                .send(thread::current().name().map(String::from))
+                .unwrap()
+        })
+        .unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Ok(Some("pool-worker-0".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_dead_thread_is_replaced() {
+        /// Panics again when the caught panic is dropped, which kills the thread
+        struct PanicOnDrop;
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                panic!("payload dropped")
+            }
+        }
+
+        let pool = ThreadPool::new(2);
+        pool.execute(|| std::panic::panic_any(PanicOnDrop)).unwrap();
+        while !pool.threads.lock().iter().any(PooledThread::is_finished) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // the dying thread started its replacement, without waiting for another job to be submitted
+        assert_eq!(pool.num_threads(), 2);
+        // both threads must be alive to run the blocking jobs at once
+        let blocker = block_threads(&pool);
+        assert_eq!(pool.active_workers(), 2);
+        drop(blocker);
+        assert_eq!(
+            pool.shutdown(Duration::from_secs(5)),
+            ShutdownSummary {
+                joined: 2,
+                abandoned: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_spawn() {
+        let pool = ThreadPool::new(2);
+        let sum = pool.spawn(|| (1..=10).sum::<u32>()).unwrap();
+        let failed = pool.spawn(|| -> u32 { panic!("job failed") }).unwrap();
+        assert_eq!(sum.wait(), Ok(55));
+        assert_eq!(
+            failed.wait(),
+            Err(JobError::Panicked("job failed".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_spawn_wait_timeout_and_cancel() {
+        let pool = ThreadPool::new(1);
+        let blocker = block_threads(&pool);
+        let queued = pool.spawn(|| "ran").unwrap();
+        assert!(!queued.wait_timeout(Duration::from_millis(10)));
+        assert!(queued.cancel());
+        assert!(queued.wait_timeout(Duration::from_millis(10)));
+        assert_eq!(queued.wait(), Err(JobError::Cancelled));
+
+        let started = pool.spawn(|| "ran").unwrap();
+        drop(blocker);
+        assert!(started.wait_timeout(Duration::from_secs(5)));
+        assert!(!started.cancel());
+        assert_eq!(started.wait(), Ok("ran"));
+    }
+
+    #[test]
+    fn test_spawn_discarded() {
+        let pool = ThreadPool::with_queue(1, 1, RejectionPolicy::Drop);
+        let blocker = block_threads(&pool);
+        let queued = pool.spawn(|| 1).unwrap();
+        let discarded = pool.spawn(|| 2).unwrap();
+        assert_eq!(discarded.wait(), Err(JobError::Discarded));
+        drop(blocker);
+        assert_eq!(queued.wait(), Ok(1));
+    }
+
+    #[test]
+    fn test_scope_borrows() {
+        let pool = ThreadPool::new(4);
+        let numbers: Vec<u32> = (1..=100).collect();
+        let mut total = 0;
+        let sums = pool.scope(|scope| {
+            let handles: Vec<_> = numbers
+                .chunks(10)
+                .map(|chunk| scope.spawn(|| chunk.iter().sum::<u32>()).unwrap())
+                .collect();
+            // jobs can also mutably borrow, since the scope outlives them
+            scope.spawn(|| total = numbers.len()).unwrap();
+            handles
+                .into_iter()
+                .map(|handle| handle.wait().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(sums.iter().sum::<u32>(), 5050);
+        assert_eq!(total, 100);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let pool = ThreadPool::new(3);
+        pool.execute(|| thread::sleep(Duration::from_millis(20)))
+            .unwrap();
+        assert_eq!(
+            pool.shutdown(Duration::from_secs(5)),
+            ShutdownSummary {
+                joined: 3,
+                abandoned: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_shutdown_abandons_stuck_jobs() {
+        let pool = ThreadPool::new(2);
+        pool.execute(|| thread::sleep(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(
+            pool.shutdown(Duration::from_millis(50)),
+            ShutdownSummary {
+                joined: 1,
+                abandoned: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use super::panic_message;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// A handle to the result of a job started with [`ThreadPool::spawn`](super::ThreadPool::spawn)
+///
+/// Dropping the handle doesn't stop the job, it just discards the result.
+pub struct JobHandle<T> {
+    state: Arc<JobState<T>>,
+}
+
+/// Why a job didn't produce a result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    /// The job was cancelled before it started
+    Cancelled,
+    /// The pool discarded the job without running it
+    Discarded,
+    /// The job panicked with the given message
+    Panicked(String),
+}
+
+/// The half of a job given to the pool, which reports the result of the work to the [`JobHandle`]
+pub(super) struct Job<F, T> {
+    work: Option<F>,
+    state: Arc<JobState<T>>,
+}
+
+struct JobState<T> {
+    status: Mutex<JobStatus<T>>,
+    finished: Condvar,
+}
+
+enum JobStatus<T> {
+    Queued,
+    Running,
+    Finished(Result<T, JobError>),
+    /// The result was taken by [`JobHandle::wait`]
+    Taken,
+}
+
+/// Split work into the job to run on the pool, and the handle used to wait for it
+pub(super) fn job<F: FnOnce() -> T, T>(work: F) -> (Job<F, T>, JobHandle<T>) {
+    let state = Arc::new(JobState {
+        status: Mutex::new(JobStatus::Queued),
+        finished: Condvar::new(),
+    });
+    let job = Job {
+        work: Some(work),
+        state: Arc::clone(&state),
+    };
+    (job, JobHandle { state })
+}
+
+impl<F: FnOnce() -> T, T> Job<F, T> {
+    /// Run the work, unless the job was cancelled while queued
+    pub(super) fn run(mut self) {
+        {
+            let mut status = self.state.lock();
+            if !matches!(*status, JobStatus::Queued) {
+                return;
+            }
+            *status = JobStatus::Running;
+        }
+        let work = self.work.take().expect("A job can only run once");
+        let result = panic::catch_unwind(AssertUnwindSafe(work))
+            .map_err(|payload| JobError::Panicked(panic_message(payload.as_ref()).to_string()));
+        self.state.finish(result);
+    }
+}
+
+/// A job dropped before it runs, e.g. by a full queue, reports that it was discarded
+impl<F, T> Drop for Job<F, T> {
+    fn drop(&mut self) {
+        // drop the work first, so a scope waiting on the job can't end while the work still holds its borrows
+        drop(self.work.take());
+        if matches!(*self.state.lock(), JobStatus::Queued) {
+            self.state.finish(Err(JobError::Discarded));
+        }
+    }
+}
+
+impl<T> JobState<T> {
+    fn lock(&self) -> MutexGuard<'_, JobStatus<T>> {
+        // the lock is never held while running user code, so it can't be poisoned
+        self.status.lock().unwrap()
+    }
+
+    fn finish(&self, result: Result<T, JobError>) {
+        *self.lock() = JobStatus::Finished(result);
+        self.finished.notify_all();
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// Wait for the job to finish, returning its result
+    pub fn wait(self) -> Result<T, JobError> {
+        let status = self.state.lock();
+        let mut status = self
+            .state
+            .finished
+            .wait_while(status, |status| !matches!(status, JobStatus::Finished(_)))
+            .unwrap();
+        match std::mem::replace(&mut *status, JobStatus::Taken) {
+            JobStatus::Finished(result) => result,
+            _ => unreachable!("The job has finished"),
+        }
+    }
+
+    /// Wait up to the timeout for the job to finish, returning whether it did
+    ///
+    /// Once this returns true, [`JobHandle::wait`] returns the result without blocking
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let status = self.state.lock();
+        let (status, _) = self
+            .state
+            .finished
+            .wait_timeout_while(status, timeout, |status| {
+                !matches!(status, JobStatus::Finished(_))
+            })
+            .unwrap();
+        matches!(*status, JobStatus::Finished(_))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(*self.state.lock(), JobStatus::Finished(_))
+    }
+
+    /// Cancel the job if it hasn't started yet, returning whether it was cancelled
+    ///
+    /// A job that is already running can't be cancelled, and will run to completion.
+    pub fn cancel(&self) -> bool {
+        let mut status = self.state.lock();
+        if !matches!(*status, JobStatus::Queued) {
+            return false;
+        }
+        *status = JobStatus::Finished(Err(JobError::Cancelled));
+        self.state.finished.notify_all();
+        true
+    }
+}
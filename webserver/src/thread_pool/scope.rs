@@ -0,0 +1,93 @@
+use super::job::{self, Job, JobHandle};
+use super::{ExecuteError, Function, ThreadPool};
+use std::marker::PhantomData;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Spawns jobs that can borrow from outside the scope, created by [`ThreadPool::scope`]
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    pending: Arc<PendingJobs>,
+    // invariant lifetimes, so jobs can't borrow anything that ends before the scope does
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// Counts the jobs of a scope that haven't finished or been dropped
+#[derive(Default)]
+struct PendingJobs {
+    count: Mutex<usize>,
+    all_finished: Condvar,
+}
+
+/// A job spawned on a scope, which stays pending until the job has been dropped
+struct ScopedJob<F, T> {
+    // fields are dropped in order, so the job and its borrows are gone before the scope can end
+    job: Job<F, T>,
+    _pending: PendingGuard,
+}
+
+struct PendingGuard(Arc<PendingJobs>);
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub(super) fn new(pool: &'scope ThreadPool) -> Self {
+        Scope {
+            pool,
+            pending: Arc::new(PendingJobs::default()),
+            scope: PhantomData,
+            env: PhantomData,
+        }
+    }
+
+    /// Run the work on the pool, it may borrow anything that outlives the scope
+    pub fn spawn<F, T>(&'scope self, work: F) -> Result<JobHandle<T>, ExecuteError>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (job, handle) = job::job(work);
+        let scoped_job = ScopedJob {
+            job,
+            _pending: PendingGuard::new(Arc::clone(&self.pending)),
+        };
+        let work: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || scoped_job.run());
+        // SAFETY: `ThreadPool::scope` waits for every job spawned on the scope to be dropped before it returns,
+        // so the work can't be used after the data it borrows has gone
+        let work =
+            unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Function>(work) };
+        self.pool.execute(work)?;
+        Ok(handle)
+    }
+
+    /// Wait until every job spawned on the scope has finished or been dropped
+    pub(super) fn wait(&self) {
+        let count = self.pending.count.lock().unwrap();
+        let _all_finished = self
+            .pending
+            .all_finished
+            .wait_while(count, |count| *count > 0)
+            .unwrap();
+    }
+}
+
+impl<F: FnOnce() -> T, T> ScopedJob<F, T> {
+    fn run(self) {
+        self.job.run();
+    }
+}
+
+impl PendingGuard {
+    fn new(pending: Arc<PendingJobs>) -> Self {
+        *pending.count.lock().unwrap() += 1;
+        PendingGuard(pending)
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.0.all_finished.notify_all();
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use super::Function;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+type Deque = Mutex<VecDeque<Function>>;
+
+/// The deques of every thread in a pool using [`Scheduling::WorkStealing`](super::Scheduling::WorkStealing)
+///
+/// A thread pushes and pops jobs at the back of its own deque, so the job it most recently queued runs next
+/// while its data is still in cache. Idle threads steal from the front of other deques, taking the oldest jobs.
+pub(super) struct WorkerDeques {
+    deques: RwLock<Vec<Arc<Deque>>>,
+    /// Where the next steal starts looking, so threads don't all steal from the same deque
+    next_victim: AtomicUsize,
+}
+
+thread_local! {
+    /// The deque of the pool thread running on this thread, along with the address of the pool's deques
+    static CURRENT_DEQUE: RefCell<Option<(usize, Arc<Deque>)>> = const { RefCell::new(None) };
+}
+
+impl WorkerDeques {
+    pub fn new() -> Self {
+        WorkerDeques {
+            deques: RwLock::new(Vec::new()),
+            next_victim: AtomicUsize::new(0),
+        }
+    }
+
+    /// Identifies these deques, to tell whether the current thread belongs to this pool
+    fn id(&self) -> usize {
+        self as *const WorkerDeques as usize
+    }
+
+    /// Give the current thread its own deque, which other threads can steal from
+    pub fn register(&self) {
+        let deque = Arc::new(Deque::default());
+        self.deques.write().unwrap().push(Arc::clone(&deque));
+        CURRENT_DEQUE.with(|current| *current.borrow_mut() = Some((self.id(), deque)));
+    }
+
+    /// Remove the current thread's deque, returning any jobs left in it
+    pub fn unregister(&self) -> VecDeque<Function> {
+        let Some(deque) = self.current_deque() else {
+            return VecDeque::new();
+        };
+        CURRENT_DEQUE.with(|current| current.borrow_mut().take());
+        self.deques
+            .write()
+            .unwrap()
+            .retain(|other| !Arc::ptr_eq(other, &deque));
+        let mut jobs = deque.lock().unwrap();
+        std::mem::take(&mut *jobs)
+    }
+
+    /// Whether the current thread is one of the pool's, with its own deque
+    pub fn is_registered(&self) -> bool {
+        self.current_deque().is_some()
+    }
+
+    fn current_deque(&self) -> Option<Arc<Deque>> {
+        CURRENT_DEQUE.with(|current| match &*current.borrow() {
+            Some((id, deque)) if *id == self.id() => Some(Arc::clone(deque)),
+            _ => None,
+        })
+    }
+
+    /// Push the job to the current thread's deque, giving it back if this thread isn't in the pool
+    pub fn push_local(&self, job: Function) -> Result<(), Function> {
+        match self.current_deque() {
+            Some(deque) => {
+                deque.lock().unwrap().push_back(job);
+                Ok(())
+            }
+            None => Err(job),
+        }
+    }
+
+    /// Take the job most recently pushed to the current thread's deque
+    pub fn pop_local(&self) -> Option<Function> {
+        self.current_deque()?.lock().unwrap().pop_back()
+    }
+
+    /// Take the oldest job from another thread's deque
+    pub fn steal(&self) -> Option<Function> {
+        let deques = self.deques.read().unwrap();
+        let start = self.next_victim.fetch_add(1, Ordering::Relaxed);
+        (0..deques.len())
+            .map(|offset| &deques[(start + offset) % deques.len()])
+            .find_map(|deque| deque.lock().unwrap().pop_front())
+    }
+
+    /// The number of jobs across all the deques
+    pub fn len(&self) -> usize {
+        let deques = self.deques.read().unwrap();
+        deques.iter().map(|deque| deque.lock().unwrap().len()).sum()
+    }
+}